@@ -1,5 +1,8 @@
-use crate::treesitter_utils::text_for_tree_sitter_node;
-use tree_sitter::StreamingIterator;
+use crate::ledger::{Amount, Inventory, amount_from_node, is_number_expr, parse_date};
+use crate::treesitter_utils::{lsp_range_for_node, text_for_tree_sitter_node};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
 use tree_sitter_beancount::tree_sitter;
 
 #[derive(Clone, Debug)]
@@ -14,291 +17,1089 @@ pub struct FlaggedEntry {
 //    }
 //}
 
+/// An `open` directive and the details declared on it.
 #[derive(Clone, Debug)]
-pub struct BeancountData {
-    accounts: Vec<String>,
-    payees: Vec<String>,
-    narration: Vec<String>,
-    pub flagged_entries: Vec<FlaggedEntry>,
-    tags: Vec<String>,
-    links: Vec<String>,
-    commodities: Vec<String>,
+pub struct AccountOpen {
+    pub account: String,
+    pub date: Option<chrono::NaiveDate>,
+    pub currencies: Vec<String>,
+    pub booking: Option<String>,
+    pub metadata: Vec<(String, String)>,
+    /// Range of the account name in the directive.
+    pub range: lsp_types::Range,
+    /// Range of the whole directive, including metadata lines.
+    pub entry_range: lsp_types::Range,
 }
 
-impl BeancountData {
-    pub fn new(tree: &tree_sitter::Tree, content: &ropey::Rope) -> Self {
-        let mut accounts = vec![];
-        let mut payees = vec![];
-        let mut narration = vec![];
-        let mut flagged_entries = vec![];
-
-        let mut cursor = tree.root_node().walk();
-
-        // Update account opens
-        tracing::debug!("beancount_data:: get account nodes");
-        tracing::debug!("beancount_data:: get account strings");
-        let account_strings = tree
-            .root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "open")
-            .filter_map(|node| {
-                let mut node_cursor = node.walk();
-                let account_node = node
-                    .children(&mut node_cursor)
-                    .find(|c| c.kind() == "account")?;
-                let account = text_for_tree_sitter_node(content, &account_node);
-                Some(account)
-            });
-
-        tracing::debug!("beancount_data:: update accounts");
-        accounts.clear();
-
-        for account in account_strings {
-            accounts.push(account);
+/// A `close` directive.
+#[derive(Clone, Debug)]
+pub struct AccountClose {
+    pub account: String,
+    pub date: Option<chrono::NaiveDate>,
+    /// Range of the account name in the directive.
+    pub range: lsp_types::Range,
+}
+
+/// A `commodity` directive.
+#[derive(Clone, Debug)]
+pub struct CommodityDeclaration {
+    pub currency: String,
+    pub date: Option<chrono::NaiveDate>,
+    /// Range of the currency in the directive.
+    pub range: lsp_types::Range,
+}
+
+/// A `balance` assertion.
+#[derive(Clone, Debug)]
+pub struct BalanceData {
+    pub account: String,
+    pub date: Option<chrono::NaiveDate>,
+    pub amount: Amount,
+    /// Explicit tolerance given with `~`.
+    pub tolerance: Option<Decimal>,
+    pub line: u32,
+}
+
+/// A `pad` directive.
+#[derive(Clone, Debug)]
+pub struct PadData {
+    pub account: String,
+    pub source_account: String,
+    pub date: Option<chrono::NaiveDate>,
+    pub line: u32,
+}
+
+/// An `event` directive.
+#[derive(Clone, Debug)]
+pub struct EventData {
+    pub event_type: String,
+    pub description: String,
+    pub date: Option<chrono::NaiveDate>,
+    /// Range of the whole directive.
+    pub range: lsp_types::Range,
+}
+
+/// A single posting of a transaction.
+#[derive(Clone, Debug)]
+pub struct PostingData {
+    pub account: String,
+    /// `None` when the amount is elided and left for beancount to infer.
+    pub units: Option<Amount>,
+    /// Total cost of the posting in the cost currency, from `{...}` or `{{...}}`.
+    pub total_cost: Option<Amount>,
+    /// Total price of the posting in the price currency, from `@` or `@@`.
+    pub total_price: Option<Amount>,
+    /// Metadata lines following the posting.
+    pub metadata: Vec<(String, String)>,
+    pub line: u32,
+    /// The posting as written, from its flag or account to its price.
+    pub text: String,
+    /// Byte range of the units number in `text`.
+    pub number_range: Option<std::ops::Range<usize>>,
+}
+
+impl PostingData {
+    /// The amount this posting contributes to the transaction balance.
+    pub fn weight(&self) -> Option<Amount> {
+        self.total_cost
+            .clone()
+            .or_else(|| self.total_price.clone())
+            .or_else(|| self.units.clone())
+    }
+}
+
+/// A transaction with its postings.
+#[derive(Clone, Debug)]
+pub struct TransactionData {
+    pub date: Option<chrono::NaiveDate>,
+    pub line: u32,
+    /// Payee string including its quotes, and its range.
+    pub payee: Option<(String, lsp_types::Range)>,
+    /// Narration string including its quotes.
+    pub narration: Option<String>,
+    pub tags: Vec<String>,
+    pub links: Vec<String>,
+    /// Metadata lines before the first posting.
+    pub metadata: Vec<(String, String)>,
+    pub postings: Vec<PostingData>,
+}
+
+impl TransactionData {
+    /// Sum of the weights of all postings that have an explicit amount.
+    pub fn residual(&self) -> Inventory {
+        let mut inventory = Inventory::new();
+        for weight in self.postings.iter().filter_map(|p| p.weight()) {
+            inventory.add_amount(&weight);
         }
+        inventory
+    }
 
-        // Update payees and narration with frequency tracking
-        tracing::debug!("beancount_data:: get payee and narration nodes");
-        let transactions = tree
-            .root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "transaction")
-            .collect::<Vec<_>>();
-
-        let mut payee_count: std::collections::HashMap<String, usize> =
-            std::collections::HashMap::new();
-        let mut narration_count: std::collections::HashMap<String, usize> =
-            std::collections::HashMap::new();
-        for transaction in transactions {
-            // When there's a payee field (two strings), use it
-            if let Some(payee_node) = transaction.child_by_field_name("payee") {
-                let payee_text = text_for_tree_sitter_node(content, &payee_node)
-                    .trim()
-                    .to_string();
-                if !payee_text.is_empty() {
-                    *payee_count.entry(payee_text).or_insert(0) += 1;
+    /// Units of every posting, with the elided amount filled in from the residual.
+    ///
+    /// An elided posting absorbs the negated residual, one amount per currency.
+    pub fn posting_units(&self) -> Vec<(&PostingData, Amount)> {
+        let residual = self.residual();
+        let mut elided_done = false;
+        let mut units = Vec::new();
+        for posting in &self.postings {
+            match &posting.units {
+                Some(amount) => units.push((posting, amount.clone())),
+                None if !elided_done => {
+                    elided_done = true;
+                    for amount in residual.amounts() {
+                        units.push((posting, Amount::new(-amount.number, amount.currency)));
+                    }
                 }
+                None => {}
             }
-            // When there's only narration (one string), also add it to payees
-            // since semantically it often represents the payee
-            else if let Some(narration_node) = transaction.child_by_field_name("narration") {
-                let narration_text = text_for_tree_sitter_node(content, &narration_node)
-                    .trim()
-                    .to_string();
-                if !narration_text.is_empty() {
-                    // Add single-string transactions to payees for completion
-                    *payee_count.entry(narration_text.clone()).or_insert(0) += 1;
+        }
+        units
+    }
+}
+
+/// The kinds of names tracked across the workspace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SymbolKind {
+    Account,
+    Currency,
+    /// The payee of a transaction, or its narration when it has a single string.
+    Payee,
+    Narration,
+    Tag,
+    Link,
+}
+
+/// One occurrence of a name in a file.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    /// Text of the name; payees and narrations keep their quotes.
+    pub name: String,
+    pub range: lsp_types::Range,
+    /// Whether this occurrence is the `open` or `commodity` directive declaring the name.
+    pub declaration: bool,
+}
+
+/// Everything extracted from one top-level entry node.
+///
+/// Entries are kept so that an edit only re-extracts the entries it touched;
+/// the others are shared with the previous data, or copied with their
+/// positions shifted when lines were added or removed above them.
+#[derive(Clone, Debug)]
+struct EntrySummary {
+    start_row: usize,
+    flagged_line: Option<u32>,
+    /// Every account, currency, payee, narration, tag and link in the entry.
+    symbols: Vec<Symbol>,
+    /// Currencies of `open` and `commodity` directives, counted a second time
+    /// towards commodity usage frequency.
+    currencies: Vec<String>,
+    open: Option<AccountOpen>,
+    close: Option<AccountClose>,
+    commodity: Option<CommodityDeclaration>,
+    balance: Option<BalanceData>,
+    pad: Option<PadData>,
+    event: Option<EventData>,
+    transaction: Option<TransactionData>,
+    /// File name of an `include` directive, without quotes.
+    include: Option<String>,
+    /// Key and value of an `option` directive, without quotes.
+    option: Option<(String, String)>,
+}
+
+impl EntrySummary {
+    fn new(node: &tree_sitter::Node, content: &ropey::Rope) -> Self {
+        let is_transaction = node.kind() == "transaction";
+
+        let flagged_line = node
+            .child_by_field_name("txn")
+            .and_then(|txn| txn.child(0))
+            .filter(|flag| flag.kind() == "flag")
+            .map(|flag| flag.start_position().row as u32);
+
+        // Directives count their declared currencies on top of every currency use
+        let mut currencies = vec![];
+        if matches!(node.kind(), "open" | "commodity") {
+            let mut cursor = node.walk();
+            for child in node.children(&mut cursor) {
+                if child.kind() == "currency" {
+                    currencies.push(
+                        text_for_tree_sitter_node(content, &child)
+                            .trim()
+                            .to_string(),
+                    );
                 }
             }
+        }
 
-            // Always collect narration for narration completions
-            if let Some(narration_node) = transaction.child_by_field_name("narration") {
-                let narration_text = text_for_tree_sitter_node(content, &narration_node)
+        let mut symbols = vec![];
+        let mut cursor = node.walk();
+        let mut descend = true;
+        loop {
+            let current = cursor.node();
+            let kind = match current.kind() {
+                "account" => Some(SymbolKind::Account),
+                "currency" => Some(SymbolKind::Currency),
+                "tag" => Some(SymbolKind::Tag),
+                "link" => Some(SymbolKind::Link),
+                _ => None,
+            };
+            if descend && let Some(kind) = kind {
+                let name = text_for_tree_sitter_node(content, &current)
                     .trim()
                     .to_string();
-                if !narration_text.is_empty() {
-                    *narration_count.entry(narration_text).or_insert(0) += 1;
+                if !name.is_empty() {
+                    let declaration = current.parent() == Some(*node)
+                        && matches!(
+                            (kind, node.kind()),
+                            (SymbolKind::Account, "open") | (SymbolKind::Currency, "commodity")
+                        );
+                    symbols.push(Symbol {
+                        kind,
+                        name,
+                        range: lsp_range_for_node(content, &current),
+                        declaration,
+                    });
                 }
             }
+            if descend && cursor.goto_first_child() {
+                continue;
+            }
+            if cursor.goto_next_sibling() {
+                descend = true;
+                continue;
+            }
+            if !cursor.goto_parent() || cursor.node() == *node {
+                break;
+            }
+            descend = false;
         }
+        currencies.retain(|currency| !currency.is_empty());
 
-        tracing::debug!("beancount_data:: update payees");
-        payees.clear();
-
-        // Sort by frequency (most used first), then alphabetically
-        let mut payee_vec: Vec<(String, usize)> = payee_count.into_iter().collect();
-        payee_vec.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
-        payees = payee_vec.into_iter().map(|(text, _)| text).collect();
-
-        tracing::debug!("beancount_data:: update narration");
-        narration.clear();
-
-        // Sort by frequency (most used first), then alphabetically
-        let mut narration_vec: Vec<(String, usize)> = narration_count.into_iter().collect();
-        narration_vec.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
-        narration = narration_vec.into_iter().map(|(text, _)| text).collect();
-
-        // Update flagged entries
-        tracing::debug!("beancount_data:: update flagged entries");
-        flagged_entries.clear();
-
-        tree.root_node()
-            .children(&mut cursor)
-            .filter(|c| {
-                let txn_node = c.child_by_field_name("txn");
-                if let Some(txn_node) = txn_node {
-                    let txn_child_node = txn_node.child(0);
-                    txn_child_node.is_some() && txn_child_node.unwrap().kind() == "flag"
-                } else {
-                    false
-                }
+        let payee = is_transaction
+            .then(|| {
+                node.child_by_field_name("payee")
+                    .or_else(|| node.child_by_field_name("narration"))
             })
-            .for_each(|node| {
-                let mut node_cursor = node.walk();
-                let txn_node = node.children(&mut node_cursor).find(|c| c.kind() == "txn");
-                if let Some(txn_node) = txn_node {
-                    let mut flag_cursor = txn_node.walk();
-                    let flag_node = txn_node
-                        .children(&mut flag_cursor)
-                        .find(|c| c.kind() == "flag");
-                    if let Some(flag) = flag_node {
-                        tracing::debug!("addind flag entry: {:?}", flag);
-                        flagged_entries.push(FlaggedEntry {
-                            _file: "".to_string(),
-                            line: flag.start_position().row as u32,
-                        });
-                    }
-                }
-            });
-
-        // Update tags
-        tracing::debug!("beancount_data:: get tags");
-        let query_string = r#"
-        (tag) @tag
-        "#;
-        let query = tree_sitter::Query::new(&tree_sitter_beancount::language(), query_string)
-            .unwrap_or_else(|_| panic!("get_position_by_query invalid query {query_string}"));
-        let mut cursor_qry = tree_sitter::QueryCursor::new();
-        let binding = content.clone().to_string();
-        let mut matches = cursor_qry.matches(&query, tree.root_node(), binding.as_bytes());
-        let mut tags: Vec<_> = {
-            let mut results = Vec::new();
-            while let Some(m) = matches.next() {
-                for capture in m.captures {
-                    results.push(text_for_tree_sitter_node(content, &capture.node));
+            .flatten();
+        let narration = is_transaction
+            .then(|| node.child_by_field_name("narration"))
+            .flatten();
+        for (kind, string) in [
+            (SymbolKind::Payee, payee),
+            (SymbolKind::Narration, narration),
+        ] {
+            if let Some(string) = string {
+                let name = text_for_tree_sitter_node(content, &string)
+                    .trim()
+                    .to_string();
+                if !name.is_empty() {
+                    symbols.push(Symbol {
+                        kind,
+                        name,
+                        range: lsp_range_for_node(content, &string),
+                        declaration: false,
+                    });
                 }
             }
-            results
+        }
+
+        Self {
+            start_row: node.start_position().row,
+            flagged_line,
+            symbols,
+            currencies,
+            open: (node.kind() == "open")
+                .then(|| extract_open(node, content))
+                .flatten(),
+            close: (node.kind() == "close")
+                .then(|| extract_close(node, content))
+                .flatten(),
+            commodity: (node.kind() == "commodity")
+                .then(|| extract_commodity(node, content))
+                .flatten(),
+            balance: (node.kind() == "balance")
+                .then(|| extract_balance(node, content))
+                .flatten(),
+            pad: (node.kind() == "pad")
+                .then(|| extract_pad(node, content))
+                .flatten(),
+            event: (node.kind() == "event")
+                .then(|| extract_event(node, content))
+                .flatten(),
+            transaction: is_transaction.then(|| extract_transaction(node, content)),
+            include: (node.kind() == "include")
+                .then(|| node.named_child(0))
+                .flatten()
+                .map(|string| {
+                    text_for_tree_sitter_node(content, &string)
+                        .trim_matches('"')
+                        .to_string()
+                }),
+            option: (node.kind() == "option")
+                .then(|| {
+                    let string = |field| {
+                        let string = node.child_by_field_name(field)?;
+                        Some(
+                            text_for_tree_sitter_node(content, &string)
+                                .trim_matches('"')
+                                .to_string(),
+                        )
+                    };
+                    Some((string("key")?, string("value")?))
+                })
+                .flatten(),
+        }
+    }
+
+    /// The same entry moved to start at `node`, which has identical text.
+    fn moved_to(self: &Arc<Self>, node: &tree_sitter::Node) -> Arc<Self> {
+        let rows = node.start_position().row as i64 - self.start_row as i64;
+        if rows == 0 {
+            return Arc::clone(self);
+        }
+        let line = |line: u32| (line as i64 + rows) as u32;
+        let range = |range: lsp_types::Range| lsp_types::Range {
+            start: lsp_types::Position::new(line(range.start.line), range.start.character),
+            end: lsp_types::Position::new(line(range.end.line), range.end.character),
         };
-        tags.sort();
-        tags.dedup();
 
-        // Update links
-        tracing::debug!("beancount_data:: get tags");
-        let query_string = r#"
-        (link) @link
-        "#;
-        let query = tree_sitter::Query::new(&tree_sitter_beancount::language(), query_string)
-            .unwrap_or_else(|_| panic!("get_position_by_query invalid query {query_string}"));
-        let mut cursor_qry = tree_sitter::QueryCursor::new();
-        let binding = content.clone().to_string();
-        let mut matches = cursor_qry.matches(&query, tree.root_node(), binding.as_bytes());
-        let mut links: Vec<_> = {
-            let mut results = Vec::new();
-            while let Some(m) = matches.next() {
-                for capture in m.captures {
-                    results.push(text_for_tree_sitter_node(content, &capture.node));
-                }
+        let mut entry = EntrySummary::clone(self);
+        entry.start_row = node.start_position().row;
+        entry.flagged_line = entry.flagged_line.map(line);
+        for symbol in &mut entry.symbols {
+            symbol.range = range(symbol.range);
+        }
+        if let Some(open) = &mut entry.open {
+            open.range = range(open.range);
+            open.entry_range = range(open.entry_range);
+        }
+        if let Some(close) = &mut entry.close {
+            close.range = range(close.range);
+        }
+        if let Some(commodity) = &mut entry.commodity {
+            commodity.range = range(commodity.range);
+        }
+        if let Some(balance) = &mut entry.balance {
+            balance.line = line(balance.line);
+        }
+        if let Some(pad) = &mut entry.pad {
+            pad.line = line(pad.line);
+        }
+        if let Some(event) = &mut entry.event {
+            event.range = range(event.range);
+        }
+        if let Some(txn) = &mut entry.transaction {
+            txn.line = line(txn.line);
+            if let Some((_, payee_range)) = &mut txn.payee {
+                *payee_range = range(*payee_range);
             }
-            results
-        };
-        links.sort();
-        links.dedup();
+            for posting in &mut txn.postings {
+                posting.line = line(posting.line);
+            }
+        }
+        Arc::new(entry)
+    }
+}
 
-        // Update commodities with usage frequency
-        tracing::debug!("beancount_data:: get commodities");
-        let mut commodities_count: std::collections::HashMap<String, usize> =
-            std::collections::HashMap::new();
-
-        // Get commodities from open directives (count once per directive)
-        let mut cursor = tree.root_node().walk();
-        tree.root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "open")
-            .for_each(|node| {
-                let mut node_cursor = node.walk();
-                // Look for currency nodes in open directives
-                for child in node.children(&mut node_cursor) {
-                    if child.kind() == "currency" {
-                        let commodity = text_for_tree_sitter_node(content, &child)
-                            .trim()
-                            .to_string();
-                        if !commodity.is_empty() {
-                            *commodities_count.entry(commodity).or_insert(0) += 1;
-                        }
-                    }
-                }
-            });
-
-        // Get commodities from commodity directives (count once per directive)
-        let mut cursor = tree.root_node().walk();
-        tree.root_node()
-            .children(&mut cursor)
-            .filter(|c| c.kind() == "commodity")
-            .for_each(|node| {
-                let mut node_cursor = node.walk();
-                for child in node.children(&mut node_cursor) {
-                    if child.kind() == "currency" {
-                        let commodity = text_for_tree_sitter_node(content, &child)
-                            .trim()
-                            .to_string();
-                        if !commodity.is_empty() {
-                            *commodities_count.entry(commodity).or_insert(0) += 1;
-                        }
-                    }
-                }
-            });
-
-        // Get commodities from transaction postings (count each usage)
-        let query_string = r#"
-        (currency) @currency
-        "#;
-        let query = tree_sitter::Query::new(&tree_sitter_beancount::language(), query_string)
-            .unwrap_or_else(|_| panic!("get_position_by_query invalid query {query_string}"));
-        let mut cursor_qry = tree_sitter::QueryCursor::new();
-        let binding = content.clone().to_string();
-        let mut matches = cursor_qry.matches(&query, tree.root_node(), binding.as_bytes());
-        while let Some(m) = matches.next() {
-            for capture in m.captures {
-                let commodity = text_for_tree_sitter_node(content, &capture.node)
-                    .trim()
-                    .to_string();
-                if !commodity.is_empty() {
-                    *commodities_count.entry(commodity).or_insert(0) += 1;
+/// Name lists derived from all entries of a file.
+#[derive(Clone, Debug)]
+struct Names {
+    accounts: Vec<String>,
+    payees: Vec<String>,
+    narration: Vec<String>,
+    tags: Vec<String>,
+    links: Vec<String>,
+    commodities: Vec<String>,
+}
+
+impl Names {
+    fn new<'a>(entries: impl Iterator<Item = &'a EntrySummary>) -> Self {
+        let mut accounts = vec![];
+        let mut payee_count: HashMap<&str, usize> = HashMap::new();
+        let mut narration_count: HashMap<&str, usize> = HashMap::new();
+        let mut commodities_count: HashMap<&str, usize> = HashMap::new();
+        let mut tags = vec![];
+        let mut links = vec![];
+
+        for entry in entries {
+            for symbol in &entry.symbols {
+                let name = symbol.name.as_str();
+                match symbol.kind {
+                    SymbolKind::Account if symbol.declaration => accounts.push(symbol.name.clone()),
+                    SymbolKind::Account => {}
+                    SymbolKind::Currency => *commodities_count.entry(name).or_insert(0) += 1,
+                    SymbolKind::Payee => *payee_count.entry(name).or_insert(0) += 1,
+                    SymbolKind::Narration => *narration_count.entry(name).or_insert(0) += 1,
+                    SymbolKind::Tag => tags.push(symbol.name.clone()),
+                    SymbolKind::Link => links.push(symbol.name.clone()),
                 }
             }
+            for currency in &entry.currencies {
+                *commodities_count.entry(currency).or_insert(0) += 1;
+            }
         }
-
-        // Convert to vec and sort by frequency (most used first), then alphabetically
-        let mut commodities: Vec<(String, usize)> = commodities_count.into_iter().collect();
-        commodities.sort_by(|a, b| {
-            // First sort by count (descending), then by name (ascending)
-            b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))
-        });
-        let commodities: Vec<String> = commodities.into_iter().map(|(name, _)| name).collect();
+        tags.sort();
+        tags.dedup();
+        links.sort();
+        links.dedup();
 
         Self {
             accounts,
-            payees,
-            narration,
-            flagged_entries,
+            payees: by_frequency(payee_count),
+            narration: by_frequency(narration_count),
             tags,
             links,
-            commodities,
+            commodities: by_frequency(commodities_count),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BeancountData {
+    /// Summaries of the top-level entries with the byte range of their node,
+    /// in document order.
+    entries: Vec<(std::ops::Range<usize>, Arc<EntrySummary>)>,
+    /// Computed on first use, so that edits only pay for what is queried.
+    names: OnceLock<Names>,
+}
+
+impl BeancountData {
+    pub fn new(tree: &tree_sitter::Tree, content: &ropey::Rope) -> Self {
+        tracing::debug!("beancount_data:: get entries");
+        let entries = entry_nodes(tree.root_node())
+            .iter()
+            .map(|node| {
+                (
+                    node.byte_range(),
+                    Arc::new(EntrySummary::new(node, content)),
+                )
+            })
+            .collect();
+        Self {
+            entries,
+            names: OnceLock::new(),
+        }
+    }
+
+    /// Data for `tree`, re-extracting only the entries touched by `edits` or
+    /// inside the `changed` ranges reported by tree-sitter.
+    ///
+    /// `edits` are the edits applied, in order, to the previous tree before
+    /// reparsing it into `tree`.
+    pub fn update(
+        &self,
+        tree: &tree_sitter::Tree,
+        content: &ropey::Rope,
+        edits: &[tree_sitter::InputEdit],
+        changed: &[tree_sitter::Range],
+    ) -> Self {
+        let previous: HashMap<usize, &(std::ops::Range<usize>, Arc<EntrySummary>)> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.0.start, entry))
+            .collect();
+
+        let mut reused = 0;
+        let entries: Vec<_> = entry_nodes(tree.root_node())
+            .iter()
+            .map(|node| {
+                let untouched = !changed.iter().any(|range| {
+                    range.start_byte < node.end_byte() && node.start_byte() < range.end_byte
+                });
+                let old_entry = untouched
+                    .then(|| previous_byte_range(node.start_byte(), node.end_byte(), edits))
+                    .flatten()
+                    .and_then(|(start, end)| {
+                        previous.get(&start).filter(|(bytes, _)| bytes.end == end)
+                    });
+                let summary = match old_entry {
+                    Some((_, summary)) => {
+                        reused += 1;
+                        summary.moved_to(node)
+                    }
+                    None => Arc::new(EntrySummary::new(node, content)),
+                };
+                (node.byte_range(), summary)
+            })
+            .collect();
+        tracing::debug!(
+            "beancount_data:: reused {} of {} entries",
+            reused,
+            entries.len()
+        );
+        Self {
+            entries,
+            names: OnceLock::new(),
         }
     }
 
+    fn summaries(&self) -> impl Iterator<Item = &EntrySummary> {
+        self.entries.iter().map(|(_, summary)| summary.as_ref())
+    }
+
+    fn names(&self) -> &Names {
+        self.names.get_or_init(|| Names::new(self.summaries()))
+    }
+
+    /// Every symbol occurrence of the file, in document order.
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.summaries().flat_map(|entry| entry.symbols.iter())
+    }
+
+    pub fn flagged_entries(&self) -> impl Iterator<Item = FlaggedEntry> {
+        self.summaries()
+            .filter_map(|entry| entry.flagged_line)
+            .map(|line| FlaggedEntry {
+                _file: "".to_string(),
+                line,
+            })
+    }
+
+    pub fn opens(&self) -> impl Iterator<Item = &AccountOpen> {
+        self.summaries().filter_map(|entry| entry.open.as_ref())
+    }
+
+    pub fn closes(&self) -> impl Iterator<Item = &AccountClose> {
+        self.summaries().filter_map(|entry| entry.close.as_ref())
+    }
+
+    pub fn commodity_declarations(&self) -> impl Iterator<Item = &CommodityDeclaration> {
+        self.summaries()
+            .filter_map(|entry| entry.commodity.as_ref())
+    }
+
+    pub fn balances(&self) -> impl Iterator<Item = &BalanceData> {
+        self.summaries().filter_map(|entry| entry.balance.as_ref())
+    }
+
+    pub fn pads(&self) -> impl Iterator<Item = &PadData> {
+        self.summaries().filter_map(|entry| entry.pad.as_ref())
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &EventData> {
+        self.summaries().filter_map(|entry| entry.event.as_ref())
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &TransactionData> {
+        self.summaries()
+            .filter_map(|entry| entry.transaction.as_ref())
+    }
+
+    /// File names of the `include` directives, without quotes, in document order.
+    pub fn includes(&self) -> impl Iterator<Item = &str> {
+        self.summaries()
+            .filter_map(|entry| entry.include.as_deref())
+    }
+
+    /// Keys and values of the `option` directives, without quotes, in document order.
+    pub fn options(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.summaries().filter_map(|entry| {
+            let (key, value) = entry.option.as_ref()?;
+            Some((key.as_str(), value.as_str()))
+        })
+    }
+
+    /// Every tag occurrence with its range, in document order.
+    pub fn tag_locations(&self) -> impl Iterator<Item = (&str, lsp_types::Range)> {
+        self.symbol_locations(SymbolKind::Tag)
+    }
+
+    /// Every link occurrence with its range, in document order.
+    pub fn link_locations(&self) -> impl Iterator<Item = (&str, lsp_types::Range)> {
+        self.symbol_locations(SymbolKind::Link)
+    }
+
+    fn symbol_locations(&self, kind: SymbolKind) -> impl Iterator<Item = (&str, lsp_types::Range)> {
+        self.symbols()
+            .filter(move |symbol| symbol.kind == kind)
+            .map(|symbol| (symbol.name.as_str(), symbol.range))
+    }
+
     pub fn get_accounts(&self) -> Vec<String> {
-        self.accounts.clone()
+        self.names().accounts.clone()
     }
 
     pub fn get_payees(&self) -> Vec<String> {
-        self.payees.clone()
+        self.names().payees.clone()
     }
 
     pub fn get_narration(&self) -> Vec<String> {
-        self.narration.clone()
+        self.names().narration.clone()
     }
 
     pub fn get_tags(&self) -> Vec<String> {
-        self.tags.clone()
+        self.names().tags.clone()
     }
 
     pub fn get_links(&self) -> Vec<String> {
-        self.links.clone()
+        self.names().links.clone()
     }
 
     pub fn get_commodities(&self) -> Vec<String> {
-        self.commodities.clone()
+        self.names().commodities.clone()
+    }
+}
+
+/// Names sorted by frequency (most used first), then alphabetically.
+fn by_frequency(counts: HashMap<&str, usize>) -> Vec<String> {
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    counts
+        .into_iter()
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Byte range a node of the edited tree had before `edits`, or `None` when
+/// an edit touched it.
+fn previous_byte_range(
+    mut start: usize,
+    mut end: usize,
+    edits: &[tree_sitter::InputEdit],
+) -> Option<(usize, usize)> {
+    for edit in edits.iter().rev() {
+        if end < edit.start_byte {
+            continue;
+        }
+        if start > edit.new_end_byte {
+            start = start - edit.new_end_byte + edit.old_end_byte;
+            end = end - edit.new_end_byte + edit.old_end_byte;
+            continue;
+        }
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Top-level entry nodes of a file, descending into org-mode sections.
+pub(crate) fn entry_nodes(root: tree_sitter::Node) -> Vec<tree_sitter::Node> {
+    let mut entries = Vec::new();
+    let mut cursor = root.walk();
+    for child in root.named_children(&mut cursor) {
+        if child.kind() == "section" {
+            entries.extend(entry_nodes(child));
+        } else {
+            entries.push(child);
+        }
+    }
+    entries
+}
+
+fn child_text(node: &tree_sitter::Node, field: &str, content: &ropey::Rope) -> Option<String> {
+    node.child_by_field_name(field)
+        .map(|child| text_for_tree_sitter_node(content, &child))
+}
+
+fn node_date(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<chrono::NaiveDate> {
+    child_text(node, "date", content).and_then(|date| parse_date(&date))
+}
+
+fn extract_open(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<AccountOpen> {
+    let account_node = node.child_by_field_name("account")?;
+    let account = text_for_tree_sitter_node(content, &account_node);
+    let mut currencies = vec![];
+    let mut booking = None;
+    let mut metadata = vec![];
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match child.kind() {
+            "currency" => currencies.push(text_for_tree_sitter_node(content, &child)),
+            "opt_booking" => {
+                booking = Some(
+                    text_for_tree_sitter_node(content, &child)
+                        .trim_matches('"')
+                        .to_string(),
+                )
+            }
+            "key_value" => metadata.extend(key_value(&child, content)),
+            _ => {}
+        }
+    }
+    Some(AccountOpen {
+        account,
+        date: node_date(node, content),
+        currencies,
+        booking,
+        metadata,
+        range: lsp_range_for_node(content, &account_node),
+        entry_range: lsp_range_for_node(content, node),
+    })
+}
+
+fn extract_close(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<AccountClose> {
+    let account_node = node.child_by_field_name("account")?;
+    Some(AccountClose {
+        account: text_for_tree_sitter_node(content, &account_node),
+        date: node_date(node, content),
+        range: lsp_range_for_node(content, &account_node),
+    })
+}
+
+fn extract_commodity(
+    node: &tree_sitter::Node,
+    content: &ropey::Rope,
+) -> Option<CommodityDeclaration> {
+    let mut cursor = node.walk();
+    let currency_node = node
+        .named_children(&mut cursor)
+        .find(|c| c.kind() == "currency")?;
+    Some(CommodityDeclaration {
+        currency: text_for_tree_sitter_node(content, &currency_node),
+        date: node_date(node, content),
+        range: lsp_range_for_node(content, &currency_node),
+    })
+}
+
+fn extract_balance(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<BalanceData> {
+    let amount_node = node.child_by_field_name("amount")?;
+    let mut cursor = amount_node.walk();
+    let tolerance = amount_node
+        .named_children(&mut cursor)
+        .filter(|c| crate::ledger::is_number_expr(c.kind()))
+        .nth(1)
+        .and_then(|n| crate::ledger::eval_number_expr(&n, content));
+    Some(BalanceData {
+        account: child_text(node, "account", content)?,
+        date: node_date(node, content),
+        amount: amount_from_node(&amount_node, content)?,
+        tolerance,
+        line: node.start_position().row as u32,
+    })
+}
+
+fn extract_pad(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<PadData> {
+    Some(PadData {
+        account: child_text(node, "account", content)?,
+        source_account: child_text(node, "from_account", content)?,
+        date: node_date(node, content),
+        line: node.start_position().row as u32,
+    })
+}
+
+fn extract_event(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<EventData> {
+    Some(EventData {
+        event_type: child_text(node, "type", content)?
+            .trim_matches('"')
+            .to_string(),
+        description: child_text(node, "desc", content)?
+            .trim_matches('"')
+            .to_string(),
+        date: node_date(node, content),
+        range: lsp_range_for_node(content, node),
+    })
+}
+
+/// Key and value of a `key_value` metadata node.
+fn key_value(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<(String, String)> {
+    let mut cursor = node.walk();
+    let mut parts = node.named_children(&mut cursor);
+    let key = parts.next()?;
+    let value = parts
+        .next()
+        .map(|v| text_for_tree_sitter_node(content, &v))
+        .unwrap_or_default();
+    Some((text_for_tree_sitter_node(content, &key), value))
+}
+
+fn extract_transaction(node: &tree_sitter::Node, content: &ropey::Rope) -> TransactionData {
+    let mut tags = vec![];
+    let mut links = vec![];
+    if let Some(tags_links) = node.child_by_field_name("tags_links") {
+        let mut cursor = tags_links.walk();
+        for child in tags_links.named_children(&mut cursor) {
+            match child.kind() {
+                "tag" => tags.push(text_for_tree_sitter_node(content, &child)),
+                "link" => links.push(text_for_tree_sitter_node(content, &child)),
+                _ => {}
+            }
+        }
+    }
+
+    // Metadata belongs to the transaction until the first posting, then to
+    // the posting it follows
+    let mut metadata = vec![];
+    let mut postings: Vec<PostingData> = vec![];
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match child.kind() {
+            "posting" => postings.extend(extract_posting(&child, content)),
+            "key_value" => match postings.last_mut() {
+                Some(posting) => posting.metadata.extend(key_value(&child, content)),
+                None => metadata.extend(key_value(&child, content)),
+            },
+            _ => {}
+        }
+    }
+    TransactionData {
+        date: node_date(node, content),
+        line: node.start_position().row as u32,
+        payee: node.child_by_field_name("payee").map(|payee| {
+            (
+                text_for_tree_sitter_node(content, &payee),
+                lsp_range_for_node(content, &payee),
+            )
+        }),
+        narration: child_text(node, "narration", content),
+        tags,
+        links,
+        metadata,
+        postings,
+    }
+}
+
+fn extract_posting(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<PostingData> {
+    let account = child_text(node, "account", content)?;
+    let units = node
+        .child_by_field_name("amount")
+        .and_then(|amount| amount_from_node(&amount, content));
+
+    let total_cost = match (&units, node.child_by_field_name("cost_spec")) {
+        (Some(units), Some(cost_spec)) => cost_total(&cost_spec, units, content),
+        _ => None,
+    };
+
+    let total_price = match &units {
+        Some(units) => price_total(node, units, content),
+        None => None,
+    };
+
+    // Everything but the trailing comment
+    let mut cursor = node.walk();
+    let parts: Vec<_> = node
+        .named_children(&mut cursor)
+        .filter(|child| child.kind() != "comment")
+        .collect();
+    let start = parts
+        .first()
+        .map_or(node.start_byte(), |part| part.start_byte());
+    let end = parts
+        .iter()
+        .map(|part| part.end_byte())
+        .max()
+        .unwrap_or(node.end_byte());
+    let number_range = node.child_by_field_name("amount").and_then(|amount| {
+        let mut cursor = amount.walk();
+        let number = amount
+            .named_children(&mut cursor)
+            .find(|child| is_number_expr(child.kind()))?;
+        Some(number.start_byte() - start..number.end_byte() - start)
+    });
+
+    Some(PostingData {
+        account,
+        units,
+        total_cost,
+        total_price,
+        metadata: vec![],
+        line: node.start_position().row as u32,
+        text: content.byte_slice(start..end).to_string(),
+        number_range,
+    })
+}
+
+/// Compute the total cost of a posting from its `{per # total CUR}` or `{{total CUR}}` spec.
+fn cost_total(
+    cost_spec: &tree_sitter::Node,
+    units: &Amount,
+    content: &ropey::Rope,
+) -> Option<Amount> {
+    let is_total = cost_spec.child(0).is_some_and(|c| c.kind() == "{{");
+    let mut cursor = cost_spec.walk();
+    let compound = cost_spec
+        .named_children(&mut cursor)
+        .filter(|c| c.kind() == "cost_comp")
+        .find_map(|comp| {
+            comp.named_child(0)
+                .filter(|c| c.kind() == "compound_amount")
+        })?;
+
+    let currency = child_text(&compound, "currency", content)?;
+    let number = |field: &str| {
+        compound
+            .child_by_field_name(field)
+            .and_then(|n| crate::ledger::eval_number_expr(&n, content))
+    };
+    let sign = if units.number.is_sign_negative() {
+        Decimal::NEGATIVE_ONE
+    } else {
+        Decimal::ONE
+    };
+
+    let total = if is_total {
+        sign * number("per")?
+    } else {
+        let per_unit = number("per").unwrap_or(Decimal::ZERO);
+        let total = number("total").unwrap_or(Decimal::ZERO);
+        units.number * per_unit + sign * total
+    };
+    Some(Amount::new(total, currency))
+}
+
+/// Compute the total price of a posting from its `@ per-unit` or `@@ total` annotation.
+fn price_total(
+    posting: &tree_sitter::Node,
+    units: &Amount,
+    content: &ropey::Rope,
+) -> Option<Amount> {
+    let annotation = posting.child_by_field_name("price_annotation")?;
+    let amount_node = annotation
+        .named_child(0)
+        .filter(|c| c.kind() == "incomplete_amount")?;
+    let price = amount_from_node(&amount_node, content)?;
+    let mut cursor = posting.walk();
+    let is_total = posting.children(&mut cursor).any(|c| c.kind() == "atat");
+    let number = if is_total {
+        if units.number.is_sign_negative() {
+            -price.number
+        } else {
+            price.number
+        }
+    } else {
+        units.number * price.number
+    };
+    Some(Amount::new(number, price.currency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDGER: &str = r#"2024-01-01 open Assets:Cash USD
+2024-01-01 commodity USD
+2024-01-02 * "Grocer" "Weekly shopping" #food ^receipt-1
+  Expenses:Food  42.17 USD
+  Assets:Cash
+2024-01-03 ! "Market"
+  Expenses:Food  5 EUR
+  Assets:Cash
+2024-01-04 balance Assets:Cash -42.17 USD
+2024-01-05 event "location" "Berlin"
+"#;
+
+    fn parse(parser: &mut tree_sitter::Parser, text: &str) -> tree_sitter::Tree {
+        parser.parse(text, None).unwrap()
+    }
+
+    /// Replace `old` with `new` in `text` and compare the incremental update with a full rebuild.
+    fn assert_update_matches_rebuild(text: &str, old: &str, new: &str) {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let old_tree = parse(&mut parser, text);
+        let data = BeancountData::new(&old_tree, &ropey::Rope::from_str(text));
+
+        let start_byte = text.find(old).unwrap();
+        let edited = format!(
+            "{}{}{}",
+            &text[..start_byte],
+            new,
+            &text[start_byte + old.len()..]
+        );
+        let point = |text: &str, byte: usize| {
+            let before = &text[..byte];
+            let row = before.matches('\n').count();
+            let column = byte - before.rfind('\n').map_or(0, |i| i + 1);
+            tree_sitter::Point::new(row, column)
+        };
+        let edit = tree_sitter::InputEdit {
+            start_byte,
+            old_end_byte: start_byte + old.len(),
+            new_end_byte: start_byte + new.len(),
+            start_position: point(text, start_byte),
+            old_end_position: point(text, start_byte + old.len()),
+            new_end_position: point(&edited, start_byte + new.len()),
+        };
+
+        let mut edited_tree = old_tree.clone();
+        edited_tree.edit(&edit);
+        let tree = parser.parse(&edited, Some(&edited_tree)).unwrap();
+        let changed: Vec<_> = edited_tree.changed_ranges(&tree).collect();
+        let content = ropey::Rope::from_str(&edited);
+
+        assert_eq!(
+            format!("{:?}", data.update(&tree, &content, &[edit], &changed)),
+            format!("{:?}", BeancountData::new(&tree, &content))
+        );
+    }
+
+    #[test]
+    fn test_new_collects_entries() {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parse(&mut parser, LEDGER);
+        let data = BeancountData::new(&tree, &ropey::Rope::from_str(LEDGER));
+
+        assert_eq!(data.get_accounts(), vec!["Assets:Cash"]);
+        assert_eq!(data.get_payees(), vec!["\"Grocer\"", "\"Market\""]);
+        assert_eq!(
+            data.get_narration(),
+            vec!["\"Market\"", "\"Weekly shopping\""]
+        );
+        assert_eq!(data.get_tags(), vec!["#food"]);
+        assert_eq!(data.get_links(), vec!["^receipt-1"]);
+        assert_eq!(data.get_commodities(), vec!["USD", "EUR"]);
+        let flagged: Vec<_> = data.flagged_entries().collect();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].line, 5);
+        assert_eq!(data.transactions().count(), 2);
+        assert_eq!(data.balances().count(), 1);
+        assert_eq!(data.events().count(), 1);
+    }
+
+    #[test]
+    fn test_transaction_keeps_tags_links_and_metadata() {
+        let text = r#"2024-01-05 * "Grocer" "Weekly" #food ^r1
+  trip: "Lisbon"
+  Assets:Cash  -52.00 USD
+    receipt: "1234"
+  Expenses:Food
+"#;
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parse(&mut parser, text);
+        let data = BeancountData::new(&tree, &ropey::Rope::from_str(text));
+
+        let txn = data.transactions().next().unwrap();
+        assert_eq!(txn.narration.as_deref(), Some("\"Weekly\""));
+        assert_eq!(txn.tags, vec!["#food"]);
+        assert_eq!(txn.links, vec!["^r1"]);
+        let metadata = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(txn.metadata, metadata(&[("trip", "\"Lisbon\"")]));
+        assert_eq!(
+            txn.postings[0].metadata,
+            metadata(&[("receipt", "\"1234\"")])
+        );
+        assert!(txn.postings[1].metadata.is_empty());
+    }
+
+    #[test]
+    fn test_update_matches_rebuild() {
+        // Edit inside an entry without changing its structure
+        assert_update_matches_rebuild(LEDGER, "Grocer", "Baker");
+        // Insert lines, shifting every following entry
+        assert_update_matches_rebuild(
+            LEDGER,
+            "2024-01-03",
+            "2024-01-02 open Assets:Bank EUR\n2024-01-03",
+        );
+        // Remove an entry
+        assert_update_matches_rebuild(LEDGER, "2024-01-04 balance Assets:Cash -42.17 USD\n", "");
+        // Break an entry and the ones around it
+        assert_update_matches_rebuild(
+            LEDGER,
+            "  Assets:Cash\n2024-01-03",
+            "  Assets:Cash\n2024-01-",
+        );
     }
 }
@@ -1,4 +1,13 @@
+use crate::providers::formatting;
 use crate::providers::semantic_tokens;
+use lsp_types::CodeActionKind;
+use lsp_types::CodeActionOptions;
+use lsp_types::CodeActionProviderCapability;
+use lsp_types::DiagnosticOptions;
+use lsp_types::DiagnosticServerCapabilities;
+use lsp_types::DocumentOnTypeFormattingOptions;
+use lsp_types::FoldingRangeProviderCapability;
+use lsp_types::HoverProviderCapability;
 use lsp_types::RenameOptions;
 use lsp_types::SemanticTokensFullOptions;
 use lsp_types::SemanticTokensOptions;
@@ -34,10 +43,35 @@ pub(crate) fn server_capabilities() -> ServerCapabilities {
             ]),
             ..Default::default()
         }),
+        code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+            code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+            ..Default::default()
+        })),
+        definition_provider: Some(OneOf::Left(true)),
+        diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+            identifier: Some("beancount".to_string()),
+            inter_file_dependencies: true,
+            workspace_diagnostics: true,
+            ..Default::default()
+        })),
         document_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: "\n".to_string(),
+            more_trigger_character: Some(
+                formatting::ON_TYPE_TRIGGER_CHARACTERS
+                    .chars()
+                    .map(|c| c.to_string())
+                    .collect(),
+            ),
+        }),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        inlay_hint_provider: Some(OneOf::Left(true)),
         references_provider: Some(OneOf::Left(true)),
         rename_provider: Some(OneOf::Right(RenameOptions {
-            prepare_provider: Some(false),
+            prepare_provider: Some(true),
             work_done_progress_options: WorkDoneProgressOptions {
                 work_done_progress: None,
             },
@@ -45,11 +79,12 @@ pub(crate) fn server_capabilities() -> ServerCapabilities {
         semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
             SemanticTokensOptions {
                 legend: semantic_tokens::legend(),
-                full: Some(SemanticTokensFullOptions::Bool(true)),
-                range: None,
+                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                range: Some(true),
                 ..Default::default()
             },
         )),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
         ..Default::default()
     }
 }
@@ -195,8 +230,8 @@ mod tests {
             OneOf::Right(options) => {
                 assert_eq!(
                     options.prepare_provider,
-                    Some(false),
-                    "prepare_provider should be disabled"
+                    Some(true),
+                    "prepare_provider should be enabled"
                 );
             }
             _ => panic!("Expected RenameOptions"),
@@ -213,18 +248,19 @@ mod tests {
 
         match semantic {
             SemanticTokensServerCapabilities::SemanticTokensOptions(options) => {
-                // Verify full document semantic tokens is enabled
+                // Verify full document semantic tokens support deltas
                 match options.full {
-                    Some(SemanticTokensFullOptions::Bool(enabled)) => {
-                        assert!(enabled, "full semantic tokens should be enabled");
+                    Some(SemanticTokensFullOptions::Delta { delta }) => {
+                        assert_eq!(delta, Some(true), "semantic token deltas should be enabled");
                     }
-                    _ => panic!("Expected boolean for full semantic tokens"),
+                    _ => panic!("Expected delta options for full semantic tokens"),
                 }
 
-                // Verify range is not supported
+                // Verify range is supported
                 assert_eq!(
-                    options.range, None,
-                    "range semantic tokens should be disabled"
+                    options.range,
+                    Some(true),
+                    "range semantic tokens should be enabled"
                 );
 
                 // Verify legend is properly configured
@@ -258,22 +294,54 @@ mod tests {
             caps.document_formatting_provider.is_some(),
             "formatting is implemented"
         );
+        assert!(
+            caps.document_range_formatting_provider.is_some(),
+            "range formatting is implemented"
+        );
+        assert!(
+            caps.document_on_type_formatting_provider.is_some(),
+            "on-type formatting is implemented"
+        );
         assert!(
             caps.references_provider.is_some(),
             "references is implemented"
         );
         assert!(caps.rename_provider.is_some(), "rename is implemented");
+        assert!(caps.hover_provider.is_some(), "hover is implemented");
+        assert!(
+            caps.definition_provider.is_some(),
+            "definition is implemented"
+        );
+        assert!(
+            caps.diagnostic_provider.is_some(),
+            "pull diagnostics are implemented"
+        );
+        assert!(
+            caps.document_symbol_provider.is_some(),
+            "document_symbol is implemented"
+        );
+        assert!(
+            caps.folding_range_provider.is_some(),
+            "folding_range is implemented"
+        );
+        assert!(
+            caps.inlay_hint_provider.is_some(),
+            "inlay_hint is implemented"
+        );
+        assert!(
+            caps.workspace_symbol_provider.is_some(),
+            "workspace_symbol is implemented"
+        );
+        assert!(
+            caps.code_action_provider.is_some(),
+            "code_action is implemented"
+        );
         assert!(
             caps.semantic_tokens_provider.is_some(),
             "semantic_tokens is implemented"
         );
 
         // Verify NOT implemented capabilities are disabled
-        assert_eq!(caps.hover_provider, None, "hover is not implemented");
-        assert_eq!(
-            caps.definition_provider, None,
-            "definition is not implemented"
-        );
         assert_eq!(
             caps.type_definition_provider, None,
             "type_definition is not implemented"
@@ -282,18 +350,6 @@ mod tests {
             caps.implementation_provider, None,
             "implementation is not implemented"
         );
-        assert_eq!(
-            caps.document_symbol_provider, None,
-            "document_symbol is not implemented"
-        );
-        assert_eq!(
-            caps.workspace_symbol_provider, None,
-            "workspace_symbol is not implemented"
-        );
-        assert_eq!(
-            caps.code_action_provider, None,
-            "code_action is not implemented"
-        );
         assert_eq!(
             caps.code_lens_provider, None,
             "code_lens is not implemented"
@@ -302,10 +358,6 @@ mod tests {
             caps.document_link_provider, None,
             "document_link is not implemented"
         );
-        assert_eq!(
-            caps.folding_range_provider, None,
-            "folding_range is not implemented"
-        );
     }
 
     #[test]
@@ -329,7 +381,7 @@ mod tests {
             );
         }
 
-        // Verify token modifiers are unique (currently empty, but check anyway)
+        // Verify token modifiers are unique
         let mut seen = std::collections::HashSet::new();
         for modifier in &legend.token_modifiers {
             assert!(
@@ -348,6 +400,7 @@ mod tests {
         // without implementing them (like the willSaveWaitUntil bug in issue #741).
 
         use crate::handlers;
+        use crate::server::LspServerState;
         use crate::server::LspServerStateSnapshot;
 
         // Get the advertised capabilities
@@ -372,6 +425,24 @@ mod tests {
                 handlers::text_document::formatting;
         }
 
+        // Range formatting capability -> handlers::text_document::range_formatting
+        if caps.document_range_formatting_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::DocumentRangeFormattingParams,
+            ) -> anyhow::Result<Option<Vec<lsp_types::TextEdit>>> =
+                handlers::text_document::range_formatting;
+        }
+
+        // On-type formatting capability -> handlers::text_document::on_type_formatting
+        if caps.document_on_type_formatting_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::DocumentOnTypeFormattingParams,
+            ) -> anyhow::Result<Option<Vec<lsp_types::TextEdit>>> =
+                handlers::text_document::on_type_formatting;
+        }
+
         // References capability -> handlers::text_document::handle_references
         if caps.references_provider.is_some() {
             let _handler: fn(
@@ -381,16 +452,92 @@ mod tests {
                 handlers::text_document::handle_references;
         }
 
-        // Rename capability -> handlers::text_document::handle_rename
+        // Hover capability -> handlers::text_document::hover
+        if caps.hover_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::HoverParams,
+            ) -> anyhow::Result<Option<lsp_types::Hover>> = handlers::text_document::hover;
+        }
+
+        // Code action capability -> handlers::text_document::code_action
+        if caps.code_action_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::CodeActionParams,
+            ) -> anyhow::Result<Option<lsp_types::CodeActionResponse>> =
+                handlers::text_document::code_action;
+        }
+
+        // Definition capability -> handlers::text_document::definition
+        if caps.definition_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::GotoDefinitionParams,
+            )
+                -> anyhow::Result<Option<lsp_types::GotoDefinitionResponse>> =
+                handlers::text_document::definition;
+        }
+
+        // Diagnostic capability -> handlers::text_document::diagnostic and handlers::workspace::diagnostic
+        if caps.diagnostic_provider.is_some() {
+            let _handler: fn(
+                &mut LspServerState,
+                lsp_types::DocumentDiagnosticParams,
+            )
+                -> anyhow::Result<lsp_types::DocumentDiagnosticReportResult> =
+                handlers::text_document::diagnostic;
+            let _handler: fn(
+                &mut LspServerState,
+                lsp_server::RequestId,
+                lsp_types::WorkspaceDiagnosticParams,
+            ) -> anyhow::Result<()> = handlers::workspace::diagnostic;
+        }
+
+        // Document symbol capability -> handlers::text_document::document_symbol
+        if caps.document_symbol_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::DocumentSymbolParams,
+            )
+                -> anyhow::Result<Option<lsp_types::DocumentSymbolResponse>> =
+                handlers::text_document::document_symbol;
+        }
+
+        // Folding range capability -> handlers::text_document::folding_range
+        if caps.folding_range_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::FoldingRangeParams,
+            ) -> anyhow::Result<Option<Vec<lsp_types::FoldingRange>>> =
+                handlers::text_document::folding_range;
+        }
+
+        // Inlay hint capability -> handlers::text_document::inlay_hint
+        if caps.inlay_hint_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::InlayHintParams,
+            ) -> anyhow::Result<Option<Vec<lsp_types::InlayHint>>> =
+                handlers::text_document::inlay_hint;
+        }
+
+        // Rename capability -> handlers::text_document::handle_{prepare_rename,rename}
         if caps.rename_provider.is_some() {
             let _handler: fn(
                 LspServerStateSnapshot,
                 lsp_types::RenameParams,
             ) -> anyhow::Result<Option<lsp_types::WorkspaceEdit>> =
                 handlers::text_document::handle_rename;
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::TextDocumentPositionParams,
+            )
+                -> anyhow::Result<Option<lsp_types::PrepareRenameResponse>> =
+                handlers::text_document::handle_prepare_rename;
         }
 
-        // Semantic tokens capability -> handlers::text_document::semantic_tokens_full
+        // Semantic tokens capability -> handlers::text_document::semantic_tokens_{full,full_delta,range}
         if caps.semantic_tokens_provider.is_some() {
             let _handler: fn(
                 LspServerStateSnapshot,
@@ -398,6 +545,28 @@ mod tests {
             )
                 -> anyhow::Result<Option<lsp_types::SemanticTokensResult>> =
                 handlers::text_document::semantic_tokens_full;
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::SemanticTokensDeltaParams,
+            )
+                -> anyhow::Result<Option<lsp_types::SemanticTokensFullDeltaResult>> =
+                handlers::text_document::semantic_tokens_full_delta;
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::SemanticTokensRangeParams,
+            )
+                -> anyhow::Result<Option<lsp_types::SemanticTokensRangeResult>> =
+                handlers::text_document::semantic_tokens_range;
+        }
+
+        // Workspace symbol capability -> handlers::workspace::symbol
+        if caps.workspace_symbol_provider.is_some() {
+            let _handler: fn(
+                LspServerStateSnapshot,
+                lsp_types::WorkspaceSymbolParams,
+            )
+                -> anyhow::Result<Option<lsp_types::WorkspaceSymbolResponse>> =
+                handlers::workspace::symbol;
         }
 
         // Text document sync notifications (these don't return responses)
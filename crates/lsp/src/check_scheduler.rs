@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+/// Decides when bean-check runs so that only one check runs at a time and
+/// requests arriving close together are merged into a single run.
+#[derive(Debug, Default)]
+pub(crate) struct CheckScheduler {
+    /// Whether a check is currently running on the thread pool.
+    running: bool,
+    /// Files to check next, in the order they were first requested.
+    pending: Vec<lsp_types::Uri>,
+    /// Earliest time the pending check may start.
+    deadline: Option<Instant>,
+}
+
+impl CheckScheduler {
+    /// Ask for a check of `uri` once no new request has arrived for `delay`.
+    pub fn request(&mut self, uri: lsp_types::Uri, delay: Duration, now: Instant) {
+        if !self.pending.contains(&uri) {
+            self.pending.push(uri);
+        }
+        self.deadline = Some(now + delay);
+    }
+
+    /// The files to check now, if a check is pending, due and none is running.
+    ///
+    /// The scheduler considers the returned check running until [`Self::finished`].
+    pub fn due(&mut self, now: Instant) -> Option<Vec<lsp_types::Uri>> {
+        if self.running || self.deadline.is_none_or(|deadline| deadline > now) {
+            return None;
+        }
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return None;
+        }
+        self.running = true;
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    /// Whether a check was requested and has not started yet.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Whether a check was started and has not completed yet.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Record that the running check completed.
+    pub fn finished(&mut self) {
+        self.running = false;
+    }
+
+    /// When the main loop has to wake up to start the pending check.
+    ///
+    /// While a check runs there is nothing to wake up for: its completion
+    /// wakes the loop anyway.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.running { None } else { self.deadline }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn uri(name: &str) -> lsp_types::Uri {
+        lsp_types::Uri::from_str(&format!("file:///ledger/{name}")).unwrap()
+    }
+
+    #[test]
+    fn test_requests_close_together_are_merged() {
+        let mut scheduler = CheckScheduler::default();
+        let start = Instant::now();
+        let delay = Duration::from_millis(300);
+
+        scheduler.request(uri("a.beancount"), delay, start);
+        scheduler.request(
+            uri("b.beancount"),
+            delay,
+            start + Duration::from_millis(100),
+        );
+        assert_eq!(scheduler.due(start + Duration::from_millis(300)), None);
+
+        // Both files are checked, not only the latest one
+        let due = scheduler.due(start + Duration::from_millis(400));
+        assert_eq!(due, Some(vec![uri("a.beancount"), uri("b.beancount")]));
+        assert_eq!(scheduler.due(start + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn test_one_check_at_a_time() {
+        let mut scheduler = CheckScheduler::default();
+        let start = Instant::now();
+
+        scheduler.request(uri("main.beancount"), Duration::ZERO, start);
+        assert!(scheduler.due(start).is_some());
+
+        // A save while the check runs waits for it to finish
+        scheduler.request(uri("main.beancount"), Duration::ZERO, start);
+        assert!(scheduler.is_running());
+        assert_eq!(scheduler.next_deadline(), None);
+        assert_eq!(scheduler.due(start + Duration::from_secs(1)), None);
+
+        scheduler.finished();
+        assert_eq!(scheduler.next_deadline(), Some(start));
+        assert_eq!(
+            scheduler.due(start + Duration::from_secs(1)),
+            Some(vec![uri("main.beancount")])
+        );
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::Result;
 use std::path::{Path, PathBuf};
 use tracing::debug;
 
+pub mod native;
 #[cfg(feature = "python-embedded")]
 mod pyo3_embedded;
 #[cfg(not(feature = "python-embedded"))]
@@ -9,6 +10,7 @@ mod pyo3_embedded_stub;
 pub mod system_call;
 pub mod types;
 
+pub use native::NativeChecker;
 #[cfg(feature = "python-embedded")]
 pub use pyo3_embedded::PyO3EmbeddedChecker;
 #[cfg(not(feature = "python-embedded"))]
@@ -41,17 +43,19 @@ pub trait BeancountChecker: Send + Sync {
 }
 
 /// Configuration for bean-check execution method selection.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum BeancountCheckMethod {
     /// Use system call to execute bean-check binary (traditional approach)
     #[default]
     SystemCall,
     /// Use embedded Python via PyO3 to call beancount library directly (best performance)
     PythonEmbedded,
+    /// Use the built-in Rust validator (no Python or bean-check required)
+    Native,
 }
 
 /// Configuration options for bean-check execution.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BeancountCheckConfig {
     /// Which execution method to use
     pub method: BeancountCheckMethod,
@@ -61,6 +65,12 @@ pub struct BeancountCheckConfig {
     pub python_cmd: PathBuf,
     /// Path to the Python script (for Python method)
     pub python_script_path: PathBuf,
+    /// Wait this long after a save for further saves before checking
+    pub debounce_ms: u64,
+    /// Also check while editing, once the document is idle
+    pub on_change: bool,
+    /// Idle time after the last change before checking (with `on_change`)
+    pub on_change_delay_ms: u64,
 }
 
 impl Default for BeancountCheckConfig {
@@ -70,6 +80,9 @@ impl Default for BeancountCheckConfig {
             bean_check_cmd: PathBuf::from("bean-check"),
             python_cmd: PathBuf::from("python3"),
             python_script_path: PathBuf::from("python/bean_check.py"),
+            debounce_ms: 200,
+            on_change: false,
+            on_change_delay_ms: 1000,
         }
     }
 }
@@ -96,6 +109,10 @@ pub fn create_checker(config: &BeancountCheckConfig) -> Box<dyn BeancountChecker
             debug!("Creating PyO3EmbeddedChecker");
             Box::new(PyO3EmbeddedChecker::new())
         }
+        BeancountCheckMethod::Native => {
+            debug!("Creating NativeChecker");
+            Box::new(NativeChecker::new())
+        }
     };
 
     debug!(
@@ -106,6 +123,21 @@ pub fn create_checker(config: &BeancountCheckConfig) -> Box<dyn BeancountChecker
     checker
 }
 
+/// Create the configured checker, falling back to the native checker when the
+/// configured one is not available on this system.
+pub fn resolve_checker(config: &BeancountCheckConfig) -> Box<dyn BeancountChecker> {
+    let checker = create_checker(config);
+    if checker.is_available() {
+        return checker;
+    }
+
+    debug!(
+        "Checker {} is not available, falling back to NativeChecker",
+        checker.name()
+    );
+    Box::new(NativeChecker::new())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,4 +171,25 @@ mod tests {
         #[cfg(not(feature = "python-embedded"))]
         assert_eq!(checker.name(), "PyO3Embedded (disabled)");
     }
+
+    #[test]
+    fn test_factory_native() {
+        let config = BeancountCheckConfig {
+            method: BeancountCheckMethod::Native,
+            ..Default::default()
+        };
+        let checker = create_checker(&config);
+        assert_eq!(checker.name(), "Native");
+        assert!(checker.is_available());
+    }
+
+    #[test]
+    fn test_resolve_checker_falls_back_to_native() {
+        let config = BeancountCheckConfig {
+            bean_check_cmd: PathBuf::from("/nonexistent/bean-check"),
+            ..Default::default()
+        };
+        let checker = resolve_checker(&config);
+        assert_eq!(checker.name(), "Native");
+    }
 }
@@ -0,0 +1,571 @@
+use super::BeancountChecker;
+use super::types::*;
+use crate::beancount_data::{
+    AccountClose, AccountOpen, BalanceData, BeancountData, PadData, TransactionData, entry_nodes,
+};
+use crate::ledger::{Amount, Inventory, infer_tolerance};
+use crate::treesitter_utils::text_for_tree_sitter_node;
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+use tree_sitter_beancount::tree_sitter;
+
+/// Bean-check implementation written in Rust on top of the tree-sitter parser.
+///
+/// It does not need Python or beancount installed, and covers the most common
+/// semantic errors: unknown or closed accounts, unbalanced transactions,
+/// currency constraints, duplicate `open`s and failed `balance` assertions.
+#[derive(Debug, Clone, Default)]
+pub struct NativeChecker;
+
+impl NativeChecker {
+    /// Create a new native checker.
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Parse the journal and every file it includes, in include order.
+    fn load(&self, journal_file: &Path) -> Result<Vec<(PathBuf, BeancountData)>> {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&tree_sitter_beancount::language())?;
+
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![journal_file.to_path_buf()];
+        while let Some(path) = pending.pop() {
+            // Compare canonical paths, so `../` include cycles are detected
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if !visited.insert(canonical) {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let tree = parser
+                .parse(&text, None)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            let content = ropey::Rope::from_str(&text);
+
+            // Push in reverse so includes are visited in the order they appear
+            let mut includes = resolve_includes(&path, &tree, &content);
+            includes.reverse();
+            pending.extend(includes);
+
+            files.push((path, BeancountData::new(&tree, &content)));
+        }
+        Ok(files)
+    }
+}
+
+impl BeancountChecker for NativeChecker {
+    fn check(&self, journal_file: &Path) -> Result<BeancountCheckResult> {
+        debug!(
+            "NativeChecker: validating journal file: {}",
+            journal_file.display()
+        );
+        let files = self.load(journal_file)?;
+        debug!("NativeChecker: loaded {} files", files.len());
+
+        let errors = validate(&files);
+        debug!("NativeChecker: found {} errors", errors.len());
+        Ok(BeancountCheckResult::with_errors(errors))
+    }
+
+    fn name(&self) -> &'static str {
+        "Native"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+}
+
+/// Resolve the `include` directives of a file to existing paths, expanding globs.
+fn resolve_includes(
+    file_path: &Path,
+    tree: &tree_sitter::Tree,
+    content: &ropey::Rope,
+) -> Vec<PathBuf> {
+    let Some(parent) = file_path.parent() else {
+        return vec![];
+    };
+    entry_nodes(tree.root_node())
+        .into_iter()
+        .filter(|node| node.kind() == "include")
+        .filter_map(|node| node.named_child(0))
+        .flat_map(|string_node| {
+            let filename = text_for_tree_sitter_node(content, &string_node);
+            let filename = filename.trim_start_matches('"').trim_end_matches('"');
+            let pattern = parent.join(filename);
+            match glob::glob(&pattern.to_string_lossy()) {
+                Ok(paths) => paths.filter_map(|p| p.ok()).collect(),
+                Err(_) => vec![],
+            }
+        })
+        .collect()
+}
+
+/// A dated entry taking part in validation, with the file it comes from.
+enum Entry<'a> {
+    Open(&'a AccountOpen),
+    Balance(&'a BalanceData),
+    Pad(&'a PadData),
+    Transaction(&'a TransactionData),
+    Close(&'a AccountClose),
+}
+
+impl Entry<'_> {
+    fn date(&self) -> Option<chrono::NaiveDate> {
+        match self {
+            Entry::Open(open) => open.date,
+            Entry::Balance(balance) => balance.date,
+            Entry::Pad(pad) => pad.date,
+            Entry::Transaction(txn) => txn.date,
+            Entry::Close(close) => close.date,
+        }
+    }
+
+    /// Order of entries on the same day, matching beancount: balances are
+    /// checked at the start of the day and closes happen at the end of it.
+    fn type_order(&self) -> u8 {
+        match self {
+            Entry::Open(_) => 0,
+            Entry::Balance(_) => 1,
+            Entry::Pad(_) => 2,
+            Entry::Transaction(_) => 3,
+            Entry::Close(_) => 4,
+        }
+    }
+
+    /// 0-based line of the entry.
+    fn line(&self) -> u32 {
+        match self {
+            Entry::Open(open) => open.range.start.line,
+            Entry::Balance(balance) => balance.line,
+            Entry::Pad(pad) => pad.line,
+            Entry::Transaction(txn) => txn.line,
+            Entry::Close(close) => close.range.start.line,
+        }
+    }
+}
+
+/// Running state while walking the entries in date order.
+#[derive(Default)]
+struct Ledger<'a> {
+    opens: HashMap<&'a str, &'a AccountOpen>,
+    closes: HashMap<&'a str, chrono::NaiveDate>,
+    balances: HashMap<&'a str, Inventory>,
+    pads: HashMap<&'a str, &'a PadData>,
+    errors: Vec<BeancountError>,
+    /// Balance of each asserted account right after its `balance` directive.
+    balances_after: Vec<(PathBuf, u32, Amount)>,
+}
+
+impl<'a> Ledger<'a> {
+    fn error(&mut self, file: &Path, line: u32, message: String) {
+        self.errors
+            .push(BeancountError::new(file.to_path_buf(), line + 1, message));
+    }
+
+    /// Report references to accounts that are not open on `date`.
+    fn check_active(&mut self, file: &Path, line: u32, account: &str, date: chrono::NaiveDate) {
+        let opened = self
+            .opens
+            .get(account)
+            .is_some_and(|open| open.date.is_none_or(|open_date| open_date <= date));
+        if !opened {
+            self.error(
+                file,
+                line,
+                format!("Invalid reference to unknown account '{account}'"),
+            );
+        } else if self.closes.get(account).is_some_and(|close| *close < date) {
+            self.error(
+                file,
+                line,
+                format!("Invalid reference to inactive account '{account}'"),
+            );
+        }
+    }
+
+    /// Balance of an account including all of its sub-accounts.
+    fn balance_of(&self, account: &str, currency: &str) -> Decimal {
+        let prefix = format!("{account}:");
+        self.balances
+            .iter()
+            .filter(|(name, _)| **name == account || name.starts_with(&prefix))
+            .map(|(_, inventory)| inventory.get(currency))
+            .sum()
+    }
+
+    fn post(&mut self, account: &'a str, amount: &Amount) {
+        self.balances.entry(account).or_default().add_amount(amount);
+    }
+
+    fn open(&mut self, file: &Path, open: &'a AccountOpen) {
+        if self.opens.contains_key(open.account.as_str()) {
+            self.error(
+                file,
+                open.range.start.line,
+                format!("Duplicate open directive for {}", open.account),
+            );
+        } else {
+            self.opens.insert(&open.account, open);
+        }
+    }
+
+    fn close(&mut self, file: &Path, close: &'a AccountClose, date: chrono::NaiveDate) {
+        if !self.opens.contains_key(close.account.as_str()) {
+            self.error(
+                file,
+                close.range.start.line,
+                format!("Unopened account {} is being closed", close.account),
+            );
+        }
+        self.closes.insert(&close.account, date);
+    }
+
+    fn transaction(&mut self, file: &Path, txn: &'a TransactionData, date: chrono::NaiveDate) {
+        for posting in &txn.postings {
+            self.check_active(file, posting.line, &posting.account, date);
+            if let (Some(units), Some(open)) =
+                (&posting.units, self.opens.get(posting.account.as_str()))
+                && !open.currencies.is_empty()
+                && !open.currencies.contains(&units.currency)
+            {
+                let message = format!(
+                    "Invalid currency {} for account '{}'",
+                    units.currency, posting.account
+                );
+                self.error(file, posting.line, message);
+            }
+        }
+
+        if txn.postings.iter().all(|p| p.units.is_some()) {
+            let unbalanced: Vec<String> = txn
+                .residual()
+                .amounts()
+                .into_iter()
+                .filter(|amount| amount.number.abs() > transaction_tolerance(txn, &amount.currency))
+                .map(|amount| amount.to_string())
+                .collect();
+            if !unbalanced.is_empty() {
+                self.error(
+                    file,
+                    txn.line,
+                    format!("Transaction does not balance: ({})", unbalanced.join(", ")),
+                );
+            }
+        }
+
+        for (posting, units) in txn.posting_units() {
+            self.post(&posting.account, &units);
+        }
+    }
+
+    fn pad(&mut self, file: &Path, pad: &'a PadData, date: chrono::NaiveDate) {
+        self.check_active(file, pad.line, &pad.account, date);
+        self.check_active(file, pad.line, &pad.source_account, date);
+        self.pads.insert(&pad.account, pad);
+    }
+
+    fn balance(&mut self, file: &Path, balance: &'a BalanceData, date: chrono::NaiveDate) {
+        self.check_active(file, balance.line, &balance.account, date);
+
+        let expected = &balance.amount;
+        let actual = self.balance_of(&balance.account, &expected.currency);
+        let difference = expected.number - actual;
+
+        // A pending pad fills the difference from its source account
+        if let Some(pad) = self.pads.remove(balance.account.as_str()) {
+            let fill = Amount::new(difference, expected.currency.clone());
+            self.post(&pad.account, &fill);
+            self.post(
+                &pad.source_account,
+                &Amount::new(-difference, fill.currency),
+            );
+            self.balances_after
+                .push((file.to_path_buf(), balance.line, expected.clone()));
+            return;
+        }
+        self.balances_after.push((
+            file.to_path_buf(),
+            balance.line,
+            Amount::new(actual, expected.currency.clone()),
+        ));
+
+        // Balance assertions are checked against twice the inferred tolerance
+        let tolerance = balance
+            .tolerance
+            .unwrap_or_else(|| infer_tolerance(expected.number) * Decimal::TWO);
+        if difference.abs() > tolerance {
+            let direction = if difference.is_sign_positive() {
+                "too little"
+            } else {
+                "too much"
+            };
+            let message = format!(
+                "Balance failed for '{}': expected {} != accumulated {} {} ({} {})",
+                balance.account,
+                expected,
+                actual,
+                expected.currency,
+                difference.abs(),
+                direction
+            );
+            self.error(file, balance.line, message);
+        }
+    }
+}
+
+/// Tolerance for a residual in `currency`, inferred from the precision of the
+/// posting amounts written in that currency, or zero when none is.
+fn transaction_tolerance(txn: &TransactionData, currency: &str) -> Decimal {
+    txn.postings
+        .iter()
+        .filter_map(|posting| posting.units.as_ref())
+        .filter(|units| units.currency == currency)
+        .map(|units| infer_tolerance(units.number))
+        .max()
+        .unwrap_or(Decimal::ZERO)
+}
+
+/// Walk the entries of all files in date order.
+fn run<D: Borrow<BeancountData>>(files: &[(PathBuf, D)]) -> Ledger<'_> {
+    let mut entries: Vec<(chrono::NaiveDate, u8, usize, u32, Entry)> = Vec::new();
+    for (file_index, (_, data)) in files.iter().enumerate() {
+        let data = data.borrow();
+        let file_entries = data
+            .opens()
+            .map(Entry::Open)
+            .chain(data.balances().map(Entry::Balance))
+            .chain(data.pads().map(Entry::Pad))
+            .chain(data.transactions().map(Entry::Transaction))
+            .chain(data.closes().map(Entry::Close));
+        for entry in file_entries {
+            if let Some(date) = entry.date() {
+                entries.push((date, entry.type_order(), file_index, entry.line(), entry));
+            }
+        }
+    }
+    entries.sort_by_key(|(date, order, file, line, _)| (*date, *order, *file, *line));
+
+    let mut ledger = Ledger::default();
+    for (date, _, file_index, _, entry) in entries {
+        let file = files[file_index].0.as_path();
+        match entry {
+            Entry::Open(open) => ledger.open(file, open),
+            Entry::Balance(balance) => ledger.balance(file, balance, date),
+            Entry::Pad(pad) => ledger.pad(file, pad, date),
+            Entry::Transaction(txn) => ledger.transaction(file, txn, date),
+            Entry::Close(close) => ledger.close(file, close, date),
+        }
+    }
+    ledger
+}
+
+/// Validate the parsed files of a journal and return the errors found.
+pub(crate) fn validate<D: Borrow<BeancountData>>(files: &[(PathBuf, D)]) -> Vec<BeancountError> {
+    let mut errors = run(files).errors;
+    errors.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    errors
+}
+
+/// Balance of each asserted account after its `balance` directive, as
+/// `(file, 0-based line, amount)`. Pads are applied like beancount does.
+pub(crate) fn balances_after_assertions<D: Borrow<BeancountData>>(
+    files: &[(PathBuf, D)],
+) -> Vec<(PathBuf, u32, Amount)> {
+    run(files).balances_after
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn check_text(text: &str) -> Vec<BeancountError> {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("main.beancount");
+        fs::write(&file_path, text).unwrap();
+        NativeChecker::new().check(&file_path).unwrap().errors
+    }
+
+    fn messages(errors: &[BeancountError]) -> Vec<(u32, &str)> {
+        errors
+            .iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_native_checker_metadata() {
+        let checker = NativeChecker::new();
+        assert_eq!(checker.name(), "Native");
+        assert!(checker.is_available());
+    }
+
+    #[test]
+    fn test_valid_journal_has_no_errors() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Cash USD
+2024-01-01 open Expenses:Food
+2024-01-02 * "Lunch"
+  Expenses:Food  12.50 USD
+  Assets:Cash
+2024-01-03 balance Assets:Cash -12.50 USD
+"#,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_unknown_and_closed_accounts() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Cash
+2024-01-02 close Assets:Cash
+2024-01-03 * "Lunch"
+  Expenses:Food  10 USD
+  Assets:Cash   -10 USD
+"#,
+        );
+        assert_eq!(
+            messages(&errors),
+            vec![
+                (4, "Invalid reference to unknown account 'Expenses:Food'"),
+                (5, "Invalid reference to inactive account 'Assets:Cash'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_transaction_uses_inferred_tolerance() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Food
+2024-01-02 * "Within tolerance"
+  Expenses:Food  10.004 USD
+  Assets:Cash   -10.00 USD
+2024-01-03 * "Off by a cent"
+  Expenses:Food  10.01 USD
+  Assets:Cash   -10.00 USD
+"#,
+        );
+        assert_eq!(
+            messages(&errors),
+            vec![(6, "Transaction does not balance: (0.01 USD)")]
+        );
+    }
+
+    #[test]
+    fn test_transaction_with_cost_and_price_balances() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Cash
+2024-01-01 open Assets:Stock
+2024-01-01 open Assets:Euro
+2024-01-02 * "Buy"
+  Assets:Stock  10 HOOL {50.00 USD}
+  Assets:Cash  -500.00 USD
+2024-01-03 * "Exchange"
+  Assets:Euro  100.00 EUR @ 1.10 USD
+  Assets:Cash  -110.00 USD
+"#,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_currency_constraint_and_duplicate_open() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Cash USD
+2024-01-01 open Equity:Opening
+2024-01-05 open Assets:Cash USD
+2024-01-02 * "Deposit"
+  Assets:Cash     10 EUR
+  Equity:Opening
+"#,
+        );
+        assert_eq!(
+            messages(&errors),
+            vec![
+                (3, "Duplicate open directive for Assets:Cash"),
+                (5, "Invalid currency EUR for account 'Assets:Cash'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_balance_assertion_includes_sub_accounts() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Bank
+2024-01-01 open Assets:Bank:Savings
+2024-01-01 open Equity:Opening
+2024-01-02 * "Deposit"
+  Assets:Bank:Savings  100.00 USD
+  Equity:Opening
+2024-01-03 balance Assets:Bank 100.00 USD
+2024-01-03 balance Assets:Bank 90.00 USD
+"#,
+        );
+        assert_eq!(
+            messages(&errors),
+            vec![(
+                8,
+                "Balance failed for 'Assets:Bank': expected 90.00 USD != accumulated 100.00 USD (10.00 too much)"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_pad_fills_balance_assertion() {
+        let errors = check_text(
+            r#"2024-01-01 open Assets:Cash
+2024-01-01 open Equity:Opening
+2024-01-01 pad Assets:Cash Equity:Opening
+2024-01-02 balance Assets:Cash 50.00 USD
+2024-01-03 balance Assets:Cash 50.00 USD
+"#,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_included_files_are_checked() {
+        let temp_dir = TempDir::new().unwrap();
+        let main = temp_dir.path().join("main.beancount");
+        let accounts = temp_dir.path().join("accounts.beancount");
+        fs::write(
+            &main,
+            "include \"accounts.beancount\"\n2024-01-02 * \"Lunch\"\n  Expenses:Food  10 USD\n  Assets:Cash\n",
+        )
+        .unwrap();
+        fs::write(&accounts, "2024-01-01 open Assets:Cash\n").unwrap();
+
+        let errors = NativeChecker::new().check(&main).unwrap().errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, main);
+        assert_eq!(
+            errors[0].message,
+            "Invalid reference to unknown account 'Expenses:Food'"
+        );
+    }
+
+    #[test]
+    fn test_parent_directory_include_cycle_terminates() {
+        let temp_dir = TempDir::new().unwrap();
+        let sub = temp_dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        let main = sub.join("main.beancount");
+        fs::write(
+            &main,
+            "include \"../sub/main.beancount\"\n2024-01-01 open Assets:Cash\n",
+        )
+        .unwrap();
+
+        let errors = NativeChecker::new().check(&main).unwrap().errors;
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+}
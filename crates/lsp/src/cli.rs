@@ -0,0 +1,350 @@
+use crate::beancount_data::BeancountData;
+use crate::checkers::resolve_checker;
+use crate::config::Config;
+use crate::forest::parse_initial_forest;
+use crate::providers::{diagnostics, formatting};
+use crate::server::{LspServerStateSnapshot, ProgressMsg, Task};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// What `format` does with files whose formatting would change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatMode {
+    /// Rewrite the files in place.
+    Write,
+    /// Only report the files that would change.
+    Check,
+    /// Print the changes as a unified diff.
+    Diff,
+}
+
+/// Run the editor's formatter on `files`.
+///
+/// Returns false when a file is not formatted in `Check` and `Diff` mode.
+pub fn format(
+    files: &[PathBuf],
+    mode: FormatMode,
+    config_file: Option<&Path>,
+    out: &mut dyn Write,
+) -> Result<bool> {
+    let mut formatted = true;
+    for file in files {
+        let path = std::path::absolute(file)?;
+        let config = load_config(&path, config_file)?;
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let new_text = format_text(&path, &text, config)?;
+        if new_text == text {
+            continue;
+        }
+
+        match mode {
+            FormatMode::Write => {
+                std::fs::write(&path, &new_text)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                writeln!(out, "formatted {}", file.display())?;
+            }
+            FormatMode::Check => {
+                formatted = false;
+                writeln!(out, "would reformat {}", file.display())?;
+            }
+            FormatMode::Diff => {
+                formatted = false;
+                let name = file.display().to_string();
+                let diff = similar::TextDiff::from_lines(&text, &new_text);
+                write!(out, "{}", diff.unified_diff().header(&name, &name))?;
+            }
+        }
+    }
+    Ok(formatted)
+}
+
+/// Run the configured bean-check on the journal at `root`.
+///
+/// Returns false when the checker reports errors; flagged entries are only printed.
+pub fn check(root: &Path, config_file: Option<&Path>, out: &mut dyn Write) -> Result<bool> {
+    let root = std::path::absolute(root)?;
+    let config = load_config(&root, config_file)?;
+    let checker = resolve_checker(&config.bean_check);
+    tracing::debug!("Checking {} with {}", root.display(), checker.name());
+
+    let result = checker.check(&root)?;
+    for error in &result.errors {
+        writeln!(
+            out,
+            "{}:{}: error: {}",
+            error.file.display(),
+            error.line,
+            error.message
+        )?;
+    }
+    for entry in &result.flagged_entries {
+        writeln!(
+            out,
+            "{}:{}: warning: {}",
+            entry.file.display(),
+            entry.line,
+            entry.message
+        )?;
+    }
+    Ok(result.errors.is_empty())
+}
+
+/// Report every diagnostic the editor shows for the journal at `root` and
+/// the files it includes.
+///
+/// Returns false when there is any diagnostic.
+pub fn lint(root: &Path, config_file: Option<&Path>, out: &mut dyn Write) -> Result<bool> {
+    let root = std::path::absolute(root)?;
+    let config = load_config(&root, config_file)?;
+    let beancount_data = load_forest(&root, config.clone())?;
+    let checker = resolve_checker(&config.bean_check);
+
+    let mut files: Vec<_> = diagnostics::diagnostics(beancount_data, checker.as_ref(), &root)
+        .into_iter()
+        .filter(|(_, diagnostics)| !diagnostics.is_empty())
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let clean = files.is_empty();
+    for (file, mut diagnostics) in files {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+        for diagnostic in diagnostics {
+            let severity = match diagnostic.severity {
+                Some(lsp_types::DiagnosticSeverity::ERROR) => "error",
+                Some(lsp_types::DiagnosticSeverity::WARNING) => "warning",
+                Some(lsp_types::DiagnosticSeverity::INFORMATION) => "info",
+                _ => "hint",
+            };
+            writeln!(
+                out,
+                "{}:{}:{}: {}: {} ({})",
+                file.display(),
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                severity,
+                diagnostic.message,
+                diagnostic.source.as_deref().unwrap_or("beancount-lsp")
+            )?;
+        }
+    }
+    Ok(clean)
+}
+
+/// Settings for `root`, read from a JSON file with the same shape as the
+/// server's initialization options.
+fn load_config(root: &Path, config_file: Option<&Path>) -> Result<Config> {
+    let mut config = Config::new(root.to_path_buf());
+    if let Some(config_file) = config_file {
+        let text = std::fs::read_to_string(config_file)
+            .with_context(|| format!("Failed to read {}", config_file.display()))?;
+        let json = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid JSON in {}", config_file.display()))?;
+        config.update(json)?;
+    }
+    Ok(config)
+}
+
+/// Format `text` exactly like `textDocument/formatting` would.
+fn format_text(path: &Path, text: &str, config: Config) -> Result<String> {
+    let mut snapshot = LspServerStateSnapshot::new(config);
+    snapshot.open_document(path.to_path_buf(), text)?;
+    let content = snapshot.open_docs[path].text();
+
+    let params = lsp_types::DocumentFormattingParams {
+        text_document: lsp_types::TextDocumentIdentifier {
+            uri: crate::providers::uri::file_path_to_uri(path)
+                .with_context(|| format!("No file URI for {}", path.display()))?,
+        },
+        options: Default::default(),
+        work_done_progress_params: Default::default(),
+    };
+    let edits = formatting::formatting(snapshot, params)?.unwrap_or_default();
+    Ok(apply_edits(content, edits))
+}
+
+/// Apply non-overlapping edits, last one first so earlier positions stay valid.
+fn apply_edits(mut content: ropey::Rope, mut edits: Vec<lsp_types::TextEdit>) -> String {
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+    for edit in edits {
+        let start = content.line_to_char(edit.range.start.line as usize)
+            + edit.range.start.character as usize;
+        let end =
+            content.line_to_char(edit.range.end.line as usize) + edit.range.end.character as usize;
+        content.remove(start..end);
+        content.insert(start, &edit.new_text);
+    }
+    content.to_string()
+}
+
+/// Parse the journal and its includes the same way the server does on startup.
+fn load_forest(root: &Path, config: Config) -> Result<HashMap<PathBuf, Arc<BeancountData>>> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    parse_initial_forest(
+        LspServerStateSnapshot::new(config),
+        root.to_path_buf(),
+        sender,
+    )?;
+
+    Ok(receiver
+        .try_iter()
+        .filter_map(|task| match task {
+            Task::Progress(ProgressMsg::ForestInit { data, .. }) => *data,
+            _ => None,
+        })
+        .map(|(path, _, data)| (path, data))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const UNFORMATTED: &str = r#"2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Food
+
+2024-01-02 * "Lunch"
+  Expenses:Food 12.50 USD
+  Assets:Cash  -12.50 USD
+"#;
+
+    const FORMATTED: &str = r#"2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Food
+
+2024-01-02 * "Lunch"
+  Expenses:Food   12.50 USD
+  Assets:Cash    -12.50 USD
+"#;
+
+    fn write(dir: &TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn native_config(dir: &TempDir) -> PathBuf {
+        write(
+            dir,
+            "config.json",
+            r#"{"bean_check": {"method": "native"}}"#,
+        )
+    }
+
+    #[test]
+    fn test_format_check_reports_without_writing() {
+        let dir = TempDir::new().unwrap();
+        let file = write(&dir, "main.beancount", UNFORMATTED);
+
+        let mut out = Vec::new();
+        let ok = format(
+            std::slice::from_ref(&file),
+            FormatMode::Check,
+            None,
+            &mut out,
+        )
+        .unwrap();
+        assert!(!ok);
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .starts_with("would reformat")
+        );
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), UNFORMATTED);
+    }
+
+    #[test]
+    fn test_format_writes_and_is_idempotent() {
+        let dir = TempDir::new().unwrap();
+        let file = write(&dir, "main.beancount", UNFORMATTED);
+
+        let mut out = Vec::new();
+        assert!(
+            format(
+                std::slice::from_ref(&file),
+                FormatMode::Write,
+                None,
+                &mut out
+            )
+            .unwrap()
+        );
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), FORMATTED);
+
+        let mut out = Vec::new();
+        assert!(
+            format(
+                std::slice::from_ref(&file),
+                FormatMode::Check,
+                None,
+                &mut out
+            )
+            .unwrap()
+        );
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_format_diff_uses_config_file() {
+        let dir = TempDir::new().unwrap();
+        let file = write(&dir, "main.beancount", FORMATTED);
+        let config = write(
+            &dir,
+            "config.json",
+            r#"{"formatting": {"decimal_places": {"USD": 3}}}"#,
+        );
+
+        let mut out = Vec::new();
+        let ok = format(&[file], FormatMode::Diff, Some(&config), &mut out).unwrap();
+        assert!(!ok);
+        let diff = String::from_utf8(out).unwrap();
+        assert!(diff.contains("-  Expenses:Food   12.50 USD"), "{diff}");
+        assert!(diff.contains("+  Expenses:Food   12.500 USD"), "{diff}");
+    }
+
+    #[test]
+    fn test_check_reports_checker_errors() {
+        let dir = TempDir::new().unwrap();
+        let config = native_config(&dir);
+        let root = write(
+            &dir,
+            "main.beancount",
+            "2024-01-02 * \"Lunch\"\n  Expenses:Food  12.50 USD\n  Assets:Cash\n",
+        );
+
+        let mut out = Vec::new();
+        assert!(!check(&root, Some(&config), &mut out).unwrap());
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("main.beancount:2: error:"), "{output}");
+
+        let clean = write(&dir, "clean.beancount", FORMATTED);
+        let mut out = Vec::new();
+        assert!(check(&clean, Some(&config), &mut out).unwrap());
+    }
+
+    #[test]
+    fn test_lint_includes_files_and_flagged_entries() {
+        let dir = TempDir::new().unwrap();
+        let config = native_config(&dir);
+        write(
+            &dir,
+            "2024.beancount",
+            "2024-01-03 ! \"Coffee\"\n  Expenses:Food  3.00 USD\n  Assets:Cash\n",
+        );
+        let root = write(
+            &dir,
+            "main.beancount",
+            &format!("include \"2024.beancount\"\n{FORMATTED}"),
+        );
+
+        let mut out = Vec::new();
+        assert!(!lint(&root, Some(&config), &mut out).unwrap());
+        let output = String::from_utf8(out).unwrap();
+        assert!(
+            output.contains("2024.beancount:1:1: warning: Transaction flagged for review"),
+            "{output}"
+        );
+    }
+}
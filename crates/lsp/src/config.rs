@@ -1,6 +1,7 @@
 use crate::checkers::{BeancountCheckConfig, BeancountCheckMethod};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
@@ -9,6 +10,8 @@ pub struct Config {
     pub journal_root: Option<PathBuf>,
     pub formatting: FormattingConfig,
     pub bean_check: BeancountCheckConfig,
+    pub inlay_hints: InlayHintsConfig,
+    pub rename: RenameConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +40,32 @@ pub struct FormattingConfig {
     /// If specified, all indentation will be normalized to this number of spaces.
     /// If None, indentation is left unchanged.
     pub indent_width: Option<usize>,
+
+    /// Number of blank lines to leave between consecutive entries.
+    /// If None, blank lines are left unchanged.
+    pub blank_lines_between_entries: Option<usize>,
+
+    /// Sort dated entries by date within each section (default: false).
+    /// Comments directly above an entry move with it; standalone comments,
+    /// headlines and undated directives stay where they are.
+    pub sort_by_date: bool,
+
+    /// Rewrite lowercase letter flags (`p` instead of `P`) on transactions and
+    /// postings in the uppercase form beancount accepts (default: false).
+    pub uppercase_flags: bool,
+
+    /// Indent metadata this many spaces deeper than the line it belongs to:
+    /// the entry's date line, or the posting above it.
+    /// If None, metadata indentation is left unchanged.
+    pub metadata_indent_width: Option<usize>,
+
+    /// Add (true) or remove (false) thousands separators in amounts.
+    /// If None, separators are left unchanged.
+    pub thousands_separator: Option<bool>,
+
+    /// Number of decimal places to render amounts of each currency with.
+    /// Only trailing zeros are added or removed, so values never change.
+    pub decimal_places: HashMap<String, usize>,
 }
 
 impl FormattingConfig {
@@ -48,8 +77,39 @@ impl FormattingConfig {
             account_amount_spacing: 2,  // Default spacing like bean-format
             number_currency_spacing: 1, // Default 1 space between number and currency
             indent_width: None,         // Default: no indent normalization
+            blank_lines_between_entries: None,
+            sort_by_date: false,
+            uppercase_flags: false,
+            metadata_indent_width: None,
+            thousands_separator: None,
+            decimal_places: HashMap::new(),
         }
     }
+
+    /// Whether amounts are rewritten, not only aligned.
+    pub fn normalizes_numbers(&self) -> bool {
+        self.thousands_separator.is_some() || !self.decimal_places.is_empty()
+    }
+
+    /// Whether formatting rewrites lines beyond amount alignment and indentation.
+    pub fn normalizes_entries(&self) -> bool {
+        self.blank_lines_between_entries.is_some()
+            || self.sort_by_date
+            || self.uppercase_flags
+            || self.metadata_indent_width.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InlayHintsConfig {
+    /// Show the account balance computed after each `balance` directive (default: false).
+    pub running_balances: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenameConfig {
+    /// Renaming an account also renames its sub-accounts (default: false).
+    pub hierarchical_accounts: bool,
 }
 
 impl Config {
@@ -59,6 +119,8 @@ impl Config {
             journal_root: None,
             formatting: FormattingConfig::default(),
             bean_check: BeancountCheckConfig::default(),
+            inlay_hints: InlayHintsConfig::default(),
+            rename: RenameConfig::default(),
         }
     }
     pub fn update(&mut self, json: serde_json::Value) -> Result<()> {
@@ -97,6 +159,24 @@ impl Config {
                 if let Some(indent_width) = formatting.indent_width {
                     self.formatting.indent_width = Some(indent_width);
                 }
+                if let Some(blank_lines) = formatting.blank_lines_between_entries {
+                    self.formatting.blank_lines_between_entries = Some(blank_lines);
+                }
+                if let Some(sort_by_date) = formatting.sort_by_date {
+                    self.formatting.sort_by_date = sort_by_date;
+                }
+                if let Some(uppercase_flags) = formatting.uppercase_flags {
+                    self.formatting.uppercase_flags = uppercase_flags;
+                }
+                if let Some(metadata_indent_width) = formatting.metadata_indent_width {
+                    self.formatting.metadata_indent_width = Some(metadata_indent_width);
+                }
+                if let Some(thousands_separator) = formatting.thousands_separator {
+                    self.formatting.thousands_separator = Some(thousands_separator);
+                }
+                if let Some(decimal_places) = formatting.decimal_places {
+                    self.formatting.decimal_places = decimal_places;
+                }
             }
 
             // Update bean-check configuration
@@ -113,6 +193,29 @@ impl Config {
                 if let Some(python_script_path) = bean_check.python_script_path {
                     self.bean_check.python_script_path = PathBuf::from(python_script_path);
                 }
+                if let Some(debounce_ms) = bean_check.debounce_ms {
+                    self.bean_check.debounce_ms = debounce_ms;
+                }
+                if let Some(on_change) = bean_check.on_change {
+                    self.bean_check.on_change = on_change;
+                }
+                if let Some(on_change_delay_ms) = bean_check.on_change_delay_ms {
+                    self.bean_check.on_change_delay_ms = on_change_delay_ms;
+                }
+            }
+
+            // Update inlay hint configuration
+            if let Some(inlay_hints) = beancount_lsp_settings.inlay_hints
+                && let Some(running_balances) = inlay_hints.running_balances
+            {
+                self.inlay_hints.running_balances = running_balances;
+            }
+
+            // Update rename configuration
+            if let Some(rename) = beancount_lsp_settings.rename
+                && let Some(hierarchical_accounts) = rename.hierarchical_accounts
+            {
+                self.rename.hierarchical_accounts = hierarchical_accounts;
             }
         }
 
@@ -125,6 +228,8 @@ pub struct BeancountLspOptions {
     pub journal_file: Option<String>,
     pub formatting: Option<FormattingOptions>,
     pub bean_check: Option<BeancountCheckOptions>,
+    pub inlay_hints: Option<InlayHintsOptions>,
+    pub rename: Option<RenameOptions>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
@@ -146,12 +251,30 @@ pub struct FormattingOptions {
 
     /// Enforce consistent indentation width for postings and directives.
     pub indent_width: Option<usize>,
+
+    /// Number of blank lines between consecutive entries.
+    pub blank_lines_between_entries: Option<usize>,
+
+    /// Sort dated entries by date within each section.
+    pub sort_by_date: Option<bool>,
+
+    /// Rewrite lowercase letter flags in uppercase.
+    pub uppercase_flags: Option<bool>,
+
+    /// Metadata indentation relative to the line it belongs to.
+    pub metadata_indent_width: Option<usize>,
+
+    /// Add (true) or remove (false) thousands separators in amounts.
+    pub thousands_separator: Option<bool>,
+
+    /// Decimal places per currency, e.g. `{"USD": 2}`.
+    pub decimal_places: Option<HashMap<String, usize>>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct BeancountCheckOptions {
     /// Method for bean-check execution: "system" or "python"
-    #[serde(with = "bean_check_method_serde")]
+    #[serde(default, with = "bean_check_method_serde")]
     pub method: Option<BeancountCheckMethod>,
     /// Path to bean-check executable (for system method)
     pub bean_check_cmd: Option<String>,
@@ -159,6 +282,24 @@ pub struct BeancountCheckOptions {
     pub python_cmd: Option<String>,
     /// Path to Python script (for python method)
     pub python_script_path: Option<String>,
+    /// Milliseconds to wait after a save for further saves before checking
+    pub debounce_ms: Option<u64>,
+    /// Check while editing once the document has been idle for `on_change_delay_ms`
+    pub on_change: Option<bool>,
+    /// Milliseconds of inactivity before an on-change check
+    pub on_change_delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InlayHintsOptions {
+    /// Show the computed account balance after `balance` directives.
+    pub running_balances: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RenameOptions {
+    /// Also rename the sub-accounts of a renamed account.
+    pub hierarchical_accounts: Option<bool>,
 }
 
 // Custom serde module for BeancountCheckMethod
@@ -176,6 +317,7 @@ mod bean_check_method_serde {
         match value {
             Some(BeancountCheckMethod::SystemCall) => "system".serialize(serializer),
             Some(BeancountCheckMethod::PythonEmbedded) => "python-embedded".serialize(serializer),
+            Some(BeancountCheckMethod::Native) => "native".serialize(serializer),
             None => serializer.serialize_none(),
         }
     }
@@ -190,6 +332,7 @@ mod bean_check_method_serde {
             Some("python-embedded") | Some("pyo3") => {
                 Ok(Some(BeancountCheckMethod::PythonEmbedded))
             }
+            Some("native") => Ok(Some(BeancountCheckMethod::Native)),
             Some(_) => Ok(None), // Invalid method, ignore gracefully
             None => Ok(None),
         }
@@ -242,4 +385,82 @@ mod tests {
             .unwrap();
         assert_eq!(config.journal_root, None);
     }
+
+    #[test]
+    fn test_native_bean_check_method() {
+        let mut config = Config::new(PathBuf::new());
+        config
+            .update(serde_json::from_str("{\"bean_check\": {\"method\": \"native\"}}").unwrap())
+            .unwrap();
+        assert!(matches!(
+            config.bean_check.method,
+            BeancountCheckMethod::Native
+        ));
+    }
+
+    #[test]
+    fn test_bean_check_scheduling_options() {
+        let mut config = Config::new(PathBuf::new());
+        assert!(!config.bean_check.on_change);
+        config
+            .update(
+                serde_json::from_str(
+                    "{\"bean_check\": {\"debounce_ms\": 500, \"on_change\": true, \"on_change_delay_ms\": 2000}}",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(config.bean_check.debounce_ms, 500);
+        assert!(config.bean_check.on_change);
+        assert_eq!(config.bean_check.on_change_delay_ms, 2000);
+    }
+
+    #[test]
+    fn test_inlay_hints_running_balances() {
+        let mut config = Config::new(PathBuf::new());
+        assert!(!config.inlay_hints.running_balances);
+        config
+            .update(
+                serde_json::from_str("{\"inlay_hints\": {\"running_balances\": true}}").unwrap(),
+            )
+            .unwrap();
+        assert!(config.inlay_hints.running_balances);
+    }
+
+    #[test]
+    fn test_rename_hierarchical_accounts() {
+        let mut config = Config::new(PathBuf::new());
+        assert!(!config.rename.hierarchical_accounts);
+        config
+            .update(
+                serde_json::from_str("{\"rename\": {\"hierarchical_accounts\": true}}").unwrap(),
+            )
+            .unwrap();
+        assert!(config.rename.hierarchical_accounts);
+    }
+
+    #[test]
+    fn test_structural_formatting_options() {
+        let mut config = Config::new(PathBuf::new());
+        assert!(!config.formatting.normalizes_entries());
+        assert!(!config.formatting.normalizes_numbers());
+        config
+            .update(
+                serde_json::from_str(
+                    r#"{"formatting": {"blank_lines_between_entries": 1, "sort_by_date": true,
+                        "uppercase_flags": true, "metadata_indent_width": 2,
+                        "thousands_separator": true, "decimal_places": {"USD": 2}}}"#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(config.formatting.blank_lines_between_entries, Some(1));
+        assert!(config.formatting.sort_by_date);
+        assert!(config.formatting.uppercase_flags);
+        assert_eq!(config.formatting.metadata_indent_width, Some(2));
+        assert_eq!(config.formatting.thousands_separator, Some(true));
+        assert_eq!(config.formatting.decimal_places.get("USD"), Some(&2));
+        assert!(config.formatting.normalizes_entries());
+        assert!(config.formatting.normalizes_numbers());
+    }
 }
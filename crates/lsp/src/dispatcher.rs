@@ -1,3 +1,4 @@
+use crate::cancellation::{CancellationToken, Cancelled};
 use crate::from_json;
 use crate::server::LspServerState;
 use crate::server::LspServerStateSnapshot;
@@ -6,6 +7,19 @@ use anyhow::Result;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
+/// Error returned by providers whose request parameters were rejected, such as
+/// an invalid new name for a rename. The message is shown to the user.
+#[derive(Debug)]
+pub(crate) struct InvalidParams(pub String);
+
+impl std::fmt::Display for InvalidParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidParams {}
+
 fn result_to_response<R>(
     id: lsp_server::RequestId,
     result: Result<R::Result>,
@@ -17,6 +31,16 @@ where
 {
     match result {
         Ok(resp) => lsp_server::Response::new_ok(id, &resp),
+        Err(e) if e.is::<Cancelled>() => lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::RequestCanceled as i32,
+            e.to_string(),
+        ),
+        Err(e) if e.is::<InvalidParams>() => lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            e.to_string(),
+        ),
         Err(e) => lsp_server::Response::new_err(
             id,
             lsp_server::ErrorCode::InternalError as i32,
@@ -30,13 +54,19 @@ where
 pub(crate) struct RequestDispatcher<'a> {
     state: &'a mut LspServerState,
     request: Option<lsp_server::Request>,
+    cancellation: CancellationToken,
 }
 
 impl<'a> RequestDispatcher<'a> {
-    pub fn new(state: &'a mut LspServerState, request: lsp_server::Request) -> Self {
+    pub fn new(
+        state: &'a mut LspServerState,
+        request: lsp_server::Request,
+        cancellation: CancellationToken,
+    ) -> Self {
         RequestDispatcher {
             state,
             request: Some(request),
+            cancellation,
         }
     }
 
@@ -85,6 +115,28 @@ impl<'a> RequestDispatcher<'a> {
         Ok(self)
     }
 
+    // Try to dispatch the event as the given Request type on the current thread,
+    // leaving the response to the handler so that it can answer later.
+    pub fn on_sync_deferred<R>(
+        &mut self,
+        f: fn(&mut LspServerState, lsp_server::RequestId, R::Params) -> Result<()>,
+    ) -> Result<&mut Self>
+    where
+        R: lsp_types::request::Request + 'static,
+        R::Params: DeserializeOwned + 'static,
+        R::Result: Serialize + 'static,
+    {
+        let (id, params) = match self.parse::<R>() {
+            Some(it) => it,
+            None => return Ok(self),
+        };
+        if let Err(e) = f(self.state, id.clone(), params) {
+            let response = result_to_response::<R>(id, Err(e));
+            self.state.respond(response);
+        }
+        Ok(self)
+    }
+
     // Try to dispatch the event as the given Request type on the thread pool.
     pub fn on<R>(
         &mut self,
@@ -101,11 +153,16 @@ impl<'a> RequestDispatcher<'a> {
         };
 
         self.state.thread_pool.execute({
-            let snapshot = self.state.snapshot();
+            let mut snapshot = self.state.snapshot();
+            snapshot.cancellation = self.cancellation.clone();
             let sender = self.state.task_sender.clone();
 
             move || {
-                let result = f(snapshot, params);
+                // Requests cancelled while queued are not worth starting
+                let result = match snapshot.cancellation.check() {
+                    Ok(()) => f(snapshot, params),
+                    Err(e) => Err(e.into()),
+                };
                 sender
                     .send(Task::Response(result_to_response::<R>(id, result)))
                     .unwrap();
@@ -181,3 +238,19 @@ impl<'a> NotificationDispatcher<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_params_are_reported_with_their_message() {
+        let response = result_to_response::<lsp_types::request::Rename>(
+            lsp_server::RequestId::from(1),
+            Err(InvalidParams("`Cash` is not a valid account name".to_string()).into()),
+        );
+        let error = response.error.unwrap();
+        assert_eq!(error.code, lsp_server::ErrorCode::InvalidParams as i32);
+        assert_eq!(error.message, "`Cash` is not a valid account name");
+    }
+}
@@ -2,14 +2,12 @@ use crate::beancount_data::BeancountData;
 use crate::server::LspServerStateSnapshot;
 use crate::server::ProgressMsg;
 use crate::server::Task;
-use crate::utils::ToFilePath;
 use crossbeam_channel::Sender;
 use glob::glob;
 use std::collections::{HashMap, HashSet, linked_list::LinkedList};
 use std::fs;
 use std::path;
 use std::path::PathBuf;
-use std::str::FromStr;
 use std::sync::Arc;
 use std::time::SystemTime;
 use tracing::error;
@@ -46,6 +44,23 @@ fn read_file_cached(path: &PathBuf, cache: &mut FileCacheMap) -> anyhow::Result<
     Ok(content)
 }
 
+/// Absolute glob patterns of the `include` directives of a parsed file.
+pub(crate) fn include_patterns(data: &BeancountData, file: &path::Path) -> Vec<String> {
+    data.includes()
+        .filter_map(|filename| {
+            let path = path::Path::new(filename);
+            let path = if path.is_absolute() {
+                path.to_path_buf()
+            } else if file.is_absolute() {
+                file.parent()?.join(path)
+            } else {
+                path.to_path_buf()
+            };
+            Some(path.to_string_lossy().to_string())
+        })
+        .collect()
+}
+
 // Issus to look at if running into issues with this
 // https://github.com/silvanshade/lspower/issues/8
 pub(crate) fn parse_initial_forest(
@@ -90,16 +105,15 @@ pub(crate) fn parse_initial_forest(
             processed += 1;
 
             let text = read_file_cached(file, &mut file_cache)?;
-            let bytes = text.as_bytes();
 
             let mut parser = tree_sitter::Parser::new();
             parser.set_language(&tree_sitter_beancount::language())?;
             let tree = parser.parse(&text, None).unwrap();
             let tree_arc = Arc::new(tree);
-            let mut cursor = tree_arc.root_node().walk();
 
             let content = ropey::Rope::from_str(text.as_str());
             let beancount_data = BeancountData::new(&tree_arc, &content);
+            let include_patterns = include_patterns(&beancount_data, file);
 
             sender
                 .send(Task::Progress(ProgressMsg::ForestInit {
@@ -115,36 +129,6 @@ pub(crate) fn parse_initial_forest(
 
             //snapshot.forest.insert(file.clone(), tree.clone());
 
-            let include_patterns: Vec<String> = tree_arc
-                .root_node()
-                .children(&mut cursor)
-                .filter(|c| c.kind() == "include")
-                .filter_map(|include_node| {
-                    let mut node_cursor = include_node.walk();
-                    let node = include_node
-                        .children(&mut node_cursor)
-                        .find(|c| c.kind() == "string")?;
-
-                    let filename = node
-                        .utf8_text(bytes)
-                        .unwrap()
-                        .trim_start_matches('"')
-                        .trim_end_matches('"');
-
-                    let path = path::Path::new(filename);
-
-                    let path = if path.is_absolute() {
-                        path.to_path_buf()
-                    } else if file.is_absolute() {
-                        file.parent().unwrap().join(path)
-                    } else {
-                        path.to_path_buf()
-                    };
-
-                    Some(path.to_string_lossy().to_string())
-                })
-                .collect();
-
             // Process all include patterns and deduplicate results
             let mut discovered_files = HashSet::new();
             for pattern in include_patterns {
@@ -163,31 +147,12 @@ pub(crate) fn parse_initial_forest(
                 }
             }
 
-            // Convert discovered files to URLs and add to processing queue
+            // Add discovered files to the processing queue
             for path in discovered_files {
-                // Handle cross-platform file URI creation
-                let path_str = path.to_str().unwrap();
-                let uri_str = if cfg!(windows)
-                    && path_str.len() > 1
-                    && path_str.chars().nth(1) == Some(':')
-                {
-                    // Windows absolute path like "C:\path"
-                    format!("file:///{}", path_str.replace('\\', "/"))
-                } else if cfg!(windows) && path_str.starts_with('/') {
-                    // Unix-style path on Windows, convert to Windows style
-                    format!("file:///C:{}", path_str.replace('\\', "/"))
-                } else {
-                    // Unix path or other platforms
-                    format!("file://{path_str}")
-                };
-                let url = lsp_types::Uri::from_str(&uri_str)
-                    .unwrap()
-                    .to_file_path()
-                    .unwrap();
-                if !snapshot.forest.contains_key(&url) && !seen_files.contains(&url) {
+                if !snapshot.forest.contains_key(&path) && !seen_files.contains(&path) {
                     total += 1;
-                    new_to_processs.push_back(url.clone());
-                    seen_files.push_back(url);
+                    new_to_processs.push_back(path.clone());
+                    seen_files.push_back(path);
                 }
             }
         }
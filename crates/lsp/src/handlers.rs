@@ -1,6 +1,13 @@
 pub mod text_document {
+    use crate::providers::code_action;
     use crate::providers::completion;
+    use crate::providers::definition;
+    use crate::providers::diagnostics;
+    use crate::providers::document_symbol;
+    use crate::providers::folding_range;
     use crate::providers::formatting;
+    use crate::providers::hover;
+    use crate::providers::inlay_hint;
     use crate::providers::references;
     use crate::providers::semantic_tokens;
     use crate::providers::text_document;
@@ -147,6 +154,61 @@ pub mod text_document {
         }
     }
 
+    /// handler for `textDocument/rangeFormatting`.
+    pub(crate) fn range_formatting(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<lsp_types::TextEdit>>> {
+        tracing::trace!(
+            "Range formatting requested for: {} lines {}-{}",
+            params.text_document.uri.as_str(),
+            params.range.start.line,
+            params.range.end.line
+        );
+
+        match formatting::range_formatting(snapshot, params) {
+            Ok(Some(edits)) => {
+                tracing::trace!("Range formatting returned {} text edits", edits.len());
+                Ok(Some(edits))
+            }
+            Ok(None) => {
+                tracing::debug!("No range formatting changes needed");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Range formatting failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// handler for `textDocument/onTypeFormatting`.
+    pub(crate) fn on_type_formatting(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<lsp_types::TextEdit>>> {
+        tracing::trace!(
+            "On-type formatting requested for: {} after {:?}",
+            params.text_document_position.text_document.uri.as_str(),
+            params.ch
+        );
+
+        match formatting::on_type_formatting(snapshot, params) {
+            Ok(Some(edits)) => {
+                tracing::trace!("On-type formatting returned {} text edits", edits.len());
+                Ok(Some(edits))
+            }
+            Ok(None) => {
+                tracing::debug!("No on-type formatting changes needed");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("On-type formatting failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     /// handler for `textDocument/willSaveWaitUntil`.
     pub(crate) fn will_save_wait_until(
         snapshot: LspServerStateSnapshot,
@@ -184,6 +246,167 @@ pub mod text_document {
         }
     }
 
+    pub(crate) fn hover(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::HoverParams,
+    ) -> Result<Option<lsp_types::Hover>> {
+        tracing::trace!(
+            "Hover requested for: {} at {}:{}",
+            params
+                .text_document_position_params
+                .text_document
+                .uri
+                .as_str(),
+            params.text_document_position_params.position.line,
+            params.text_document_position_params.position.character
+        );
+
+        match hover::hover(snapshot, params) {
+            Ok(Some(hover)) => Ok(Some(hover)),
+            Ok(None) => {
+                tracing::debug!("No hover information available");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Hover failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn code_action(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::CodeActionParams,
+    ) -> Result<Option<lsp_types::CodeActionResponse>> {
+        tracing::trace!(
+            "Code actions requested for: {} with {} diagnostics",
+            params.text_document.uri.as_str(),
+            params.context.diagnostics.len()
+        );
+
+        match code_action::code_action(snapshot, params) {
+            Ok(Some(actions)) => {
+                tracing::trace!("Returning {} code actions", actions.len());
+                Ok(Some(actions))
+            }
+            Ok(None) => {
+                tracing::debug!("No code actions available");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Code actions failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn definition(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::GotoDefinitionParams,
+    ) -> Result<Option<lsp_types::GotoDefinitionResponse>> {
+        tracing::trace!(
+            "Definition requested for: {} at {}:{}",
+            params
+                .text_document_position_params
+                .text_document
+                .uri
+                .as_str(),
+            params.text_document_position_params.position.line,
+            params.text_document_position_params.position.character
+        );
+
+        match definition::definition(snapshot, params) {
+            Ok(Some(response)) => Ok(Some(response)),
+            Ok(None) => {
+                tracing::debug!("No definition found");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Definition lookup failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// handler for `textDocument/diagnostic`.
+    pub(crate) fn diagnostic(
+        state: &mut LspServerState,
+        params: lsp_types::DocumentDiagnosticParams,
+    ) -> Result<lsp_types::DocumentDiagnosticReportResult> {
+        tracing::trace!(
+            "Diagnostics pulled for: {}",
+            params.text_document.uri.as_str()
+        );
+        state.check_if_stale();
+        diagnostics::document_diagnostic(&state.diagnostic_data, params)
+    }
+
+    pub(crate) fn document_symbol(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::DocumentSymbolParams,
+    ) -> Result<Option<lsp_types::DocumentSymbolResponse>> {
+        tracing::trace!(
+            "Document symbols requested for: {}",
+            params.text_document.uri.as_str()
+        );
+
+        match document_symbol::document_symbol(snapshot, params) {
+            Ok(Some(response)) => Ok(Some(response)),
+            Ok(None) => {
+                tracing::debug!("No document symbols available");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Document symbols failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn folding_range(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::FoldingRangeParams,
+    ) -> Result<Option<Vec<lsp_types::FoldingRange>>> {
+        tracing::trace!(
+            "Folding ranges requested for: {}",
+            params.text_document.uri.as_str()
+        );
+
+        match folding_range::folding_range(snapshot, params) {
+            Ok(Some(ranges)) => Ok(Some(ranges)),
+            Ok(None) => {
+                tracing::debug!("No folding ranges available");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Folding ranges failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn inlay_hint(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::InlayHintParams,
+    ) -> Result<Option<Vec<lsp_types::InlayHint>>> {
+        tracing::trace!(
+            "Inlay hints requested for: {}",
+            params.text_document.uri.as_str()
+        );
+
+        match inlay_hint::inlay_hint(snapshot, params) {
+            Ok(Some(hints)) => Ok(Some(hints)),
+            Ok(None) => {
+                tracing::debug!("No inlay hints available");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Inlay hints failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     pub(crate) fn handle_references(
         snapshot: LspServerStateSnapshot,
         params: lsp_types::ReferenceParams,
@@ -211,6 +434,30 @@ pub mod text_document {
         }
     }
 
+    pub(crate) fn handle_prepare_rename(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::TextDocumentPositionParams,
+    ) -> Result<Option<lsp_types::PrepareRenameResponse>> {
+        tracing::trace!(
+            "Prepare rename requested for: {} at {}:{}",
+            params.text_document.uri.as_str(),
+            params.position.line,
+            params.position.character
+        );
+
+        match references::prepare_rename(snapshot, params) {
+            Ok(Some(response)) => Ok(Some(response)),
+            Ok(None) => {
+                tracing::debug!("Nothing to rename at position");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Prepare rename failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     pub(crate) fn handle_rename(
         snapshot: LspServerStateSnapshot,
         params: lsp_types::RenameParams,
@@ -254,4 +501,117 @@ pub mod text_document {
         );
         semantic_tokens::semantic_tokens_full(snapshot, params)
     }
+
+    pub(crate) fn semantic_tokens_full_delta(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::SemanticTokensDeltaParams,
+    ) -> Result<Option<lsp_types::SemanticTokensFullDeltaResult>> {
+        tracing::debug!(
+            "Semantic tokens delta requested for: {}",
+            params.text_document.uri.as_str()
+        );
+        semantic_tokens::semantic_tokens_full_delta(snapshot, params)
+    }
+
+    pub(crate) fn semantic_tokens_range(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::SemanticTokensRangeParams,
+    ) -> Result<Option<lsp_types::SemanticTokensRangeResult>> {
+        tracing::debug!(
+            "Semantic tokens range requested for: {}",
+            params.text_document.uri.as_str()
+        );
+        semantic_tokens::semantic_tokens_range(snapshot, params)
+    }
+}
+
+pub mod workspace {
+    use crate::providers::watched_files;
+    use crate::providers::workspace_symbol;
+    use crate::server::CONFIGURATION_SECTION;
+    use crate::server::LspServerState;
+    use crate::server::LspServerStateSnapshot;
+    use anyhow::Result;
+
+    /// handler for `workspace/didChangeConfiguration`.
+    pub(crate) fn did_change_configuration(
+        state: &mut LspServerState,
+        params: lsp_types::DidChangeConfigurationParams,
+    ) -> Result<()> {
+        tracing::trace!("Configuration changed");
+
+        // Clients that support it send an empty notification and expect a pull
+        if state.supports_configuration_pull() {
+            state.request_configuration();
+            return Ok(());
+        }
+
+        // Settings may be nested under the section name or sent as-is
+        let settings = match params.settings.get(CONFIGURATION_SECTION) {
+            Some(section) => section.clone(),
+            None => params.settings,
+        };
+        state.apply_config(settings);
+        Ok(())
+    }
+
+    /// handler for `workspace/didChangeWatchedFiles`.
+    pub(crate) fn did_change_watched_files(
+        state: &mut LspServerState,
+        params: lsp_types::DidChangeWatchedFilesParams,
+    ) -> Result<()> {
+        tracing::trace!("Watched files changed: {} events", params.changes.len());
+        watched_files::did_change_watched_files(state, params)
+    }
+
+    /// handler for `workspace/diagnostic`.
+    ///
+    /// Held open while nothing changed for the client, instead of having it poll again.
+    pub(crate) fn diagnostic(
+        state: &mut LspServerState,
+        id: lsp_server::RequestId,
+        params: lsp_types::WorkspaceDiagnosticParams,
+    ) -> Result<()> {
+        tracing::trace!(
+            "Workspace diagnostics requested with {} previous result ids",
+            params.previous_result_ids.len()
+        );
+        state.check_if_stale();
+        state.answer_workspace_diagnostic(id, params);
+        Ok(())
+    }
+
+    /// handler for `workspace/symbol`.
+    pub(crate) fn symbol(
+        snapshot: LspServerStateSnapshot,
+        params: lsp_types::WorkspaceSymbolParams,
+    ) -> Result<Option<lsp_types::WorkspaceSymbolResponse>> {
+        tracing::trace!("Workspace symbols requested for query: '{}'", params.query);
+
+        match workspace_symbol::workspace_symbol(snapshot, params) {
+            Ok(Some(response)) => Ok(Some(response)),
+            Ok(None) => {
+                tracing::debug!("No workspace symbols found");
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::error!("Workspace symbols failed: {}", e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// handler for `$/cancelRequest`.
+pub(crate) fn cancel_request(
+    state: &mut crate::server::LspServerState,
+    params: lsp_types::CancelParams,
+) -> anyhow::Result<()> {
+    let id: lsp_server::RequestId = match params.id {
+        lsp_types::NumberOrString::Number(id) => id.into(),
+        lsp_types::NumberOrString::String(id) => id.into(),
+    };
+    tracing::trace!("Cancel requested for request {}", id);
+    state.cancel_request(id);
+    Ok(())
 }
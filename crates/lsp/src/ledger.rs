@@ -0,0 +1,221 @@
+use crate::treesitter_utils::text_for_tree_sitter_node;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use tree_sitter_beancount::tree_sitter;
+
+/// A number together with its currency, e.g. `10.50 USD`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Amount {
+    pub number: Decimal,
+    pub currency: String,
+}
+
+impl Amount {
+    pub fn new(number: Decimal, currency: impl Into<String>) -> Self {
+        Self {
+            number,
+            currency: currency.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.number, self.currency)
+    }
+}
+
+/// Per-currency totals, ordered by currency for stable output.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Inventory {
+    positions: BTreeMap<String, Decimal>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, number: Decimal, currency: &str) {
+        *self
+            .positions
+            .entry(currency.to_string())
+            .or_insert(Decimal::ZERO) += number;
+    }
+
+    pub fn add_amount(&mut self, amount: &Amount) {
+        self.add(amount.number, &amount.currency);
+    }
+
+    pub fn get(&self, currency: &str) -> Decimal {
+        self.positions
+            .get(currency)
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Iterate over the non-zero positions.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Decimal)> {
+        self.positions
+            .iter()
+            .filter(|(_, number)| !number.is_zero())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Return the positions as amounts, skipping zero balances.
+    pub fn amounts(&self) -> Vec<Amount> {
+        self.iter()
+            .map(|(currency, number)| Amount::new(*number, currency.clone()))
+            .collect()
+    }
+}
+
+/// Parse a beancount number literal, accepting thousands separators.
+pub fn parse_number(text: &str) -> Option<Decimal> {
+    let cleaned: String = text.chars().filter(|c| *c != ',').collect();
+    Decimal::from_str(cleaned.trim()).ok()
+}
+
+/// Parse a date in `YYYY-MM-DD` or `YYYY/MM/DD` form.
+pub fn parse_date(text: &str) -> Option<chrono::NaiveDate> {
+    let normalized = text.trim().replace('/', "-");
+    chrono::NaiveDate::parse_from_str(&normalized, "%Y-%m-%d").ok()
+}
+
+/// Infer the tolerance implied by the precision of a number.
+///
+/// Like beancount, this is half of the last significant digit; integers have no tolerance.
+pub fn infer_tolerance(number: Decimal) -> Decimal {
+    match number.scale() {
+        0 => Decimal::ZERO,
+        scale => Decimal::new(5, scale + 1),
+    }
+}
+
+/// Returns true when the node kind is one of the arithmetic expression nodes.
+pub fn is_number_expr(kind: &str) -> bool {
+    matches!(kind, "number" | "unary_number_expr" | "binary_number_expr")
+}
+
+/// Evaluate a `number`, `unary_number_expr` or `binary_number_expr` node.
+pub fn eval_number_expr(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<Decimal> {
+    match node.kind() {
+        "number" => parse_number(&text_for_tree_sitter_node(content, node)),
+        "unary_number_expr" => {
+            let mut cursor = node.walk();
+            let children: Vec<_> = node.named_children(&mut cursor).collect();
+            let operator = children.first()?;
+            let operand = children.iter().find(|c| is_number_expr(c.kind()))?;
+            let value = eval_number_expr(operand, content)?;
+            match operator.kind() {
+                "minus" => Some(-value),
+                _ => Some(value),
+            }
+        }
+        "binary_number_expr" => {
+            let mut cursor = node.walk();
+            let children: Vec<_> = node.named_children(&mut cursor).collect();
+            let mut operands = children.iter().filter(|c| is_number_expr(c.kind()));
+            let lhs = eval_number_expr(operands.next()?, content)?;
+            let rhs = eval_number_expr(operands.next()?, content)?;
+            let operator = children
+                .iter()
+                .find(|c| matches!(c.kind(), "plus" | "minus" | "asterisk" | "slash"))?;
+            match operator.kind() {
+                "plus" => lhs.checked_add(rhs),
+                "minus" => lhs.checked_sub(rhs),
+                "asterisk" => lhs.checked_mul(rhs),
+                _ => lhs.checked_div(rhs),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Extract the amount from a node holding a number expression followed by a
+/// currency (`amount`, `incomplete_amount`, `amount_tolerance`).
+pub fn amount_from_node(node: &tree_sitter::Node, content: &ropey::Rope) -> Option<Amount> {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.named_children(&mut cursor).collect();
+    let number_node = children.iter().find(|c| is_number_expr(c.kind()))?;
+    let currency_node = children.iter().find(|c| c.kind() == "currency")?;
+    Some(Amount::new(
+        eval_number_expr(number_node, content)?,
+        text_for_tree_sitter_node(content, currency_node),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> (tree_sitter::Tree, ropey::Rope) {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        (
+            parser.parse(text, None).unwrap(),
+            ropey::Rope::from_str(text),
+        )
+    }
+
+    fn first_amount(text: &str) -> Option<Amount> {
+        let (tree, content) = parse(text);
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "incomplete_amount" {
+                return amount_from_node(&node, &content);
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        None
+    }
+
+    #[test]
+    fn test_parse_number_with_separators() {
+        assert_eq!(parse_number("1,234.50"), Some(Decimal::new(123450, 2)));
+        assert_eq!(parse_number("abc"), None);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_date("2024/01/31"),
+            chrono::NaiveDate::from_ymd_opt(2024, 1, 31)
+        );
+        assert_eq!(parse_date("2024-13-01"), None);
+    }
+
+    #[test]
+    fn test_infer_tolerance() {
+        assert_eq!(infer_tolerance(Decimal::new(1050, 2)), Decimal::new(5, 3));
+        assert_eq!(infer_tolerance(Decimal::new(10, 0)), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_amount_from_expression() {
+        let amount = first_amount("2024-01-01 *\n  Assets:Cash  (10 + 2) * 3 USD\n").unwrap();
+        assert_eq!(amount, Amount::new(Decimal::new(36, 0), "USD"));
+
+        let amount = first_amount("2024-01-01 *\n  Assets:Cash  -12.50 USD\n").unwrap();
+        assert_eq!(amount, Amount::new(Decimal::new(-1250, 2), "USD"));
+    }
+
+    #[test]
+    fn test_inventory_skips_zero_positions() {
+        let mut inventory = Inventory::new();
+        inventory.add(Decimal::new(10, 0), "USD");
+        inventory.add(Decimal::new(-10, 0), "USD");
+        inventory.add(Decimal::new(5, 0), "EUR");
+        assert_eq!(
+            inventory.amounts(),
+            vec![Amount::new(Decimal::new(5, 0), "EUR")]
+        );
+    }
+}
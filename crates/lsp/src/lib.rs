@@ -1,18 +1,23 @@
 mod beancount_data;
+mod cancellation;
 mod capabilities;
+mod check_scheduler;
 pub mod checkers;
+pub mod cli;
 mod config;
 mod dispatcher;
 pub mod document;
 //pub mod error;
 pub mod forest;
 pub mod handlers;
+mod ledger;
 pub mod progress;
 pub mod providers;
 pub mod server;
 //pub mod session;
 mod treesitter_utils;
 mod utils;
+mod workspace_index;
 
 use crate::config::Config;
 use crate::server::LspServerState;
@@ -109,7 +114,7 @@ pub fn run_server() -> Result<()> {
     };
 
     tracing::debug!("Starting main loop");
-    main_loop(connection, config)?;
+    main_loop(connection, config, initialize_params.capabilities)?;
 
     tracing::debug!("Waiting for IO threads to complete");
     io_threads.join()?;
@@ -118,9 +123,13 @@ pub fn run_server() -> Result<()> {
     Ok(())
 }
 
-pub fn main_loop(connection: Connection, config: Config) -> Result<()> {
+pub fn main_loop(
+    connection: Connection,
+    config: Config,
+    client_capabilities: lsp_types::ClientCapabilities,
+) -> Result<()> {
     tracing::info!("initial config: {:#?}", config);
-    LspServerState::new(connection.sender, config).run(connection.receiver)
+    LspServerState::new(connection.sender, config, client_capabilities).run(connection.receiver)
 }
 
 pub fn from_json<T: DeserializeOwned>(what: &'static str, json: serde_json::Value) -> Result<T> {
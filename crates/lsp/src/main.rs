@@ -1,6 +1,8 @@
-use clap::{Command, arg};
+use beancount_language_server::cli::{self, FormatMode};
+use clap::{ArgMatches, Command, arg, value_parser};
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
@@ -13,6 +15,32 @@ fn main() {
             arg!(--log [LOG_LEVEL] "write log to file with optional level (trace, debug, info, warn, error)"),
             arg!(version: -v --version),
         ])
+        .subcommand(
+            Command::new("format")
+                .about("Format beancount files like the language server does")
+                .args(&[
+                    arg!(<FILES> ... "files to format").value_parser(value_parser!(PathBuf)),
+                    arg!(--check "report files that are not formatted instead of writing them"),
+                    arg!(--diff "print the formatting changes instead of writing them"),
+                    config_arg(),
+                ]),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Run bean-check on a journal and report its errors")
+                .args(&[
+                    arg!(<ROOT> "root journal file").value_parser(value_parser!(PathBuf)),
+                    config_arg(),
+                ]),
+        )
+        .subcommand(
+            Command::new("lint")
+                .about("Report every diagnostic the language server shows for a journal")
+                .args(&[
+                    arg!(<ROOT> "root journal file").value_parser(value_parser!(PathBuf)),
+                    config_arg(),
+                ]),
+        )
         .get_matches();
 
     if matches.args_present() && matches.get_flag("version") {
@@ -22,6 +50,13 @@ fn main() {
 
     let log_to_file = matches.contains_id("log");
     let log_level = matches.get_one::<String>("log");
+
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        // Keep the output of subcommands readable unless asked for more
+        let warn = String::from("warn");
+        setup_logging(log_to_file, log_level.or(Some(&warn)));
+        std::process::exit(run_subcommand(name, sub_matches));
+    }
     setup_logging(log_to_file, log_level);
 
     tracing::info!(
@@ -46,6 +81,54 @@ fn main() {
     }
 }
 
+fn config_arg() -> clap::Arg {
+    arg!(--config <FILE> "JSON file with the same settings as the initialization options")
+        .value_parser(value_parser!(PathBuf))
+}
+
+/// Run a subcommand and return the process exit code: 1 when files are not
+/// formatted or have diagnostics, 2 when the command itself failed.
+fn run_subcommand(name: &str, matches: &ArgMatches) -> i32 {
+    let config = matches.get_one::<PathBuf>("config").map(PathBuf::as_path);
+    let mut out = io::stdout().lock();
+    let result = match name {
+        "format" => {
+            let files: Vec<PathBuf> = matches
+                .get_many::<PathBuf>("FILES")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
+            let mode = if matches.get_flag("diff") {
+                FormatMode::Diff
+            } else if matches.get_flag("check") {
+                FormatMode::Check
+            } else {
+                FormatMode::Write
+            };
+            cli::format(&files, mode, config, &mut out)
+        }
+        "check" => cli::check(root_arg(matches), config, &mut out),
+        "lint" => cli::lint(root_arg(matches), config, &mut out),
+        _ => unreachable!("unknown subcommand {name}"),
+    };
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            2
+        }
+    }
+}
+
+fn root_arg(matches: &ArgMatches) -> &Path {
+    matches
+        .get_one::<PathBuf>("ROOT")
+        .expect("ROOT is required")
+}
+
 fn setup_logging(log_to_file: bool, log_level_arg: Option<&String>) {
     let level = match log_level_arg {
         Some(level_str) => parse_log_level(level_str),
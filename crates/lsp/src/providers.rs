@@ -1,7 +1,19 @@
+/// Provider definitions for LSP `textDocument/codeAction`.
+pub mod code_action;
 pub mod completion;
-/// Provider definitions for LSP `textDocument/publishDiagnostics`.
+/// Provider definitions for LSP `textDocument/definition`.
+pub mod definition;
+/// Provider definitions for LSP `textDocument/publishDiagnostics` and pull diagnostics.
 pub mod diagnostics;
+/// Provider definitions for LSP `textDocument/documentSymbol`.
+pub mod document_symbol;
+/// Provider definitions for LSP `textDocument/foldingRange`.
+pub mod folding_range;
 pub mod formatting;
+/// Provider definitions for LSP `textDocument/hover`.
+pub mod hover;
+/// Provider definitions for LSP `textDocument/inlayHint`.
+pub mod inlay_hint;
 /// Provider definitions for LSP `textDocument/references` and `textDocument/rename`.
 pub mod references;
 /// Provider definitions for LSP semantic tokens (syntax highlighting).
@@ -10,3 +22,7 @@ pub mod semantic_tokens;
 pub mod text_document;
 /// Utilities for cross-platform URI handling.
 pub mod uri;
+/// Provider definitions for LSP `workspace/didChangeWatchedFiles`.
+pub mod watched_files;
+/// Provider definitions for LSP `workspace/symbol`.
+pub mod workspace_symbol;
@@ -0,0 +1,466 @@
+use crate::beancount_data::BeancountData;
+use crate::providers::uri::file_path_to_uri;
+use crate::server::LspServerStateSnapshot;
+use crate::treesitter_utils::{lsp_position_to_point, text_for_tree_sitter_node};
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use lsp_types::{CodeAction, CodeActionKind, CodeActionOrCommand, Diagnostic, Position, TextEdit};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tree_sitter_beancount::tree_sitter;
+
+static UNKNOWN_ACCOUNT_REGEX: OnceLock<regex::Regex> = OnceLock::new();
+static MISSING_COMMODITY_REGEX: OnceLock<regex::Regex> = OnceLock::new();
+
+fn unknown_account_regex() -> &'static regex::Regex {
+    UNKNOWN_ACCOUNT_REGEX.get_or_init(|| {
+        regex::Regex::new(r"unknown account '([^']+)'").expect("Failed to compile regex")
+    })
+}
+
+fn missing_commodity_regex() -> &'static regex::Regex {
+    MISSING_COMMODITY_REGEX.get_or_init(|| {
+        regex::Regex::new(r"Missing Commodity directive for '([^']+)'")
+            .expect("Failed to compile regex")
+    })
+}
+
+/// Provider function for LSP `textDocument/codeAction`.
+///
+/// Offers quick fixes for diagnostics reported by bean-check or the native
+/// checker, plus adding a `commodity` directive for an undeclared currency
+/// under the cursor.
+pub(crate) fn code_action(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::CodeActionParams,
+) -> Result<Option<lsp_types::CodeActionResponse>> {
+    let path = params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&path), snapshot.open_docs.get(&path))
+    else {
+        return Ok(None);
+    };
+    let context = ActionContext {
+        data: &snapshot.beancount_data,
+        path: &path,
+        tree,
+        content: &doc.content,
+    };
+
+    let mut actions = Vec::new();
+    for diagnostic in &params.context.diagnostics {
+        if let Some(caps) = unknown_account_regex().captures(&diagnostic.message) {
+            actions.extend(context.insert_open(diagnostic, &caps[1]));
+        } else if let Some(caps) = missing_commodity_regex().captures(&diagnostic.message) {
+            actions.extend(context.add_commodity(Some(diagnostic), &caps[1]));
+        } else if diagnostic
+            .message
+            .starts_with("Transaction does not balance")
+        {
+            actions.extend(context.balance_transaction(diagnostic));
+        } else if diagnostic.code
+            == Some(lsp_types::NumberOrString::String(
+                "flagged-entry".to_string(),
+            ))
+        {
+            actions.extend(context.clear_flag(diagnostic));
+        }
+    }
+
+    // Undeclared currency under the cursor, for checkers that report it at file level
+    if let Some(currency) = context.currency_at(params.range.start)
+        && !actions
+            .iter()
+            .any(|action| action.title == add_commodity_title(&currency))
+    {
+        actions.extend(context.add_commodity(None, &currency));
+    }
+
+    Ok(Some(
+        actions
+            .into_iter()
+            .map(CodeActionOrCommand::CodeAction)
+            .collect(),
+    ))
+}
+
+fn add_commodity_title(currency: &str) -> String {
+    format!("Add missing commodity directive for {currency}")
+}
+
+struct ActionContext<'a> {
+    data: &'a HashMap<PathBuf, Arc<BeancountData>>,
+    path: &'a Path,
+    tree: &'a tree_sitter::Tree,
+    content: &'a ropey::Rope,
+}
+
+impl ActionContext<'_> {
+    /// Top-level entry (transaction, directive) starting on `line`.
+    fn entry_at_line(&self, line: u32) -> Option<tree_sitter::Node<'_>> {
+        let point = tree_sitter::Point {
+            row: line as usize,
+            column: 0,
+        };
+        let mut node = self
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)?;
+        while let Some(parent) = node.parent() {
+            if matches!(parent.kind(), "file" | "section") {
+                return Some(node);
+            }
+            node = parent;
+        }
+        None
+    }
+
+    fn entry_date(&self, line: u32) -> Option<chrono::NaiveDate> {
+        let entry = self.entry_at_line(line)?;
+        let date = entry.child_by_field_name("date")?;
+        crate::ledger::parse_date(&text_for_tree_sitter_node(self.content, &date))
+    }
+
+    fn currency_at(&self, position: Position) -> Option<String> {
+        let point = lsp_position_to_point(self.content, position);
+        let node = self
+            .tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)
+            .filter(|node| node.kind() == "currency")?;
+        let currency = text_for_tree_sitter_node(self.content, &node);
+        let declared = self.data.values().any(|data| {
+            data.commodity_declarations()
+                .any(|commodity| commodity.currency == currency)
+        });
+        (!declared).then_some(currency)
+    }
+
+    /// The file holding the most `open` directives, the position after the last
+    /// one, and the prefix needed when that directive ends without a newline.
+    fn open_directive_insertion(&self) -> (PathBuf, Position, &'static str) {
+        let mut files: Vec<_> = self
+            .data
+            .iter()
+            .filter(|(_, data)| data.opens().next().is_some())
+            .collect();
+        files.sort_by(|a, b| {
+            b.1.opens()
+                .count()
+                .cmp(&a.1.opens().count())
+                .then(a.0.cmp(b.0))
+        });
+
+        match files.first() {
+            Some((path, data)) => {
+                let end = data
+                    .opens()
+                    .map(|open| open.entry_range.end)
+                    .max()
+                    .unwrap_or_default();
+                let prefix = if end.character == 0 { "" } else { "\n" };
+                (path.to_path_buf(), end, prefix)
+            }
+            None => (self.path.to_path_buf(), Position::new(0, 0), ""),
+        }
+    }
+
+    /// Earliest date on which an account is referenced.
+    fn first_use(&self, account: &str) -> Option<chrono::NaiveDate> {
+        self.data
+            .values()
+            .flat_map(|data| {
+                let postings = data.transactions().filter_map(|txn| {
+                    txn.postings
+                        .iter()
+                        .any(|p| p.account == account)
+                        .then_some(txn.date)?
+                });
+                let balances = data
+                    .balances()
+                    .filter(|b| b.account == account)
+                    .filter_map(|b| b.date);
+                let pads = data
+                    .pads()
+                    .filter(|p| p.account == account || p.source_account == account)
+                    .filter_map(|p| p.date);
+                postings.chain(balances).chain(pads).collect::<Vec<_>>()
+            })
+            .min()
+    }
+
+    fn insert_open(&self, diagnostic: &Diagnostic, account: &str) -> Option<CodeAction> {
+        let first_use = self
+            .first_use(account)
+            .or_else(|| self.entry_date(diagnostic.range.start.line))?;
+        let date = first_use.pred_opt().unwrap_or(first_use);
+        let (path, position, prefix) = self.open_directive_insertion();
+        quick_fix(
+            format!("Insert open directive for {account}"),
+            Some(diagnostic),
+            &path,
+            TextEdit::new(
+                lsp_types::Range::new(position, position),
+                format!("{prefix}{} open {account}\n", date.format("%Y-%m-%d")),
+            ),
+        )
+    }
+
+    fn add_commodity(&self, diagnostic: Option<&Diagnostic>, currency: &str) -> Option<CodeAction> {
+        let date = self
+            .data
+            .values()
+            .flat_map(|data| data.opens().filter_map(|open| open.date))
+            .min()?;
+        let (path, position, prefix) = self.open_directive_insertion();
+        quick_fix(
+            add_commodity_title(currency),
+            diagnostic,
+            &path,
+            TextEdit::new(
+                lsp_types::Range::new(position, position),
+                format!("{prefix}{} commodity {currency}\n", date.format("%Y-%m-%d")),
+            ),
+        )
+    }
+
+    fn balance_transaction(&self, diagnostic: &Diagnostic) -> Option<CodeAction> {
+        let line = diagnostic.range.start.line;
+        let txn = self
+            .data
+            .get(self.path)?
+            .transactions()
+            .find(|txn| txn.line == line)?;
+        let last_posting = txn.postings.last()?;
+        let residual = txn.residual();
+        if residual.is_empty() {
+            return None;
+        }
+
+        let indent: String = self
+            .content
+            .line(last_posting.line as usize)
+            .chars()
+            .take_while(|c| c.is_whitespace() && *c != '\n')
+            .collect();
+        let new_text: String = residual
+            .amounts()
+            .into_iter()
+            .map(|amount| {
+                format!(
+                    "{indent}{}  {} {}\n",
+                    last_posting.account, -amount.number, amount.currency
+                )
+            })
+            .collect();
+
+        let entry = self.entry_at_line(line)?;
+        let end = entry.end_position();
+        let (position, new_text) = if end.column == 0 {
+            (Position::new(end.row as u32, 0), new_text)
+        } else {
+            let position = Position::new(end.row as u32, end.column as u32);
+            (position, format!("\n{}", new_text.trim_end_matches('\n')))
+        };
+
+        quick_fix(
+            format!(
+                "Balance transaction with residual posting to {}",
+                last_posting.account
+            ),
+            Some(diagnostic),
+            self.path,
+            TextEdit::new(lsp_types::Range::new(position, position), new_text),
+        )
+    }
+
+    fn clear_flag(&self, diagnostic: &Diagnostic) -> Option<CodeAction> {
+        let entry = self.entry_at_line(diagnostic.range.start.line)?;
+        let txn = entry.child_by_field_name("txn")?;
+        if text_for_tree_sitter_node(self.content, &txn) != "!" {
+            return None;
+        }
+        quick_fix(
+            "Clear `!` flag to `*`".to_string(),
+            Some(diagnostic),
+            self.path,
+            TextEdit::new(
+                crate::treesitter_utils::lsp_range_for_node(self.content, &txn),
+                "*".to_string(),
+            ),
+        )
+    }
+}
+
+fn quick_fix(
+    title: String,
+    diagnostic: Option<&Diagnostic>,
+    path: &Path,
+    edit: TextEdit,
+) -> Option<CodeAction> {
+    #[allow(clippy::mutable_key_type)]
+    let mut changes = HashMap::new();
+    changes.insert(file_path_to_uri(path)?, vec![edit]);
+    Some(CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: diagnostic.map(|d| vec![d.clone()]),
+        edit: Some(lsp_types::WorkspaceEdit::new(changes)),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    struct TestState {
+        snapshot: LspServerStateSnapshot,
+        uri: lsp_types::Uri,
+    }
+
+    impl TestState {
+        /// Build a snapshot from `(file name, text)` pairs; the first file is the one queried.
+        fn new(files: &[(&str, &str)]) -> Self {
+            let dir = std::env::current_dir().unwrap();
+            let files: Vec<_> = files
+                .iter()
+                .map(|(name, text)| (dir.join(name), *text))
+                .collect();
+            Self {
+                snapshot: LspServerStateSnapshot::from_files(Config::new(dir), &files),
+                uri: file_path_to_uri(&files[0].0).unwrap(),
+            }
+        }
+
+        fn actions(&self, position: Position, diagnostics: Vec<Diagnostic>) -> Vec<CodeAction> {
+            let params = lsp_types::CodeActionParams {
+                text_document: lsp_types::TextDocumentIdentifier {
+                    uri: self.uri.clone(),
+                },
+                range: lsp_types::Range::new(position, position),
+                context: lsp_types::CodeActionContext {
+                    diagnostics,
+                    only: None,
+                    trigger_kind: None,
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            code_action(self.snapshot.clone(), params)
+                .unwrap()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|action| match action {
+                    CodeActionOrCommand::CodeAction(action) => action,
+                    CodeActionOrCommand::Command(_) => panic!("Expected code action"),
+                })
+                .collect()
+        }
+    }
+
+    fn diagnostic(line: u32, message: &str) -> Diagnostic {
+        Diagnostic {
+            range: lsp_types::Range::new(Position::new(line, 0), Position::new(line, 0)),
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The single edit of an action, with the file name it applies to.
+    fn only_edit(action: &CodeAction) -> (String, TextEdit) {
+        #[allow(clippy::mutable_key_type)]
+        let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        assert_eq!(changes.len(), 1);
+        let (uri, edits) = changes.iter().next().unwrap();
+        assert_eq!(edits.len(), 1);
+        let name = uri.as_str().rsplit('/').next().unwrap().to_string();
+        (name, edits[0].clone())
+    }
+
+    const MAIN: &str = r#"2024-03-05 * "Grocer"
+  Expenses:Food  10.00 USD
+  Assets:Cash   -9.50 USD
+2024-03-06 ! "Pending"
+  Expenses:Food  1 USD
+  Assets:Cash
+"#;
+
+    const ACCOUNTS: &str = r#"2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Misc
+  description: "misc"
+"#;
+
+    #[test]
+    fn test_insert_open_for_unknown_account() {
+        let state = TestState::new(&[("main.beancount", MAIN), ("accounts.beancount", ACCOUNTS)]);
+        let actions = state.actions(
+            Position::new(1, 4),
+            vec![diagnostic(
+                1,
+                "Invalid reference to unknown account 'Expenses:Food'",
+            )],
+        );
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].title, "Insert open directive for Expenses:Food");
+        let (file, edit) = only_edit(&actions[0]);
+        assert_eq!(file, "accounts.beancount");
+        assert_eq!(edit.range.start, Position::new(3, 0));
+        assert_eq!(edit.new_text, "2024-03-04 open Expenses:Food\n");
+    }
+
+    #[test]
+    fn test_balance_transaction_with_residual_posting() {
+        let state = TestState::new(&[("main.beancount", MAIN)]);
+        let actions = state.actions(
+            Position::new(0, 0),
+            vec![diagnostic(0, "Transaction does not balance: (0.50 USD)")],
+        );
+        assert_eq!(actions.len(), 1);
+        let (_, edit) = only_edit(&actions[0]);
+        assert_eq!(edit.range.start, Position::new(3, 0));
+        assert_eq!(edit.new_text, "  Assets:Cash  -0.50 USD\n");
+    }
+
+    #[test]
+    fn test_clear_flag() {
+        let state = TestState::new(&[("main.beancount", MAIN)]);
+        let mut flagged = diagnostic(3, "Transaction flagged for review");
+        flagged.code = Some(lsp_types::NumberOrString::String(
+            "flagged-entry".to_string(),
+        ));
+        let actions = state.actions(Position::new(3, 0), vec![flagged]);
+        assert_eq!(actions.len(), 1);
+        let (_, edit) = only_edit(&actions[0]);
+        assert_eq!(edit.range.start, Position::new(3, 11));
+        assert_eq!(edit.new_text, "*");
+    }
+
+    #[test]
+    fn test_add_missing_commodity() {
+        let state = TestState::new(&[("main.beancount", MAIN), ("accounts.beancount", ACCOUNTS)]);
+
+        let actions = state.actions(
+            Position::new(0, 0),
+            vec![diagnostic(
+                0,
+                "Missing Commodity directive for 'USD' in 'Expenses:Food'",
+            )],
+        );
+        assert_eq!(actions.len(), 1);
+        let (file, edit) = only_edit(&actions[0]);
+        assert_eq!(file, "accounts.beancount");
+        assert_eq!(edit.new_text, "2024-01-01 commodity USD\n");
+
+        // Also offered from the cursor when no diagnostic points at it
+        let actions = state.actions(Position::new(1, 23), vec![]);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].title, "Add missing commodity directive for USD");
+        assert!(actions[0].diagnostics.is_none());
+    }
+}
@@ -1,6 +1,9 @@
-use crate::beancount_data::BeancountData;
+use crate::beancount_data::{BeancountData, SymbolKind, TransactionData};
+use crate::cancellation::CancellationToken;
+use crate::ledger::{Amount, Inventory};
 use crate::server::LspServerStateSnapshot;
 use crate::utils::ToFilePath;
+use crate::workspace_index::{AccountCounts, WorkspaceIndex};
 use anyhow::Result;
 use chrono::Datelike;
 use lsp_types::{CompletionItem, CompletionItemKind, Position, Range, TextEdit};
@@ -9,7 +12,7 @@ use nucleo::{
     pattern::{CaseMatching, Normalization, Pattern},
 };
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::debug;
 use tree_sitter::Point;
@@ -117,9 +120,10 @@ pub(crate) fn completion(
     let context = determine_completion_context(tree, content, cursor_point, trigger_character);
 
     debug!("Determined context: {:?}", context);
+    snapshot.cancellation.check()?;
 
     // Generate completions based on context
-    generate_completions(&snapshot.beancount_data, &context, content, cursor.position)
+    generate_completions(&snapshot, &uri, &context, content, cursor.position)
 }
 
 /// Determine completion context using left-context-aware traversal.
@@ -593,11 +597,13 @@ fn extract_link_prefix(line: &str, cursor_col: usize) -> Option<String> {
 
 /// Generate completions based on context with LSP 3.17 InsertReplaceEdit support
 fn generate_completions(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
+    snapshot: &LspServerStateSnapshot,
+    path: &Path,
     context: &CompletionContext,
     content: &ropey::Rope,
     position: Position,
 ) -> Result<Option<Vec<CompletionItem>>> {
+    let data = snapshot.index.as_ref();
     match context {
         CompletionContext::DocumentRoot => {
             let mut items = complete_date(content, position)?;
@@ -608,30 +614,66 @@ fn generate_completions(
         CompletionContext::AfterDate => Ok(Some(complete_directive_keywords()?)),
 
         CompletionContext::AfterFlag => {
-            Ok(Some(complete_payee(data, "", content, position, false)?))
+            let mut items = complete_payee(data, "", content, position, false)?;
+            items.extend(complete_transaction_template(
+                data,
+                &posting_indent(snapshot),
+                "",
+                content,
+                position,
+                false,
+            )?);
+            Ok(Some(items))
         }
 
         CompletionContext::AfterPayee => Ok(Some(complete_narration(
             data, "", content, position, false,
         )?)),
 
-        CompletionContext::PostingAccount { prefix } => {
-            Ok(Some(complete_account(data, prefix, content, position)?))
-        }
+        CompletionContext::PostingAccount { prefix } => Ok(Some(complete_account(
+            data,
+            &AccountUsage::new(
+                data,
+                snapshot.beancount_data.get(path).and_then(|file| {
+                    transaction_at_line(file.transactions(), content, position.line)
+                }),
+                position.line,
+            ),
+            prefix,
+            content,
+            position,
+            &snapshot.cancellation,
+        )?)),
 
-        CompletionContext::PostingAmount => Ok(Some(complete_amount()?)),
+        CompletionContext::PostingAmount => Ok(Some(complete_amount(
+            data,
+            &snapshot.beancount_data,
+            path,
+            content,
+            position,
+        )?)),
 
         CompletionContext::PostingCurrency => Ok(Some(complete_currency(data, content, position)?)),
 
-        CompletionContext::OpenAccount { prefix } => {
-            Ok(Some(complete_account(data, prefix, content, position)?))
-        }
+        CompletionContext::OpenAccount { prefix } => Ok(Some(complete_account(
+            data,
+            &AccountUsage::default(),
+            prefix,
+            content,
+            position,
+            &snapshot.cancellation,
+        )?)),
 
         CompletionContext::OpenCurrency => Ok(Some(complete_currency(data, content, position)?)),
 
-        CompletionContext::BalanceAccount { prefix } => {
-            Ok(Some(complete_account(data, prefix, content, position)?))
-        }
+        CompletionContext::BalanceAccount { prefix } => Ok(Some(complete_account(
+            data,
+            &AccountUsage::default(),
+            prefix,
+            content,
+            position,
+            &snapshot.cancellation,
+        )?)),
 
         CompletionContext::PriceContext => Ok(Some(complete_currency(data, content, position)?)),
 
@@ -642,13 +684,17 @@ fn generate_completions(
             has_closing_quote,
         } => {
             if *is_payee {
-                Ok(Some(complete_payee(
+                let mut items =
+                    complete_payee(data, prefix, content, position, *has_closing_quote)?;
+                items.extend(complete_transaction_template(
                     data,
+                    &posting_indent(snapshot),
                     prefix,
                     content,
                     position,
                     *has_closing_quote,
-                )?))
+                )?);
+                Ok(Some(items))
             } else {
                 Ok(Some(complete_narration(
                     data,
@@ -756,23 +802,27 @@ fn complete_date(content: &ropey::Rope, position: Position) -> Result<Vec<Comple
 
 /// Complete account names with fuzzy matching and InsertReplaceEdit
 fn complete_account(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
+    data: &WorkspaceIndex,
+    usage: &AccountUsage,
     prefix: &str,
     content: &ropey::Rope,
     position: Position,
+    cancellation: &CancellationToken,
 ) -> Result<Vec<CompletionItem>> {
-    let mut all_accounts: Vec<String> = Vec::new();
+    // Most used accounts first
+    let all_accounts: Vec<String> = data
+        .declared_accounts()
+        .into_iter()
+        .map(|account| account.to_string())
+        .collect();
 
-    for bean_data in data.values() {
-        all_accounts.extend(bean_data.get_accounts().into_iter());
+    // Fuzzy search, then rank equally good matches by how the accounts were used
+    let mut matches = fuzzy_search_accounts(&all_accounts, prefix);
+    for (account, score) in &mut matches {
+        cancellation.check()?;
+        *score += usage.boost(account);
     }
-
-    // Remove duplicates
-    all_accounts.sort();
-    all_accounts.dedup();
-
-    // Fuzzy search
-    let matches = fuzzy_search_accounts(&all_accounts, prefix);
+    matches.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
 
     // Calculate ranges for InsertReplaceEdit
     let line = content.line(position.line as usize).to_string();
@@ -795,28 +845,118 @@ fn complete_account(
         .collect())
 }
 
+// Largest boosts from sharing the payee, sharing accounts and recency of use.
+// Together they stay below 100, the smallest gap between two match tiers of
+// `score_account` (exact-case and case-insensitive prefix matches).
+const PAYEE_WEIGHT: f32 = 45.0;
+const CO_POSTING_WEIGHT: f32 = 30.0;
+const RECENCY_WEIGHT: f32 = 15.0;
+
+/// Days after which an account no longer counts as recently used.
+const RECENCY_DAYS: f32 = 365.0;
+
+/// How other transactions used each account, relative to the transaction
+/// being edited.
+#[derive(Debug, Default)]
+struct AccountUsage<'a> {
+    /// Transactions with the same payee and the accounts they used.
+    payee: AccountCounts,
+    /// Highest share, among the accounts already in the transaction, of the
+    /// transactions using it that also used the account.
+    co_postings: HashMap<String, f32>,
+    /// Date each account was last used.
+    last_used: HashMap<&'a str, chrono::NaiveDate>,
+    /// Date of the latest transaction in the workspace.
+    latest: Option<chrono::NaiveDate>,
+}
+
+impl<'a> AccountUsage<'a> {
+    /// Look up the usage of accounts by the transactions other than `current`,
+    /// whose posting on `line` is being edited.
+    fn new(data: &'a WorkspaceIndex, current: Option<&TransactionData>, line: u32) -> Self {
+        let last_used = data.last_used();
+        let latest = last_used.values().max().copied();
+        let Some(current) = current else {
+            return Self {
+                last_used,
+                latest,
+                ..Default::default()
+            };
+        };
+
+        // The index counts the transaction being edited as well
+        let accounts: HashSet<&str> = current
+            .postings
+            .iter()
+            .map(|posting| posting.account.as_str())
+            .collect();
+        let present: HashSet<&str> = current
+            .postings
+            .iter()
+            .filter(|posting| posting.line != line)
+            .map(|posting| posting.account.as_str())
+            .collect();
+
+        let payee = match &current.payee {
+            Some((payee, _)) => {
+                let mut counts = data.payee_accounts(payee);
+                counts.remove(accounts.iter().copied());
+                counts
+            }
+            None => AccountCounts::default(),
+        };
+
+        let mut co_postings: HashMap<String, f32> = HashMap::new();
+        for account in &present {
+            let mut counts = data.co_postings(account);
+            counts.remove(accounts.iter().copied().filter(|other| other != account));
+            for other in counts.accounts.keys() {
+                if !present.contains(other.as_str()) {
+                    let share = co_postings.entry(other.clone()).or_default();
+                    *share = share.max(counts.share(other));
+                }
+            }
+        }
+
+        Self {
+            payee,
+            co_postings,
+            last_used,
+            latest,
+        }
+    }
+
+    /// Score added to the match score of `account`.
+    fn boost(&self, account: &str) -> f32 {
+        let recency = match (self.last_used.get(account), self.latest) {
+            (Some(used), Some(latest)) => {
+                (1.0 - (latest - *used).num_days() as f32 / RECENCY_DAYS).max(0.0)
+            }
+            _ => 0.0,
+        };
+        PAYEE_WEIGHT * self.payee.share(account)
+            + CO_POSTING_WEIGHT * self.co_postings.get(account).copied().unwrap_or(0.0)
+            + RECENCY_WEIGHT * recency
+    }
+}
+
 /// Complete sub-accounts when colon is typed (e.g., "Assets:" shows "Checking", "Savings")
-fn complete_subaccounts(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
-    parent_path: &str,
-) -> Result<Vec<CompletionItem>> {
+fn complete_subaccounts(data: &WorkspaceIndex, parent_path: &str) -> Result<Vec<CompletionItem>> {
     let mut subaccounts: Vec<String> = Vec::new();
 
-    for bean_data in data.values() {
-        for account in bean_data.get_accounts() {
-            if let Some(suffix) = account.strip_prefix(parent_path) {
-                let suffix = suffix.strip_prefix(':').unwrap_or(suffix);
+    for account in data.declared_accounts() {
+        if let Some(suffix) = account.strip_prefix(parent_path) {
+            let suffix = suffix.strip_prefix(':').unwrap_or(suffix);
 
-                // Extract only the next segment
-                let next_segment = if let Some(colon_pos) = suffix.find(':') {
-                    &suffix[..colon_pos]
-                } else {
-                    suffix
-                };
+            // Extract only the next segment
+            let next_segment = if let Some(colon_pos) = suffix.find(':') {
+                &suffix[..colon_pos]
+            } else {
+                suffix
+            };
 
-                if !next_segment.is_empty() {
-                    subaccounts.push(next_segment.to_string());
-                }
+            if !next_segment.is_empty() {
+                subaccounts.push(next_segment.to_string());
             }
         }
     }
@@ -840,17 +980,16 @@ fn complete_subaccounts(
 
 /// Complete currency codes
 fn complete_currency(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
+    data: &WorkspaceIndex,
     content: &ropey::Rope,
     position: Position,
 ) -> Result<Vec<CompletionItem>> {
     // Collect commodities from all beancount files
-    let mut commodities_set: HashSet<String> = HashSet::new();
-    for bean_data in data.values() {
-        for commodity in bean_data.get_commodities() {
-            commodities_set.insert(commodity);
-        }
-    }
+    let commodities_set: HashSet<String> = data
+        .names(SymbolKind::Currency)
+        .into_iter()
+        .map(|(commodity, _)| commodity.to_string())
+        .collect();
 
     // If no commodities found in the files, fall back to common currencies
     let fallback_currencies = vec![
@@ -885,14 +1024,149 @@ fn complete_currency(
         .collect())
 }
 
-/// Complete amount suggestions
-fn complete_amount() -> Result<Vec<CompletionItem>> {
-    Ok(vec![])
+/// Most amounts suggested from earlier transactions with the same payee.
+const MAX_PREVIOUS_AMOUNTS: usize = 5;
+
+/// Complete the amount of the posting being edited.
+///
+/// Suggests, in order, the amount balancing the transaction, the amount of
+/// this account in the latest transaction the one being edited repeats, and
+/// other amounts recently used with the same account and payee.
+fn complete_amount(
+    data: &WorkspaceIndex,
+    beancount_data: &HashMap<PathBuf, Arc<BeancountData>>,
+    path: &Path,
+    content: &ropey::Rope,
+    position: Position,
+) -> Result<Vec<CompletionItem>> {
+    let line = position.line;
+    let Some(txn) = beancount_data
+        .get(path)
+        .and_then(|data| transaction_at_line(data.transactions(), content, line))
+    else {
+        return Ok(vec![]);
+    };
+    let line_text = content.line(line as usize).to_string();
+    let Some(account) = txn
+        .postings
+        .iter()
+        .find(|posting| posting.line == line)
+        .map(|posting| posting.account.as_str())
+    else {
+        return Ok(vec![]);
+    };
+    let other_postings = || txn.postings.iter().filter(|posting| posting.line != line);
+
+    let mut suggestions: Vec<(Amount, String)> = Vec::new();
+
+    let mut residual = Inventory::new();
+    for weight in other_postings().filter_map(|posting| posting.weight()) {
+        residual.add_amount(&weight);
+    }
+    for amount in residual.amounts() {
+        suggestions.push((
+            Amount::new(-amount.number, amount.currency),
+            "Balances the transaction".to_string(),
+        ));
+    }
+    let limit = suggestions.len() + MAX_PREVIOUS_AMOUNTS;
+
+    if let Some((payee, _)) = &txn.payee {
+        // Earlier transactions with the same payee, latest first
+        let mut previous: Vec<&TransactionData> = data
+            .files_using(beancount_data, SymbolKind::Payee, payee)
+            .flat_map(|(file, data)| {
+                data.transactions()
+                    .filter(move |other| !(file == path && other.line == txn.line))
+            })
+            .filter(|other| other.payee.as_ref().is_some_and(|(name, _)| name == payee))
+            .collect();
+        previous.sort_by(|a, b| b.date.cmp(&a.date));
+
+        // The transaction being repeated has all the accounts entered so far
+        let accounts: HashSet<&str> = other_postings()
+            .map(|posting| posting.account.as_str())
+            .chain([account])
+            .collect();
+        let repeated = previous.iter().position(|other| {
+            accounts.iter().all(|account| {
+                other
+                    .postings
+                    .iter()
+                    .any(|posting| posting.account == *account)
+            })
+        });
+
+        let date = |txn: &TransactionData| {
+            txn.date
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+        let amounts = |other: &&TransactionData| {
+            other
+                .postings
+                .iter()
+                .filter(|posting| posting.account == account)
+                .filter_map(|posting| posting.units.clone())
+                .collect::<Vec<_>>()
+        };
+        if let Some(repeated) = repeated.map(|index| previous[index]) {
+            for amount in amounts(&repeated) {
+                suggestions.push((amount, format!("As on {}", date(repeated))));
+            }
+        }
+        for other in &previous {
+            for amount in amounts(other) {
+                suggestions.push((amount, format!("Used with {payee} on {}", date(other))));
+            }
+        }
+    }
+
+    let (insert_range, replace_range) = calculate_word_ranges(&line_text, position);
+    let mut seen = HashSet::new();
+    Ok(suggestions
+        .into_iter()
+        .filter(|(amount, _)| seen.insert(amount.to_string()))
+        .take(limit)
+        .enumerate()
+        .map(|(rank, (amount, detail))| {
+            create_completion_with_insert_replace(
+                amount.to_string(),
+                detail,
+                CompletionItemKind::VALUE,
+                insert_range,
+                replace_range,
+                100.0 - rank as f32,
+                vec![],
+            )
+        })
+        .collect())
+}
+
+/// The transaction whose postings include `line`.
+///
+/// While a posting is typed the transaction may not have parsed it yet, so
+/// any indented line below the header counts as part of it.
+fn transaction_at_line<'a>(
+    transactions: impl Iterator<Item = &'a TransactionData>,
+    content: &ropey::Rope,
+    line: u32,
+) -> Option<&'a TransactionData> {
+    let txn = transactions
+        .filter(|txn| txn.line < line)
+        .max_by_key(|txn| txn.line)?;
+    let indented = |row: u32| {
+        content
+            .get_line(row as usize)
+            .and_then(|text| text.chars().next())
+            .is_some_and(|c| c == ' ' || c == '\t')
+    };
+    (txn.line + 1..=line).all(indented).then_some(txn)
 }
 
 /// Complete payee names
 fn complete_payee(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
+    data: &WorkspaceIndex,
     prefix: &str,
     content: &ropey::Rope,
     position: Position,
@@ -900,12 +1174,10 @@ fn complete_payee(
 ) -> Result<Vec<CompletionItem>> {
     let mut payees: Vec<String> = Vec::new();
 
-    for bean_data in data.values() {
-        for payee in bean_data.get_payees() {
-            let clean = payee.trim_matches('"');
-            if !clean.is_empty() {
-                payees.push(clean.to_string());
-            }
+    for (payee, _) in data.names(SymbolKind::Payee) {
+        let clean = payee.trim_matches('"');
+        if !clean.is_empty() {
+            payees.push(clean.to_string());
         }
     }
 
@@ -940,9 +1212,129 @@ fn complete_payee(
         .collect())
 }
 
+/// Indentation of the postings inserted by completions.
+fn posting_indent(snapshot: &LspServerStateSnapshot) -> String {
+    " ".repeat(snapshot.config.formatting.indent_width.unwrap_or(2))
+}
+
+/// Complete a payee with the rest of the latest transaction using it.
+///
+/// The narration, tags, links, metadata and postings are copied, with tab
+/// stops on the narration and the amounts.
+fn complete_transaction_template(
+    data: &WorkspaceIndex,
+    indent: &str,
+    prefix: &str,
+    content: &ropey::Rope,
+    position: Position,
+    has_closing_quote: bool,
+) -> Result<Vec<CompletionItem>> {
+    let line = content.line(position.line as usize).to_string();
+    let (insert_range, mut replace_range) =
+        calculate_string_ranges(&line, position, has_closing_quote);
+
+    // The template replaces the closing quote, so it only fits at the end of the line
+    let after_payee = line
+        .chars()
+        .skip(replace_range.end.character as usize + has_closing_quote as usize);
+    if !after_payee.collect::<String>().trim().is_empty() {
+        return Ok(vec![]);
+    }
+    if has_closing_quote {
+        replace_range.end.character += 1;
+    }
+
+    let latest: HashMap<String, &TransactionData> = data
+        .latest_transactions()
+        .into_iter()
+        .map(|(payee, txn)| (payee.trim_matches('"').to_string(), txn))
+        .filter(|(payee, _)| !payee.is_empty())
+        .collect();
+
+    let mut payees: Vec<String> = latest.keys().cloned().collect();
+    payees.sort();
+
+    Ok(fuzzy_search_strings(&payees, prefix)
+        .into_iter()
+        .map(|(payee, score)| {
+            let txn = latest[&payee];
+            let date = txn
+                .date
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+            let mut item = create_completion_with_insert_replace(
+                payee.clone(),
+                format!("Transaction from {date}"),
+                CompletionItemKind::SNIPPET,
+                insert_range,
+                replace_range,
+                score,
+                vec![],
+            )
+            .with_insert_text(transaction_template(&payee, txn, indent));
+            item.label_details = Some(lsp_types::CompletionItemLabelDetails {
+                detail: None,
+                description: Some("template".to_string()),
+            });
+            item.insert_text_format = Some(lsp_types::InsertTextFormat::SNIPPET);
+            // List the template right after the plain payee
+            item.sort_text = item.sort_text.map(|sort_text| sort_text + "~");
+            item
+        })
+        .collect())
+}
+
+/// Snippet completing the payee string with the rest of `txn`, with its
+/// postings and metadata indented by `indent`.
+fn transaction_template(payee: &str, txn: &TransactionData, indent: &str) -> String {
+    let escape = |text: &str| {
+        text.replace('\\', "\\\\")
+            .replace('$', "\\$")
+            .replace('}', "\\}")
+    };
+    let mut stops = 0;
+    let mut tab_stop = |default: &str| {
+        stops += 1;
+        format!("${{{stops}:{}}}", escape(default))
+    };
+
+    let mut snippet = format!("{}\"", escape(payee));
+    if let Some(narration) = &txn.narration {
+        snippet.push_str(&format!(" \"{}\"", tab_stop(narration.trim_matches('"'))));
+    }
+    for tag_or_link in txn.tags.iter().chain(&txn.links) {
+        snippet.push_str(&format!(" {}", escape(tag_or_link)));
+    }
+    for (key, value) in &txn.metadata {
+        snippet.push_str(&format!("\n{indent}{}: {}", escape(key), escape(value)));
+    }
+    for posting in &txn.postings {
+        let text = &posting.text;
+        snippet.push_str(&format!("\n{indent}"));
+        match &posting.number_range {
+            Some(number) => snippet.push_str(&format!(
+                "{}{}{}",
+                escape(&text[..number.start]),
+                tab_stop(&text[number.clone()]),
+                escape(&text[number.end..])
+            )),
+            None => snippet.push_str(&escape(text)),
+        }
+        for (key, value) in &posting.metadata {
+            snippet.push_str(&format!(
+                "\n{indent}{indent}{}: {}",
+                escape(key),
+                escape(value)
+            ));
+        }
+    }
+    snippet.push_str("$0");
+    snippet
+}
+
 /// Complete narration strings
 fn complete_narration(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
+    data: &WorkspaceIndex,
     prefix: &str,
     content: &ropey::Rope,
     position: Position,
@@ -950,10 +1342,8 @@ fn complete_narration(
 ) -> Result<Vec<CompletionItem>> {
     let mut narrations: Vec<String> = Vec::new();
 
-    for bean_data in data.values() {
-        for narration in bean_data.get_narration() {
-            narrations.push(narration.trim_matches('"').to_string());
-        }
+    for (narration, _) in data.names(SymbolKind::Narration) {
+        narrations.push(narration.trim_matches('"').to_string());
     }
 
     narrations.sort();
@@ -988,20 +1378,12 @@ fn complete_narration(
 }
 
 /// Complete tags
-fn complete_tag(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
-    prefix: &str,
-) -> Result<Vec<CompletionItem>> {
-    let mut tags: Vec<String> = Vec::new();
-
-    for bean_data in data.values() {
-        tags.extend(
-            bean_data
-                .get_tags()
-                .into_iter()
-                .map(|t| t.trim_start_matches('#').to_string()),
-        );
-    }
+fn complete_tag(data: &WorkspaceIndex, prefix: &str) -> Result<Vec<CompletionItem>> {
+    let mut tags: Vec<String> = data
+        .names(SymbolKind::Tag)
+        .into_iter()
+        .map(|(t, _)| t.trim_start_matches('#').to_string())
+        .collect();
 
     tags.sort();
     tags.dedup();
@@ -1020,20 +1402,12 @@ fn complete_tag(
 }
 
 /// Complete links
-fn complete_link(
-    data: &HashMap<PathBuf, Arc<BeancountData>>,
-    prefix: &str,
-) -> Result<Vec<CompletionItem>> {
-    let mut links: Vec<String> = Vec::new();
-
-    for bean_data in data.values() {
-        links.extend(
-            bean_data
-                .get_links()
-                .into_iter()
-                .map(|l| l.trim_start_matches('^').to_string()),
-        );
-    }
+fn complete_link(data: &WorkspaceIndex, prefix: &str) -> Result<Vec<CompletionItem>> {
+    let mut links: Vec<String> = data
+        .names(SymbolKind::Link)
+        .into_iter()
+        .map(|(l, _)| l.trim_start_matches('^').to_string())
+        .collect();
 
     links.sort();
     links.dedup();
@@ -1232,7 +1606,7 @@ fn fuzzy_search_strings(strings: &[String], query: &str) -> Vec<(String, f32)> {
 }
 
 /// Score an account using tiered matching strategy
-fn score_account(account: &str, query: &str) -> f32 {
+pub(crate) fn score_account(account: &str, query: &str) -> f32 {
     let account_lower = account.to_lowercase();
     let query_lower = query.to_lowercase();
 
@@ -1349,6 +1723,8 @@ impl CompletionItemExt for CompletionItem {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::beancount_data::BeancountData;
+    use crate::workspace_index::WorkspaceIndex;
 
     #[test]
     fn test_extract_account_prefix() {
@@ -2063,7 +2439,14 @@ A"#;
             character: 14,
         };
 
-        let items = complete_payee(&data_map, "", &content, position, false).unwrap();
+        let items = complete_payee(
+            &WorkspaceIndex::new(&data_map),
+            "",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
 
         assert!(items.len() >= 3, "Should return all payees when no prefix");
 
@@ -2096,7 +2479,14 @@ A"#;
             character: 15,
         };
 
-        let items = complete_payee(&data_map, "K", &content, position, false).unwrap();
+        let items = complete_payee(
+            &WorkspaceIndex::new(&data_map),
+            "K",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
 
         // Should fuzzy match Kroger and King Soopers
         assert!(items.len() >= 2, "Should match payees starting with K");
@@ -2128,7 +2518,14 @@ A"#;
         };
 
         // No closing quote
-        let items = complete_payee(&data_map, "Kr", &content, position, false).unwrap();
+        let items = complete_payee(
+            &WorkspaceIndex::new(&data_map),
+            "Kr",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
         assert!(!items.is_empty());
 
         // Should add closing quote in insert_text
@@ -2163,7 +2560,14 @@ A"#;
         };
 
         // Has closing quote
-        let items = complete_payee(&data_map, "Kr", &content, position, true).unwrap();
+        let items = complete_payee(
+            &WorkspaceIndex::new(&data_map),
+            "Kr",
+            &content,
+            position,
+            true,
+        )
+        .unwrap();
         assert!(!items.is_empty());
 
         // Should NOT add closing quote
@@ -2199,7 +2603,14 @@ A"#;
             character: 14,
         };
 
-        let items = complete_payee(&data_map, "", &content, position, false).unwrap();
+        let items = complete_payee(
+            &WorkspaceIndex::new(&data_map),
+            "",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
 
         // Should deduplicate
         assert_eq!(items.len(), 1, "Should deduplicate payees");
@@ -2331,7 +2742,14 @@ A"#;
             character: 23,
         };
 
-        let items = complete_narration(&data_map, "", &content, position, false).unwrap();
+        let items = complete_narration(
+            &WorkspaceIndex::new(&data_map),
+            "",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
 
         assert!(
             items.len() >= 3,
@@ -2372,7 +2790,14 @@ A"#;
             character: 23, // Position at 'G'
         };
 
-        let items = complete_narration(&data_map, "G", &content, position, false).unwrap();
+        let items = complete_narration(
+            &WorkspaceIndex::new(&data_map),
+            "G",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
 
         // Should fuzzy match all items starting with G
         assert!(items.len() >= 3, "Should match narrations starting with G");
@@ -2405,7 +2830,14 @@ A"#;
         };
 
         // No closing quote
-        let items = complete_narration(&data_map, "Groc", &content, position, false).unwrap();
+        let items = complete_narration(
+            &WorkspaceIndex::new(&data_map),
+            "Groc",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
         assert!(!items.is_empty());
 
         // Should add closing quote in insert_text
@@ -2440,7 +2872,14 @@ A"#;
         };
 
         // Has closing quote
-        let items = complete_narration(&data_map, "Groc", &content, position, true).unwrap();
+        let items = complete_narration(
+            &WorkspaceIndex::new(&data_map),
+            "Groc",
+            &content,
+            position,
+            true,
+        )
+        .unwrap();
         assert!(!items.is_empty());
 
         // Should NOT add closing quote
@@ -2476,7 +2915,14 @@ A"#;
             character: 22, // Position inside empty narration string
         };
 
-        let items = complete_narration(&data_map, "", &content, position, false).unwrap();
+        let items = complete_narration(
+            &WorkspaceIndex::new(&data_map),
+            "",
+            &content,
+            position,
+            false,
+        )
+        .unwrap();
 
         // Should deduplicate
         assert_eq!(items.len(), 1, "Should deduplicate narrations");
@@ -2699,12 +3145,8 @@ A"#;
     #[test]
     fn test_integration_narration_completion_not_payee() {
         use lsp_types::{TextDocumentIdentifier, TextDocumentPositionParams};
-        use ropey::Rope;
-        use std::collections::HashMap;
         use std::path::PathBuf;
         use std::str::FromStr;
-        use std::sync::Arc;
-        use tree_sitter::Parser;
 
         // Create test data with distinct payees and narrations
         let test_data = r#"
@@ -2713,56 +3155,23 @@ A"#;
 2026-01-03 * "PayeeThree" "NarrationThree"
 "#;
 
-        // Parse the test data
-        let rope = Rope::from_str(test_data);
-        let mut parser = Parser::new();
-        parser
-            .set_language(&tree_sitter_beancount::language())
-            .unwrap();
-        let tree = parser.parse(test_data, None).unwrap();
-        let bean_data = BeancountData::new(&Arc::new(tree), &rope);
-
-        // Create snapshot with test data
-        let mut beancount_data: HashMap<PathBuf, Arc<BeancountData>> = HashMap::new();
-        let (path, uri) = if cfg!(windows) {
-            let path = PathBuf::from("C:\\test.bean");
-            let url = url::Url::from_file_path(&path).unwrap();
-            let uri = lsp_types::Uri::from_str(url.as_str()).unwrap();
-            (path, uri)
-        } else {
-            let path = PathBuf::from("/test.bean");
-            let url = url::Url::from_file_path(&path).unwrap();
-            let uri = lsp_types::Uri::from_str(url.as_str()).unwrap();
-            (path, uri)
-        };
-        beancount_data.insert(path.clone(), Arc::new(bean_data));
-
-        // Parse the document being edited - use partial narration to test completion
+        // The document being edited - use partial narration to test completion
         let edit_text = r#"2026-01-06 * "NewPayee" "Nar"#;
-        let edit_rope = Rope::from_str(edit_text);
-        let mut edit_parser = Parser::new();
-        edit_parser
-            .set_language(&tree_sitter_beancount::language())
-            .unwrap();
-        let edit_tree = edit_parser.parse(edit_text, None).unwrap();
-
-        let mut forest = HashMap::new();
-        forest.insert(path.clone(), Arc::new(edit_tree));
-
-        let mut open_docs = HashMap::new();
-        open_docs.insert(
-            path.clone(),
-            crate::document::Document {
-                content: edit_rope.clone(),
-            },
-        );
 
-        let snapshot = LspServerStateSnapshot {
-            beancount_data,
-            config: crate::config::Config::new(PathBuf::from("/test")),
-            forest,
-            open_docs,
+        let root = if cfg!(windows) {
+            PathBuf::from("C:\\")
+        } else {
+            PathBuf::from("/")
         };
+        let path = root.join("test.bean");
+        let url = url::Url::from_file_path(&path).unwrap();
+        let uri = lsp_types::Uri::from_str(url.as_str()).unwrap();
+
+        // Create snapshot with the test data in another file
+        let snapshot = LspServerStateSnapshot::from_files(
+            crate::config::Config::new(root.clone()),
+            &[(root.join("data.bean"), test_data), (path, edit_text)],
+        );
 
         // Cursor position inside second string after "Nar"
         // Text: '2026-01-06 * "NewPayee" "Nar"'
@@ -2817,4 +3226,294 @@ A"#;
             labels
         );
     }
+
+    /// (label, detail) of the completions at a position in a single file.
+    fn completions_at(text: &str, line: u32, character: u32) -> Vec<(String, String)> {
+        use std::str::FromStr;
+
+        let path = std::env::current_dir().unwrap().join("amounts.beancount");
+        let uri = url::Url::from_file_path(&path).unwrap();
+        let snapshot = LspServerStateSnapshot::from_files(
+            crate::config::Config::new(path.clone()),
+            &[(&path, text)],
+        );
+        let position = lsp_types::TextDocumentPositionParams {
+            text_document: lsp_types::TextDocumentIdentifier {
+                uri: lsp_types::Uri::from_str(uri.as_str()).unwrap(),
+            },
+            position: Position::new(line, character),
+        };
+
+        let mut items = completion(snapshot, None, position)
+            .unwrap()
+            .unwrap_or_default();
+        items.sort_by(|a, b| a.sort_text.cmp(&b.sort_text));
+        items
+            .into_iter()
+            .map(|item| (item.label, item.detail.unwrap_or_default()))
+            .collect()
+    }
+
+    const RECEIPTS: &str = r#"2024-01-05 * "Grocer" "Weekly shop"
+  Assets:Cash  -52.00 USD
+  Expenses:Food  40.00 USD
+  Expenses:Household  12.00 USD
+2024-01-12 * "Grocer" "Weekly shop"
+  Assets:Cash  -35.50 USD
+  Expenses:Household  35.50 USD
+2024-01-20 * "Bakery" "Bread"
+  Assets:Cash  -4.00 USD
+  Expenses:Household  4.00 USD
+2024-02-01 * "Grocer" "Split receipt"
+  Assets:Cash  -60.00 USD
+  Expenses:Food  41.00 USD
+  Expenses:Household 
+"#;
+
+    #[test]
+    fn test_amount_completion_suggests_residual_first() {
+        let items = completions_at(RECEIPTS, 13, 21);
+        assert_eq!(
+            items.first(),
+            Some(&(
+                "19.00 USD".to_string(),
+                "Balances the transaction".to_string()
+            )),
+            "{items:?}"
+        );
+    }
+
+    #[test]
+    fn test_amount_completion_suggests_repeated_and_recent_amounts() {
+        let items = completions_at(RECEIPTS, 13, 21);
+        let labels: Vec<&str> = items.iter().map(|(label, _)| label.as_str()).collect();
+        // The 2024-01-05 transaction also has Assets:Cash and Expenses:Food
+        assert_eq!(labels, vec!["19.00 USD", "12.00 USD", "35.50 USD"]);
+        assert_eq!(items[1].1, "As on 2024-01-05");
+        assert_eq!(items[2].1, "Used with \"Grocer\" on 2024-01-12");
+    }
+
+    #[test]
+    fn test_amount_completion_skips_posting_flag() {
+        let text = RECEIPTS.replace("  Expenses:Household \n", "  ! Expenses:Household \n");
+        let items = completions_at(&text, 13, 23);
+        let labels: Vec<&str> = items.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(labels, vec!["19.00 USD", "12.00 USD", "35.50 USD"]);
+    }
+
+    #[test]
+    fn test_amount_completion_needs_enclosing_transaction() {
+        let text = format!("{RECEIPTS}\n  Expenses:Food \n");
+        let rope = ropey::Rope::from_str(&text);
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(&text, None).unwrap();
+        let path = PathBuf::from("amounts.beancount");
+        let data = HashMap::from([(path.clone(), Arc::new(BeancountData::new(&tree, &rope)))]);
+
+        let items = complete_amount(
+            &WorkspaceIndex::new(&data),
+            &data,
+            &path,
+            &rope,
+            Position::new(15, 16),
+        )
+        .unwrap();
+        assert!(items.is_empty(), "{items:?}");
+        let items = complete_amount(
+            &WorkspaceIndex::new(&data),
+            &data,
+            &path,
+            &rope,
+            Position::new(13, 21),
+        )
+        .unwrap();
+        assert_eq!(items.len(), 3);
+    }
+
+    fn template_items(text: &str, line: u32, character: u32, closing: bool) -> Vec<CompletionItem> {
+        let rope = ropey::Rope::from_str(text);
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let data = HashMap::from([(
+            PathBuf::from("templates.beancount"),
+            Arc::new(BeancountData::new(&tree, &rope)),
+        )]);
+        let prefix =
+            extract_string_prefix(&rope.line(line as usize).to_string(), character as usize);
+        complete_transaction_template(
+            &WorkspaceIndex::new(&data),
+            "  ",
+            &prefix,
+            &rope,
+            Position::new(line, character),
+            closing,
+        )
+        .unwrap()
+    }
+
+    fn new_text(item: &CompletionItem) -> (&str, Range) {
+        match &item.text_edit {
+            Some(lsp_types::CompletionTextEdit::Edit(edit)) => (edit.new_text.as_str(), edit.range),
+            _ => panic!("Expected a text edit"),
+        }
+    }
+
+    const TEMPLATES: &str = r#"2024-01-05 * "Grocer" "Old shop"
+  Expenses:Food  10.00 USD
+  Assets:Cash
+2024-01-12 * "Grocer" "Weekly $hop" #food ^receipt
+  trip: "Lisbon"
+  Assets:Cash  -52.00 USD
+    receipt: "1234"
+  Expenses:Food  40.00 USD
+  Expenses:Household
+2024-02-01 * "Gro
+"#;
+
+    #[test]
+    fn test_payee_template_copies_latest_transaction() {
+        let items = template_items(TEMPLATES, 9, 17, false);
+        assert_eq!(items.len(), 1);
+        let item = &items[0];
+        assert_eq!(item.label, "Grocer");
+        assert_eq!(item.detail.as_deref(), Some("Transaction from 2024-01-12"));
+        assert_eq!(
+            item.insert_text_format,
+            Some(lsp_types::InsertTextFormat::SNIPPET)
+        );
+        let (text, range) = new_text(item);
+        assert_eq!(
+            text,
+            "Grocer\" \"${1:Weekly \\$hop}\" #food ^receipt\n  trip: \"Lisbon\"\n  Assets:Cash  ${2:-52.00} USD\n    receipt: \"1234\"\n  Expenses:Food  ${3:40.00} USD\n  Expenses:Household$0"
+        );
+        assert_eq!(
+            range,
+            Range::new(Position::new(9, 14), Position::new(9, 17))
+        );
+    }
+
+    #[test]
+    fn test_payee_template_keeps_flags_costs_and_prices() {
+        let text = r#"2024-03-01 * "Broker" "Buy"
+  ! Assets:Stock  10 AAPL {100.00 USD} @ 101 USD ; lot
+  Assets:Cash
+2024-03-05 * "Bro
+"#;
+        let rope = ropey::Rope::from_str(text);
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let data = HashMap::from([(
+            PathBuf::from("templates.beancount"),
+            Arc::new(BeancountData::new(&tree, &rope)),
+        )]);
+        let items = complete_transaction_template(
+            &WorkspaceIndex::new(&data),
+            "    ",
+            "Bro",
+            &rope,
+            Position::new(3, 17),
+            false,
+        )
+        .unwrap();
+        let (text, _) = new_text(&items[0]);
+        assert_eq!(
+            text,
+            "Broker\" \"${1:Buy}\"\n    ! Assets:Stock  ${2:10} AAPL {100.00 USD\\} @ 101 USD\n    Assets:Cash$0"
+        );
+    }
+
+    #[test]
+    fn test_payee_template_replaces_closing_quote_at_end_of_line() {
+        let text = TEMPLATES.replace("\"Gro\n", "\"Gro\"\n");
+        let items = template_items(&text, 9, 17, true);
+        let (_, range) = new_text(&items[0]);
+        assert_eq!(
+            range,
+            Range::new(Position::new(9, 14), Position::new(9, 18))
+        );
+
+        // A narration after the payee is kept, so no template is offered
+        let text = TEMPLATES.replace("\"Gro\n", "\"Gro\" \"Lunch\"\n");
+        assert!(template_items(&text, 9, 17, true).is_empty());
+    }
+
+    const ACCOUNT_HISTORY: &str = r#"2024-01-01 open Assets:Bank
+2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Food
+2024-01-01 open Expenses:Household
+2024-01-01 open Expenses:Rent
+2024-01-01 open Liabilities:Card
+2024-01-05 * "Landlord" "January"
+  Expenses:Rent  900 USD
+  Assets:Bank
+2024-01-06 * "Grocer" "Weekly"
+  Expenses:Food  40 USD
+  Liabilities:Card
+2024-03-06 * "Cafe" "Coffee"
+  Expenses:Household  4 USD
+  Assets:Cash
+"#;
+
+    /// Account labels in ranking order; accounts not matching the prefix come last.
+    fn account_labels(text: &str, line: u32, character: u32) -> Vec<String> {
+        completions_at(text, line, character)
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect()
+    }
+
+    #[test]
+    fn test_accounts_used_with_payee_and_co_postings_rank_first() {
+        let text = format!(
+            "{ACCOUNT_HISTORY}2024-03-07 * \"Grocer\" \"Weekly\"\n  Liabilities:Card  -12 USD\n  Ex\n"
+        );
+        assert_eq!(
+            account_labels(&text, 17, 4)[..3],
+            vec!["Expenses:Food", "Expenses:Household", "Expenses:Rent"]
+        );
+    }
+
+    #[test]
+    fn test_recently_used_accounts_rank_first() {
+        let text = format!("{ACCOUNT_HISTORY}2024-03-07 * \"New shop\"\n  Ex\n");
+        assert_eq!(
+            account_labels(&text, 16, 4)[..3],
+            vec!["Expenses:Household", "Expenses:Food", "Expenses:Rent"]
+        );
+    }
+
+    #[test]
+    fn test_usage_boost_does_not_beat_better_match() {
+        let text = format!(
+            "{ACCOUNT_HISTORY}2024-03-07 * \"Landlord\" \"March\"\n  Assets:Bank  -900 USD\n  Expenses:Food\n"
+        );
+        assert_eq!(account_labels(&text, 17, 15)[0], "Expenses:Food");
+    }
+
+    #[test]
+    fn test_usage_boost_does_not_beat_exact_case_prefix() {
+        let text = r#"2024-01-01 open Assets:Bank
+2024-01-01 open Expenses:HOA
+2024-01-01 open Expenses:Hobby
+2024-03-01 * "Condo" "Fee"
+  Expenses:HOA  200 USD
+  Assets:Bank
+2024-03-07 * "Condo" "Fee"
+  Assets:Bank  -200 USD
+  Expenses:Ho
+"#;
+        assert_eq!(
+            account_labels(text, 8, 13)[..2],
+            vec!["Expenses:Hobby", "Expenses:HOA"]
+        );
+    }
 }
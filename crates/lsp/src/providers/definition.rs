@@ -0,0 +1,268 @@
+use crate::beancount_data::SymbolKind;
+use crate::providers::references::node_at_position;
+use crate::providers::uri::file_path_to_uri;
+use crate::server::LspServerStateSnapshot;
+use crate::treesitter_utils::{lsp_position_to_point, text_for_tree_sitter_node};
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use lsp_types::Location;
+use std::path::Path;
+use tree_sitter_beancount::tree_sitter;
+
+/// Node kinds that can be resolved to a declaration.
+const DEFINITION_KINDS: &[&str] = &["account", "currency", "payee", "string"];
+
+/// Provider function for `textDocument/definition`.
+///
+/// Accounts resolve to their `open` directive, currencies to their `commodity`
+/// directive, payees to the earliest transaction using them, and `include`
+/// strings to the included files.
+pub(crate) fn definition(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::GotoDefinitionParams,
+) -> Result<Option<lsp_types::GotoDefinitionResponse>> {
+    let uri = params
+        .text_document_position_params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+    let position = params.text_document_position_params.position;
+
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&uri), snapshot.open_docs.get(&uri)) else {
+        return Ok(None);
+    };
+    let Some(node) = definition_node_at(tree, &doc.content, position) else {
+        return Ok(None);
+    };
+    let text = text_for_tree_sitter_node(&doc.content, &node);
+
+    let locations = match node.kind() {
+        "account" => account_definition(&snapshot, &text),
+        "currency" => commodity_definition(&snapshot, &text),
+        "payee" => payee_definition(&snapshot, &text),
+        "string" if node.parent().is_some_and(|p| p.kind() == "include") => {
+            include_definition(&uri, &text)
+        }
+        _ => vec![],
+    };
+
+    if locations.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(lsp_types::GotoDefinitionResponse::Array(locations)))
+}
+
+/// Find a node that can be resolved to a declaration at the cursor.
+fn definition_node_at<'a>(
+    tree: &'a tree_sitter::Tree,
+    content: &ropey::Rope,
+    position: lsp_types::Position,
+) -> Option<tree_sitter::Node<'a>> {
+    let point = lsp_position_to_point(content, position);
+    let exact = tree
+        .root_node()
+        .named_descendant_for_point_range(point, point);
+    [node_at_position(tree, content, position), exact]
+        .into_iter()
+        .flatten()
+        .find(|node| DEFINITION_KINDS.contains(&node.kind()))
+}
+
+fn account_definition(snapshot: &LspServerStateSnapshot, account: &str) -> Vec<Location> {
+    let mut locations: Vec<_> = snapshot
+        .index
+        .files_using(&snapshot.beancount_data, SymbolKind::Account, account)
+        .flat_map(|(path, bean_data)| {
+            bean_data
+                .opens()
+                .filter(|open| open.account == account)
+                .map(move |open| (open.date, path, open.range))
+        })
+        .collect();
+    locations.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    locations
+        .into_iter()
+        .filter_map(|(_, path, range)| Some(Location::new(file_path_to_uri(path)?, range)))
+        .collect()
+}
+
+fn commodity_definition(snapshot: &LspServerStateSnapshot, currency: &str) -> Vec<Location> {
+    let mut locations: Vec<_> = snapshot
+        .index
+        .files_using(&snapshot.beancount_data, SymbolKind::Currency, currency)
+        .flat_map(|(path, bean_data)| {
+            bean_data
+                .commodity_declarations()
+                .filter(|commodity| commodity.currency == currency)
+                .map(move |commodity| (commodity.date, path, commodity.range))
+        })
+        .collect();
+    locations.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    locations
+        .into_iter()
+        .filter_map(|(_, path, range)| Some(Location::new(file_path_to_uri(path)?, range)))
+        .collect()
+}
+
+/// Payees have no declaring directive, so the earliest transaction using it is the definition.
+fn payee_definition(snapshot: &LspServerStateSnapshot, payee: &str) -> Vec<Location> {
+    snapshot
+        .index
+        .files_using(&snapshot.beancount_data, SymbolKind::Payee, payee)
+        .flat_map(|(path, bean_data)| {
+            bean_data.transactions().filter_map(move |txn| {
+                let (name, range) = txn.payee.as_ref()?;
+                (name == payee).then_some((txn.date, path, range.start.line, *range))
+            })
+        })
+        .min_by(|a, b| (a.0, a.1, a.2).cmp(&(b.0, b.1, b.2)))
+        .and_then(|(_, path, _, range)| Some(Location::new(file_path_to_uri(path)?, range)))
+        .into_iter()
+        .collect()
+}
+
+/// Resolve an `include` string, relative to the including file, to the matching files.
+fn include_definition(current_file: &Path, include: &str) -> Vec<Location> {
+    let filename = include.trim_start_matches('"').trim_end_matches('"');
+    let path = Path::new(filename);
+    let resolved = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        match current_file.parent() {
+            Some(parent) => parent.join(path),
+            None => return vec![],
+        }
+    };
+
+    let Ok(paths) = glob::glob(&resolved.to_string_lossy()) else {
+        return vec![];
+    };
+    paths
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .filter_map(|path| Some(Location::new(file_path_to_uri(&path)?, Default::default())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    struct TestState {
+        snapshot: LspServerStateSnapshot,
+        uri: lsp_types::Uri,
+    }
+
+    impl TestState {
+        /// Build a snapshot from `(file name, text)` pairs; the first file is the one queried.
+        fn new(dir: &Path, files: &[(&str, &str)]) -> Self {
+            let files: Vec<_> = files
+                .iter()
+                .map(|(name, text)| {
+                    let path = dir.join(name);
+                    std::fs::write(&path, text).unwrap();
+                    (path, *text)
+                })
+                .collect();
+            Self {
+                snapshot: LspServerStateSnapshot::from_files(
+                    Config::new(dir.to_path_buf()),
+                    &files,
+                ),
+                uri: file_path_to_uri(&files[0].0).unwrap(),
+            }
+        }
+
+        fn definition(&self, line: u32, character: u32) -> Vec<Location> {
+            let params = lsp_types::GotoDefinitionParams {
+                text_document_position_params: lsp_types::TextDocumentPositionParams {
+                    text_document: lsp_types::TextDocumentIdentifier {
+                        uri: self.uri.clone(),
+                    },
+                    position: lsp_types::Position { line, character },
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            match definition(self.snapshot.clone(), params).unwrap() {
+                Some(lsp_types::GotoDefinitionResponse::Array(locations)) => locations,
+                Some(other) => panic!("Unexpected response: {other:?}"),
+                None => vec![],
+            }
+        }
+    }
+
+    const MAIN: &str = r#"include "accounts.beancount"
+2024-01-02 * "Grocer" "Food"
+  Expenses:Food  10 USD
+  Assets:Cash
+2024-01-01 * "Grocer" "Earlier"
+  Expenses:Food  5 USD
+  Assets:Cash
+"#;
+
+    const ACCOUNTS: &str = r#"2020-01-01 commodity USD
+2020-01-01 open Assets:Cash USD
+2020-01-01 open Expenses:Food
+"#;
+
+    fn uri_ends_with(location: &Location, name: &str) -> bool {
+        location.uri.as_str().ends_with(name)
+    }
+
+    #[test]
+    fn test_account_jumps_to_open_directive() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = TestState::new(
+            dir.path(),
+            &[("main.beancount", MAIN), ("accounts.beancount", ACCOUNTS)],
+        );
+        let locations = state.definition(2, 4);
+        assert_eq!(locations.len(), 1);
+        assert!(uri_ends_with(&locations[0], "accounts.beancount"));
+        assert_eq!(locations[0].range.start, lsp_types::Position::new(2, 16));
+    }
+
+    #[test]
+    fn test_currency_jumps_to_commodity_directive() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = TestState::new(
+            dir.path(),
+            &[("main.beancount", MAIN), ("accounts.beancount", ACCOUNTS)],
+        );
+        let locations = state.definition(2, 20);
+        assert_eq!(locations.len(), 1);
+        assert!(uri_ends_with(&locations[0], "accounts.beancount"));
+        assert_eq!(locations[0].range.start, lsp_types::Position::new(0, 21));
+    }
+
+    #[test]
+    fn test_payee_jumps_to_earliest_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = TestState::new(dir.path(), &[("main.beancount", MAIN)]);
+        let locations = state.definition(1, 15);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].range.start, lsp_types::Position::new(4, 13));
+    }
+
+    #[test]
+    fn test_include_opens_included_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = TestState::new(
+            dir.path(),
+            &[("main.beancount", MAIN), ("accounts.beancount", ACCOUNTS)],
+        );
+        let locations = state.definition(0, 12);
+        assert_eq!(locations.len(), 1);
+        assert!(uri_ends_with(&locations[0], "accounts.beancount"));
+    }
+
+    #[test]
+    fn test_unknown_account_has_no_definition() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = TestState::new(dir.path(), &[("main.beancount", MAIN)]);
+        assert!(state.definition(2, 4).is_empty());
+    }
+}
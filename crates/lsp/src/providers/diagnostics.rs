@@ -1,5 +1,8 @@
 use crate::beancount_data::BeancountData;
 use crate::checkers::{BeancountChecker, BeancountError, FlaggedEntry};
+use crate::providers::uri::file_path_to_uri;
+use crate::utils::ToFilePath;
+use anyhow::Result;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -7,16 +10,113 @@ use std::sync::Arc;
 use tempfile;
 use tracing::debug;
 
-/// Container for diagnostic data management.
-/// Currently unused but reserved for future caching and state management.
+/// Latest diagnostics per file, together with the result ids handed out to
+/// clients that pull diagnostics.
+///
+/// A file gets a new result id whenever its diagnostics change, so pull
+/// requests carrying the current id can be answered with `Unchanged`.
 pub struct DiagnosticData {
-    // Future: store current diagnostics for efficient incremental updates
+    files: HashMap<PathBuf, FileDiagnostics>,
+    next_result_id: u64,
+    /// Whether documents changed since the last check started.
+    stale: bool,
+}
+
+struct FileDiagnostics {
+    result_id: String,
+    diagnostics: Vec<lsp_types::Diagnostic>,
 }
 
+/// Result id of files that never had any diagnostics.
+const INITIAL_RESULT_ID: &str = "0";
+
 impl DiagnosticData {
-    /// Creates a new diagnostic data container.
+    /// Creates an empty diagnostic cache.
     pub fn new() -> Self {
-        Self {}
+        Self {
+            files: HashMap::new(),
+            next_result_id: 1,
+            stale: true,
+        }
+    }
+
+    /// Record that documents changed, so the cached diagnostics may be outdated.
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// Record that a check of the current documents started.
+    pub fn check_started(&mut self) {
+        self.stale = false;
+    }
+
+    /// Whether no check covers the current documents.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Store the result of a check and return the files whose diagnostics changed.
+    ///
+    /// Every checked file must be present in `diagnostics`, with an empty list
+    /// when it is clean, so that stale diagnostics get cleared.
+    pub fn update(
+        &mut self,
+        diagnostics: HashMap<PathBuf, Vec<lsp_types::Diagnostic>>,
+    ) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (file, diagnostics) in diagnostics {
+            let unchanged = match self.files.get(&file) {
+                Some(current) => current.diagnostics == diagnostics,
+                None => diagnostics.is_empty(),
+            };
+            if unchanged {
+                continue;
+            }
+            let result_id = self.next_result_id.to_string();
+            self.next_result_id += 1;
+            self.files.insert(
+                file.clone(),
+                FileDiagnostics {
+                    result_id,
+                    diagnostics,
+                },
+            );
+            changed.push(file);
+        }
+        changed.sort();
+        changed
+    }
+
+    /// Current result id of a file.
+    pub fn result_id(&self, file: &Path) -> &str {
+        self.files
+            .get(file)
+            .map_or(INITIAL_RESULT_ID, |entry| entry.result_id.as_str())
+    }
+
+    /// Current diagnostics of a file.
+    pub fn get(&self, file: &Path) -> &[lsp_types::Diagnostic] {
+        self.files
+            .get(file)
+            .map_or(&[], |entry| entry.diagnostics.as_slice())
+    }
+
+    /// Files that currently have, or previously had, diagnostics.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.keys()
+    }
+
+    /// Full or unchanged report for a file, depending on the id the client last saw.
+    fn report(&self, file: &Path, previous_result_id: Option<&str>) -> FileReport {
+        let result_id = self.result_id(file).to_string();
+        if previous_result_id == Some(result_id.as_str()) {
+            FileReport::Unchanged(lsp_types::UnchangedDocumentDiagnosticReport { result_id })
+        } else {
+            FileReport::Full(lsp_types::FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: self.get(file).to_vec(),
+            })
+        }
     }
 }
 
@@ -26,6 +126,103 @@ impl Default for DiagnosticData {
     }
 }
 
+enum FileReport {
+    Full(lsp_types::FullDocumentDiagnosticReport),
+    Unchanged(lsp_types::UnchangedDocumentDiagnosticReport),
+}
+
+/// Provider function for LSP `textDocument/diagnostic`.
+///
+/// Answers from the cache filled by the last check; the client is asked to
+/// pull again through `workspace/diagnostic/refresh` when a check changes it.
+pub fn document_diagnostic(
+    diagnostic_data: &DiagnosticData,
+    params: lsp_types::DocumentDiagnosticParams,
+) -> Result<lsp_types::DocumentDiagnosticReportResult> {
+    let file = params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+
+    let report = match diagnostic_data.report(&file, params.previous_result_id.as_deref()) {
+        FileReport::Full(report) => lsp_types::DocumentDiagnosticReport::Full(
+            lsp_types::RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: report,
+            },
+        ),
+        FileReport::Unchanged(report) => lsp_types::DocumentDiagnosticReport::Unchanged(
+            lsp_types::RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report: report,
+            },
+        ),
+    };
+    Ok(lsp_types::DocumentDiagnosticReportResult::Report(report))
+}
+
+/// Provider function for LSP `workspace/diagnostic`.
+///
+/// Reports every file of the journal plus any file that had diagnostics.
+/// Returns `None` when the client already has the current report of every
+/// file, so that the request can be held open until diagnostics change.
+pub fn workspace_diagnostic<'a>(
+    diagnostic_data: &'a DiagnosticData,
+    journal_files: impl Iterator<Item = &'a PathBuf>,
+    params: &lsp_types::WorkspaceDiagnosticParams,
+) -> Result<Option<lsp_types::WorkspaceDiagnosticReportResult>> {
+    let previous: HashMap<PathBuf, &str> = params
+        .previous_result_ids
+        .iter()
+        .filter_map(|previous| Some((previous.uri.to_file_path().ok()?, previous.value.as_str())))
+        .collect();
+
+    let mut files: Vec<&PathBuf> = journal_files.chain(diagnostic_data.files()).collect();
+    files.sort();
+    files.dedup();
+
+    let items = files
+        .into_iter()
+        .filter_map(|file| {
+            let uri = file_path_to_uri(file)?;
+            Some(
+                match diagnostic_data.report(file, previous.get(file).copied()) {
+                    FileReport::Full(report) => lsp_types::WorkspaceDocumentDiagnosticReport::Full(
+                        lsp_types::WorkspaceFullDocumentDiagnosticReport {
+                            uri,
+                            version: None,
+                            full_document_diagnostic_report: report,
+                        },
+                    ),
+                    FileReport::Unchanged(report) => {
+                        lsp_types::WorkspaceDocumentDiagnosticReport::Unchanged(
+                            lsp_types::WorkspaceUnchangedDocumentDiagnosticReport {
+                                uri,
+                                version: None,
+                                unchanged_document_diagnostic_report: report,
+                            },
+                        )
+                    }
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let unchanged = items.iter().all(|item| {
+        matches!(
+            item,
+            lsp_types::WorkspaceDocumentDiagnosticReport::Unchanged(_)
+        )
+    });
+    if unchanged {
+        return Ok(None);
+    }
+    Ok(Some(lsp_types::WorkspaceDiagnosticReportResult::Report(
+        lsp_types::WorkspaceDiagnosticReport { items },
+    )))
+}
+
 /// Provider function for LSP `textDocument/publishDiagnostics`.
 ///
 /// This function collects diagnostics from two sources:
@@ -193,7 +390,7 @@ fn merge_flagged_entries_from_parsed_data(
     beancount_data: HashMap<PathBuf, Arc<BeancountData>>,
 ) {
     for (file_path, data) in beancount_data.iter() {
-        for flagged_entry in &data.flagged_entries {
+        for flagged_entry in data.flagged_entries() {
             let position = lsp_types::Position {
                 line: flagged_entry.line,
                 character: 0, // Start of line
@@ -560,4 +757,158 @@ mod tests {
             panic!("Regex should match line 0 error format");
         }
     }
+
+    fn error_at(line: u32, message: &str) -> lsp_types::Diagnostic {
+        lsp_types::Diagnostic {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(line, 0),
+                lsp_types::Position::new(line, 0),
+            ),
+            message: message.to_string(),
+            ..lsp_types::Diagnostic::default()
+        }
+    }
+
+    #[test]
+    fn test_diagnostic_data_only_reports_changed_files() {
+        let main = PathBuf::from("/ledger/main.beancount");
+        let other = PathBuf::from("/ledger/other.beancount");
+        let mut data = DiagnosticData::new();
+
+        let changed = data.update(HashMap::from([
+            (main.clone(), vec![error_at(1, "broken")]),
+            (other.clone(), vec![]),
+        ]));
+        assert_eq!(changed, vec![main.clone()]);
+        assert_eq!(data.result_id(&other), INITIAL_RESULT_ID);
+        let first_id = data.result_id(&main).to_string();
+
+        // Same result again changes nothing
+        let changed = data.update(HashMap::from([
+            (main.clone(), vec![error_at(1, "broken")]),
+            (other.clone(), vec![]),
+        ]));
+        assert!(changed.is_empty());
+        assert_eq!(data.result_id(&main), first_id);
+
+        // Fixing the file clears its diagnostics under a new result id
+        let changed = data.update(HashMap::from([(main.clone(), vec![])]));
+        assert_eq!(changed, vec![main.clone()]);
+        assert_ne!(data.result_id(&main), first_id);
+        assert!(data.get(&main).is_empty());
+    }
+
+    #[test]
+    fn test_document_diagnostic_returns_unchanged_for_current_result_id() {
+        let (_temp_dir, file_path) = create_temp_beancount_file("");
+        let uri = file_path_to_uri(&file_path).unwrap();
+        let mut data = DiagnosticData::new();
+        data.update(HashMap::from([(
+            file_path.clone(),
+            vec![error_at(0, "broken")],
+        )]));
+
+        let params = |previous_result_id: Option<String>| lsp_types::DocumentDiagnosticParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri.clone() },
+            identifier: None,
+            previous_result_id,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let result_id = match document_diagnostic(&data, params(None)).unwrap() {
+            lsp_types::DocumentDiagnosticReportResult::Report(
+                lsp_types::DocumentDiagnosticReport::Full(report),
+            ) => {
+                let report = report.full_document_diagnostic_report;
+                assert_eq!(report.items.len(), 1);
+                report.result_id.unwrap()
+            }
+            other => panic!("Expected a full report, got {other:?}"),
+        };
+
+        assert!(matches!(
+            document_diagnostic(&data, params(Some(result_id))).unwrap(),
+            lsp_types::DocumentDiagnosticReportResult::Report(
+                lsp_types::DocumentDiagnosticReport::Unchanged(_)
+            )
+        ));
+    }
+
+    #[test]
+    fn test_workspace_diagnostic_covers_all_journal_files() {
+        let (_temp_dir, main) = create_temp_beancount_file("");
+        let clean = main.with_file_name("clean.beancount");
+        let mut data = DiagnosticData::new();
+        data.update(HashMap::from([(main.clone(), vec![error_at(0, "broken")])]));
+
+        let params = lsp_types::WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: vec![lsp_types::PreviousResultId {
+                uri: file_path_to_uri(&clean).unwrap(),
+                value: INITIAL_RESULT_ID.to_string(),
+            }],
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let journal_files = [main.clone(), clean.clone()];
+        let Some(lsp_types::WorkspaceDiagnosticReportResult::Report(report)) =
+            workspace_diagnostic(&data, journal_files.iter(), &params).unwrap()
+        else {
+            panic!("Expected a full workspace report");
+        };
+
+        assert_eq!(report.items.len(), 2);
+        for item in report.items {
+            match item {
+                lsp_types::WorkspaceDocumentDiagnosticReport::Full(full) => {
+                    assert_eq!(Some(full.uri), file_path_to_uri(&main));
+                    assert_eq!(full.full_document_diagnostic_report.items.len(), 1);
+                }
+                lsp_types::WorkspaceDocumentDiagnosticReport::Unchanged(unchanged) => {
+                    assert_eq!(Some(unchanged.uri), file_path_to_uri(&clean));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_workspace_diagnostic_is_held_until_something_changed() {
+        let (_temp_dir, main) = create_temp_beancount_file("");
+        let mut data = DiagnosticData::new();
+        data.update(HashMap::from([(main.clone(), vec![error_at(0, "broken")])]));
+
+        let params = lsp_types::WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: vec![lsp_types::PreviousResultId {
+                uri: file_path_to_uri(&main).unwrap(),
+                value: data.result_id(&main).to_string(),
+            }],
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let journal_files = [main.clone()];
+        assert!(
+            workspace_diagnostic(&data, journal_files.iter(), &params)
+                .unwrap()
+                .is_none()
+        );
+
+        data.update(HashMap::from([(main.clone(), vec![])]));
+        assert!(
+            workspace_diagnostic(&data, journal_files.iter(), &params)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_are_stale_until_a_check_starts() {
+        let mut data = DiagnosticData::new();
+        assert!(data.is_stale());
+        data.check_started();
+        assert!(!data.is_stale());
+        data.mark_stale();
+        assert!(data.is_stale());
+    }
 }
@@ -0,0 +1,274 @@
+use crate::server::LspServerStateSnapshot;
+use crate::treesitter_utils::{lsp_range_for_node, text_for_tree_sitter_node};
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use lsp_types::{DocumentSymbol, SymbolKind};
+use tree_sitter_beancount::tree_sitter;
+
+/// Provider function for LSP `textDocument/documentSymbol`.
+///
+/// Returns a hierarchical outline: org-mode style sections contain their
+/// entries, and transactions contain their postings.
+pub(crate) fn document_symbol(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::DocumentSymbolParams,
+) -> Result<Option<lsp_types::DocumentSymbolResponse>> {
+    let uri = params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&uri), snapshot.open_docs.get(&uri)) else {
+        return Ok(None);
+    };
+
+    let symbols = child_symbols(tree.root_node(), &doc.content);
+    Ok(Some(lsp_types::DocumentSymbolResponse::Nested(symbols)))
+}
+
+/// Symbols for the named children of a `file` or `section` node.
+fn child_symbols(node: tree_sitter::Node, content: &ropey::Rope) -> Vec<DocumentSymbol> {
+    let mut cursor = node.walk();
+    node.named_children(&mut cursor)
+        .filter_map(|child| node_symbol(child, content))
+        .collect()
+}
+
+fn node_symbol(node: tree_sitter::Node, content: &ropey::Rope) -> Option<DocumentSymbol> {
+    let text = |field: &str| {
+        node.child_by_field_name(field)
+            .map(|child| text_for_tree_sitter_node(content, &child))
+    };
+    let unquoted = |field: &str| text(field).map(|t| t.trim_matches('"').to_string());
+    let date = text("date").unwrap_or_default();
+
+    let (name, kind, selection, children) = match node.kind() {
+        "section" => {
+            let headline = node.child_by_field_name("headline")?;
+            let title = headline
+                .child_by_field_name("item")
+                .map(|item| text_for_tree_sitter_node(content, &item))
+                .unwrap_or_default();
+            (
+                title.trim().to_string(),
+                SymbolKind::NAMESPACE,
+                headline,
+                child_symbols(node, content),
+            )
+        }
+        "transaction" => {
+            let mut cursor = node.walk();
+            let postings = node
+                .named_children(&mut cursor)
+                .filter(|c| c.kind() == "posting")
+                .filter_map(|posting| posting_symbol(posting, content))
+                .collect();
+            (
+                join_non_blank([Some(date), unquoted("payee"), unquoted("narration")], " "),
+                SymbolKind::STRUCT,
+                node.child_by_field_name("date")?,
+                postings,
+            )
+        }
+        "open" | "close" | "pad" => (
+            format!("{date} {} {}", node.kind(), text("account")?),
+            SymbolKind::CLASS,
+            node.child_by_field_name("account")?,
+            vec![],
+        ),
+        "balance" => (
+            format!("{date} balance {} {}", text("account")?, text("amount")?),
+            SymbolKind::NUMBER,
+            node.child_by_field_name("account")?,
+            vec![],
+        ),
+        "price" => (
+            format!("{date} price {} {}", text("currency")?, text("amount")?),
+            SymbolKind::CONSTANT,
+            node.child_by_field_name("currency")?,
+            vec![],
+        ),
+        "event" => (
+            join_non_blank(
+                [
+                    Some(date),
+                    Some("event".to_string()),
+                    Some(join_non_blank([unquoted("type"), unquoted("desc")], ": ")),
+                ],
+                " ",
+            ),
+            SymbolKind::EVENT,
+            node.child_by_field_name("type")?,
+            vec![],
+        ),
+        _ => return None,
+    };
+    // Blank headlines and descriptions fall back to the directive kind
+    let name = if name.is_empty() {
+        node.kind().to_string()
+    } else {
+        name
+    };
+
+    #[allow(deprecated)]
+    Some(DocumentSymbol {
+        name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: lsp_range_for_node(content, &node),
+        selection_range: lsp_range_for_node(content, &selection),
+        children: if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        },
+    })
+}
+
+/// Join the parts that are not blank with `separator`.
+fn join_non_blank<const N: usize>(parts: [Option<String>; N], separator: &str) -> String {
+    parts
+        .into_iter()
+        .flatten()
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn posting_symbol(node: tree_sitter::Node, content: &ropey::Rope) -> Option<DocumentSymbol> {
+    let account = node.child_by_field_name("account")?;
+    let detail = node
+        .child_by_field_name("amount")
+        .map(|amount| text_for_tree_sitter_node(content, &amount));
+
+    #[allow(deprecated)]
+    Some(DocumentSymbol {
+        name: text_for_tree_sitter_node(content, &account),
+        detail,
+        kind: SymbolKind::FIELD,
+        tags: None,
+        deprecated: None,
+        range: lsp_range_for_node(content, &node),
+        selection_range: lsp_range_for_node(content, &account),
+        children: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(text: &str) -> Vec<DocumentSymbol> {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        child_symbols(tree.root_node(), &ropey::Rope::from_str(text))
+    }
+
+    #[test]
+    fn test_directives_and_transactions() {
+        let symbols = symbols(
+            r#"2024-01-01 open Assets:Cash USD
+2024-01-02 * "Grocer" "Weekly shop"
+  Expenses:Food  20.00 USD
+  Assets:Cash
+2024-01-03 balance Assets:Cash -20.00 USD
+2024-01-03 price EUR 1.10 USD
+2024-01-04 event "location" "Berlin"
+2024-01-05 close Assets:Cash
+option "title" "Ledger"
+"#,
+        );
+
+        let names: Vec<_> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "2024-01-01 open Assets:Cash",
+                "2024-01-02 Grocer Weekly shop",
+                "2024-01-03 balance Assets:Cash -20.00 USD",
+                "2024-01-03 price EUR 1.10 USD",
+                "2024-01-04 event location: Berlin",
+                "2024-01-05 close Assets:Cash",
+            ]
+        );
+
+        let transaction = &symbols[1];
+        assert_eq!(transaction.kind, SymbolKind::STRUCT);
+        assert_eq!(transaction.range.start.line, 1);
+        assert_eq!(transaction.range.end.line, 4);
+        let postings = transaction.children.as_ref().expect("postings");
+        assert_eq!(postings.len(), 2);
+        assert_eq!(postings[0].name, "Expenses:Food");
+        assert_eq!(postings[0].detail.as_deref(), Some("20.00 USD"));
+        assert_eq!(postings[1].detail, None);
+    }
+
+    #[test]
+    fn test_sections_nest_entries() {
+        let symbols = symbols(
+            r#"* Accounts
+2024-01-01 open Assets:Cash
+** Closed
+2024-12-31 close Assets:Cash
+* Transactions
+2024-01-02 * "Coffee"
+  Expenses:Food  3 USD
+  Assets:Cash
+"#,
+        );
+
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, "Accounts");
+        assert_eq!(symbols[0].kind, SymbolKind::NAMESPACE);
+
+        let accounts = symbols[0].children.as_ref().unwrap();
+        assert_eq!(accounts[0].name, "2024-01-01 open Assets:Cash");
+        assert_eq!(accounts[1].name, "Closed");
+        assert_eq!(
+            accounts[1].children.as_ref().unwrap()[0].name,
+            "2024-12-31 close Assets:Cash"
+        );
+
+        assert_eq!(symbols[1].name, "Transactions");
+        assert_eq!(
+            symbols[1].children.as_ref().unwrap()[0].name,
+            "2024-01-02 Coffee"
+        );
+    }
+
+    #[test]
+    fn test_blank_text_falls_back_to_date_or_kind() {
+        // A headline with nothing after the stars
+        let symbols = symbols(concat!(
+            "* \n",
+            r#"2024-01-02 * ""
+  Expenses:Food  3 USD
+  Assets:Cash
+2024-01-03 * "Shop" ""
+  Expenses:Food  3 USD
+  Assets:Cash
+2024-01-04 event "location" ""
+"#
+        ));
+
+        assert_eq!(symbols[0].name, "section");
+        let names: Vec<_> = symbols[0]
+            .children
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["2024-01-02", "2024-01-03 Shop", "2024-01-04 event location"]
+        );
+    }
+}
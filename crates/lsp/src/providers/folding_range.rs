@@ -0,0 +1,184 @@
+use crate::server::LspServerStateSnapshot;
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use lsp_types::{FoldingRange, FoldingRangeKind};
+use tree_sitter_beancount::tree_sitter;
+
+/// Provider function for LSP `textDocument/foldingRange`.
+///
+/// Folds org-mode sections, multi-line entries, posting metadata blocks and
+/// runs of consecutive comment lines.
+pub(crate) fn folding_range(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::FoldingRangeParams,
+) -> Result<Option<Vec<FoldingRange>>> {
+    let uri = params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+
+    let Some(tree) = snapshot.forest.get(&uri) else {
+        return Ok(None);
+    };
+
+    let mut ranges = Vec::new();
+    collect_ranges(tree.root_node(), &mut ranges);
+    ranges.sort_by_key(|range| (range.start_line, std::cmp::Reverse(range.end_line)));
+    Ok(Some(ranges))
+}
+
+/// Last line actually covered by a node; nodes ending at column 0 end on the previous line.
+fn last_line(node: &tree_sitter::Node) -> u32 {
+    let end = node.end_position();
+    if end.column == 0 && end.row > node.start_position().row {
+        (end.row - 1) as u32
+    } else {
+        end.row as u32
+    }
+}
+
+fn push_range(
+    ranges: &mut Vec<FoldingRange>,
+    start_line: u32,
+    end_line: u32,
+    kind: Option<FoldingRangeKind>,
+) {
+    if end_line > start_line {
+        ranges.push(FoldingRange {
+            start_line,
+            end_line,
+            kind,
+            ..Default::default()
+        });
+    }
+}
+
+/// Collect folds for the named children of a `file`, `section` or entry node.
+fn collect_ranges(node: tree_sitter::Node, ranges: &mut Vec<FoldingRange>) {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.named_children(&mut cursor).collect();
+
+    let mut index = 0;
+    while index < children.len() {
+        let child = children[index];
+        match child.kind() {
+            "section" => {
+                push_range(
+                    ranges,
+                    child.start_position().row as u32,
+                    last_line(&child),
+                    Some(FoldingRangeKind::Region),
+                );
+                collect_ranges(child, ranges);
+            }
+            "comment" => {
+                // Group comments on consecutive lines into a single block
+                let start_line = child.start_position().row as u32;
+                let mut end_line = last_line(&child);
+                while let Some(next) = children.get(index + 1)
+                    && next.kind() == "comment"
+                    && next.start_position().row as u32 == end_line + 1
+                {
+                    end_line = last_line(next);
+                    index += 1;
+                }
+                push_range(
+                    ranges,
+                    start_line,
+                    end_line,
+                    Some(FoldingRangeKind::Comment),
+                );
+            }
+            "posting" => {
+                // A posting followed by its metadata lines
+                let mut end_line = last_line(&child);
+                while let Some(next) = children.get(index + 1)
+                    && next.kind() == "key_value"
+                {
+                    end_line = last_line(next);
+                    index += 1;
+                }
+                push_range(ranges, child.start_position().row as u32, end_line, None);
+            }
+            "headline" | "key_value" => {}
+            _ if node.kind() == "file" || node.kind() == "section" => {
+                push_range(
+                    ranges,
+                    child.start_position().row as u32,
+                    last_line(&child),
+                    None,
+                );
+                collect_ranges(child, ranges);
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folds(text: &str) -> Vec<(u32, u32, Option<FoldingRangeKind>)> {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let mut ranges = Vec::new();
+        collect_ranges(tree.root_node(), &mut ranges);
+        ranges.sort_by_key(|range| (range.start_line, std::cmp::Reverse(range.end_line)));
+        ranges
+            .into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_transactions_metadata_and_comments() {
+        let text = r#"; Opening balances
+; imported from bank
+2024-01-01 open Assets:Cash
+  institution: "Bank"
+2024-01-02 * "Shop"
+  Expenses:Food  10 USD
+    receipt: "r-1"
+    category: "groceries"
+  Assets:Cash
+2024-01-03 close Assets:Cash
+"#;
+        assert_eq!(
+            folds(text),
+            vec![
+                (0, 1, Some(FoldingRangeKind::Comment)),
+                (2, 3, None),
+                (4, 8, None),
+                (5, 7, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sections_fold_with_their_entries() {
+        let text = r#"* Accounts
+2024-01-01 open Assets:Cash
+** Closed
+2024-12-31 close Assets:Cash
+* Transactions
+2024-01-02 * "Shop"
+  Expenses:Food  10 USD
+  Assets:Cash
+"#;
+        assert_eq!(
+            folds(text),
+            vec![
+                (0, 3, Some(FoldingRangeKind::Region)),
+                (2, 3, Some(FoldingRangeKind::Region)),
+                (4, 7, Some(FoldingRangeKind::Region)),
+                (5, 7, None),
+            ]
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use crate::config::FormattingConfig;
 use crate::server::LspServerStateSnapshot;
+use crate::treesitter_utils::{lsp_position_to_point, text_for_tree_sitter_node};
 use crate::utils::ToFilePath;
 use anyhow::Result;
+use std::sync::OnceLock;
 use tracing::debug;
 use tree_sitter::StreamingIterator;
 use tree_sitter_beancount::tree_sitter;
@@ -117,8 +120,18 @@ pub(crate) fn formatting(
         }
     };
 
+    format_node(&snapshot, doc, tree, tree.root_node()).map(Some)
+}
+
+/// Formatting edits for the lines of `node`, aligned with each other.
+fn format_node(
+    snapshot: &LspServerStateSnapshot,
+    doc: &crate::document::Document,
+    tree: &tree_sitter::Tree,
+    node: tree_sitter::Node,
+) -> Result<Vec<lsp_types::TextEdit>> {
     // Extract formateable lines using tree-sitter
-    let formateable_lines = match extract_formateable_lines(doc, tree) {
+    let mut formateable_lines = match extract_formateable_lines(doc, node) {
         Ok(lines) => {
             tracing::debug!("Extracted {} formateable lines", lines.len());
             lines
@@ -129,9 +142,17 @@ pub(crate) fn formatting(
         }
     };
 
-    if formateable_lines.is_empty() {
+    if formateable_lines.is_empty() && !snapshot.config.formatting.normalizes_entries() {
         tracing::debug!("No formateable lines found, returning empty edits");
-        return Ok(Some(vec![]));
+        return Ok(vec![]);
+    }
+    snapshot.cancellation.check()?;
+
+    if snapshot.config.formatting.normalizes_numbers() {
+        for line in &mut formateable_lines {
+            let currency = line.rest.split_whitespace().next().unwrap_or_default();
+            line.number = normalize_number(&line.number, currency, &snapshot.config.formatting);
+        }
     }
 
     // Calculate formatting configuration
@@ -150,18 +171,178 @@ pub(crate) fn formatting(
         )
     };
 
+    snapshot.cancellation.check()?;
+
     // Apply indent normalization to remaining lines if configured
     let final_text_edits = if let Some(indent_width) = snapshot.config.formatting.indent_width {
-        apply_indent_normalization_to_remaining_lines(doc, tree, indent_width, text_edits)?
+        let rows = if node.parent().is_none() {
+            0..doc.content.len_lines()
+        } else {
+            node.start_position().row..last_row(node) + 1
+        };
+        apply_indent_normalization_to_remaining_lines(doc, rows, indent_width, text_edits)?
     } else {
         text_edits
     };
 
+    // Rewrite flags, metadata and the order of entries on top of the line edits
+    let final_text_edits = if snapshot.config.formatting.normalizes_entries() {
+        snapshot.cancellation.check()?;
+        normalize_entries(doc, tree, &snapshot.config.formatting, final_text_edits)
+    } else {
+        final_text_edits
+    };
+
     debug!(
         "Generated {} text edits for formatting",
         final_text_edits.len()
     );
-    Ok(Some(final_text_edits))
+    Ok(final_text_edits)
+}
+
+/// Characters that trigger `textDocument/onTypeFormatting` besides Enter:
+/// the first letter of a currency typed after an amount.
+pub(crate) const ON_TYPE_TRIGGER_CHARACTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Provider function for LSP `textDocument/rangeFormatting`.
+///
+/// Aligns only the entries overlapping the range, using the widths of the
+/// whole document so they line up with the entries around them.
+pub(crate) fn range_formatting(
+    mut snapshot: LspServerStateSnapshot,
+    params: lsp_types::DocumentRangeFormattingParams,
+) -> Result<Option<Vec<lsp_types::TextEdit>>> {
+    keep_entries_in_place(&mut snapshot.config.formatting);
+    let Some((_, tree)) = get_document_and_tree(&snapshot, &params.text_document.uri) else {
+        return Ok(None);
+    };
+    let lines = entry_lines(tree, params.range.start.line, params.range.end.line);
+
+    let params = lsp_types::DocumentFormattingParams {
+        text_document: params.text_document,
+        options: params.options,
+        work_done_progress_params: params.work_done_progress_params,
+    };
+    Ok(formatting(snapshot, params)?.map(|edits| {
+        edits
+            .into_iter()
+            .filter(|edit| lines.contains(&edit.range.start.line))
+            .collect()
+    }))
+}
+
+/// Provider function for LSP `textDocument/onTypeFormatting`.
+///
+/// Aligns the amount of the posting that was just finished, either by
+/// pressing Enter after it or by typing the first letter of its currency.
+/// Only the entry around it is formatted, with its amounts aligned with
+/// each other.
+pub(crate) fn on_type_formatting(
+    mut snapshot: LspServerStateSnapshot,
+    params: lsp_types::DocumentOnTypeFormattingParams,
+) -> Result<Option<Vec<lsp_types::TextEdit>>> {
+    keep_entries_in_place(&mut snapshot.config.formatting);
+    // Typing only aligns amounts; flags and metadata are left to full formatting
+    snapshot.config.formatting.uppercase_flags = false;
+    snapshot.config.formatting.metadata_indent_width = None;
+
+    let uri = &params.text_document_position.text_document.uri;
+    let Some((doc, tree)) = get_document_and_tree(&snapshot, uri) else {
+        return Ok(None);
+    };
+    let position = params.text_document_position.position;
+    let (line, node) = if params.ch == "\n" {
+        // Enter was pressed at the end of the line above
+        let Some(line) = position.line.checked_sub(1) else {
+            return Ok(None);
+        };
+        let text = doc.content.line(line as usize).to_string();
+        let indent = text.len() - text.trim_start().len();
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        let point = tree_sitter::Point::new(line as usize, indent);
+        (
+            line,
+            tree.root_node().descendant_for_point_range(point, point),
+        )
+    } else {
+        let Some(character) = position.character.checked_sub(1) else {
+            return Ok(None);
+        };
+        let typed = lsp_types::Position::new(position.line, character);
+        let point = lsp_position_to_point(&doc.content, typed);
+        let node = tree.root_node().descendant_for_point_range(point, point);
+        let after_number = node
+            .and_then(|node| node.prev_sibling())
+            .is_some_and(|prev| {
+                matches!(
+                    prev.kind(),
+                    "number" | "unary_number_expr" | "binary_number_expr" | ")"
+                )
+            });
+        let starts_currency =
+            node.is_some_and(|node| node.kind() == "currency" && node.start_position() == point);
+        if !starts_currency || !after_number {
+            return Ok(None);
+        }
+        (position.line, node)
+    };
+    let Some(entry) = node.and_then(enclosing_entry) else {
+        return Ok(None);
+    };
+
+    let edits = format_node(&snapshot, doc, tree, entry)?;
+    Ok(Some(
+        edits
+            .into_iter()
+            .filter(|edit| edit.range.start.line == line)
+            .collect(),
+    ))
+}
+
+/// The top-level entry containing `node`.
+fn enclosing_entry(node: tree_sitter::Node) -> Option<tree_sitter::Node> {
+    let mut node = node;
+    while let Some(parent) = node.parent() {
+        if parent.parent().is_none() || parent.kind() == "section" {
+            return Some(node);
+        }
+        node = parent;
+    }
+    None
+}
+
+/// Partial formatting edits lines where they are, so it leaves out the
+/// options that reorder entries or change the number of lines between them.
+fn keep_entries_in_place(config: &mut FormattingConfig) {
+    config.sort_by_date = false;
+    config.blank_lines_between_entries = None;
+}
+
+/// Last line of a node; entries end at column 0 of the line after it.
+fn last_row(node: tree_sitter::Node) -> usize {
+    let end = node.end_position();
+    if end.column == 0 && end.row > node.start_position().row {
+        end.row - 1
+    } else {
+        end.row
+    }
+}
+
+/// Lines from `start` to `end`, widened to whole entries at both ends.
+fn entry_lines(tree: &tree_sitter::Tree, start: u32, end: u32) -> std::ops::RangeInclusive<u32> {
+    let mut first = start;
+    let mut last = end;
+    for entry in crate::beancount_data::entry_nodes(tree.root_node()) {
+        let entry_start = entry.start_position().row as u32;
+        let entry_last = last_row(entry) as u32;
+        if entry_start <= end && entry_last >= start {
+            first = first.min(entry_start);
+            last = last.max(entry_last);
+        }
+    }
+    first..=last
 }
 
 /// Gets the document and tree from the snapshot, with error handling
@@ -200,9 +381,9 @@ fn get_document_and_tree<'a>(
 /// This mimics bean-format's regex-based line extraction
 fn extract_formateable_lines(
     doc: &crate::document::Document,
-    tree: &tree_sitter::Tree,
+    node: tree_sitter::Node,
 ) -> Result<Vec<FormatableLine>> {
-    let query = match tree_sitter::Query::new(&tree.language(), QUERY_STR) {
+    let query = match tree_sitter::Query::new(&node.language(), QUERY_STR) {
         Ok(query) => query,
         Err(e) => {
             debug!("Failed to create tree-sitter query: {}", e);
@@ -213,7 +394,7 @@ fn extract_formateable_lines(
     let mut query_cursor = tree_sitter::QueryCursor::new();
     let mut matches = query_cursor.matches(
         &query,
-        tree.root_node(),
+        node,
         RopeProvider(doc.content.get_slice(..).unwrap()),
     );
 
@@ -264,8 +445,8 @@ fn extract_line_components(
         .to_string();
 
     // Extract prefix (from line start to end of account/directive)
-    let prefix_end_char = doc.content.line_to_char(prefix_node.start_position().row)
-        + prefix_node.end_position().column;
+    // Node columns count bytes, so go through byte offsets to get char indices
+    let prefix_end_char = doc.content.byte_to_char(prefix_node.end_byte());
     let prefix_start_char = doc.content.line_to_char(prefix_node.start_position().row);
     let prefix_text = doc
         .content
@@ -273,10 +454,8 @@ fn extract_line_components(
         .to_string();
 
     // Extract number text
-    let number_start_char = doc.content.line_to_char(number_node.start_position().row)
-        + number_node.start_position().column;
-    let number_end_char = doc.content.line_to_char(number_node.end_position().row)
-        + number_node.end_position().column;
+    let number_start_char = doc.content.byte_to_char(number_node.start_byte());
+    let number_end_char = doc.content.byte_to_char(number_node.end_byte());
     let number_text = doc
         .content
         .slice(number_start_char..number_end_char)
@@ -306,13 +485,13 @@ fn calculate_format_config(
     // Calculate maximum widths across all lines (bean-format behavior)
     let max_prefix_width = formateable_lines
         .iter()
-        .map(|line| line.prefix.trim_end().len())
+        .map(|line| line.prefix.trim_end().chars().count())
         .max()
         .unwrap_or(0);
 
     let max_number_width = formateable_lines
         .iter()
-        .map(|line| line.number.len())
+        .map(|line| line.number.chars().count())
         .max()
         .unwrap_or(0);
 
@@ -337,8 +516,8 @@ fn generate_currency_column_edits(
     for line in formateable_lines {
         // Calculate spacing needed to align currency at the specified column
         // Bean-format logic: num_of_spaces = currency_column - len(prefix) - len(number) - 3
-        let prefix_len = line.prefix.trim_end().len();
-        let number_len = line.number.len();
+        let prefix_len = line.prefix.trim_end().chars().count();
+        let number_len = line.number.chars().count();
         let spaces_needed = if currency_col >= prefix_len + number_len + 3 {
             currency_col - prefix_len - number_len - 3
         } else {
@@ -474,7 +653,7 @@ fn create_line_replacement_edit(
         .content
         .slice(line_start_char..line_end_char)
         .to_string();
-    let original_line_len = original_line.trim_end().len();
+    let original_line_len = original_line.trim_end().encode_utf16().count();
 
     // Skip edit if the line content hasn't changed
     // This optimization prevents unnecessary edits for already-formatted lines
@@ -504,7 +683,7 @@ fn create_line_replacement_edit(
 /// This ensures that indent changes don't conflict with amount/currency formatting
 fn apply_indent_normalization_to_remaining_lines(
     doc: &crate::document::Document,
-    _tree: &tree_sitter::Tree,
+    rows: std::ops::Range<usize>,
     target_indent_width: usize,
     mut existing_edits: Vec<lsp_types::TextEdit>,
 ) -> Result<Vec<lsp_types::TextEdit>> {
@@ -518,8 +697,8 @@ fn apply_indent_normalization_to_remaining_lines(
         .map(|edit| edit.range.start.line)
         .collect();
 
-    // Process all lines in the document
-    for line_num in 0..doc.content.len_lines() {
+    // Process the lines being formatted
+    for line_num in rows {
         let line_num_u32 = line_num as u32;
 
         // Skip lines that already have formatting edits
@@ -574,17 +753,353 @@ fn apply_indent_normalization_to_remaining_lines(
     Ok(existing_edits)
 }
 
+/// Rewrites a plain number with the configured thousands separators and
+/// decimal places. Anything else, like an arithmetic expression, is kept.
+fn normalize_number(number: &str, currency: &str, config: &FormattingConfig) -> String {
+    let (sign, unsigned) = match number.strip_prefix(['-', '+']) {
+        Some(rest) => (&number[..1], rest),
+        None => ("", number),
+    };
+    let (integer, fraction) = match unsigned.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let digits: String = integer.chars().filter(|c| *c != ',').collect();
+    if digits.is_empty()
+        || !digits.chars().all(|c| c.is_ascii_digit())
+        || !fraction.is_none_or(|fraction| fraction.chars().all(|c| c.is_ascii_digit()))
+    {
+        return number.to_string();
+    }
+
+    let integer = match config.thousands_separator {
+        Some(true) => group_thousands(&digits),
+        Some(false) => digits,
+        None => integer.to_string(),
+    };
+    // Only trailing zeros are added or dropped so the value stays the same
+    let fraction = match config.decimal_places.get(currency) {
+        Some(&places) => {
+            let mut fraction = fraction.unwrap_or_default().to_string();
+            while fraction.len() > places && fraction.ends_with('0') {
+                fraction.pop();
+            }
+            while fraction.len() < places {
+                fraction.push('0');
+            }
+            (!fraction.is_empty()).then_some(fraction)
+        }
+        None => fraction.map(str::to_string),
+    };
+
+    match fraction {
+        Some(fraction) => format!("{sign}{integer}.{fraction}"),
+        None => format!("{sign}{integer}"),
+    }
+}
+
+/// Inserts a comma between every group of three digits.
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+static LOWERCASE_FLAG_REGEX: OnceLock<regex::Regex> = OnceLock::new();
+
+/// Matches a lowercase letter flag after a transaction date or before a posting account.
+fn get_lowercase_flag_regex() -> &'static regex::Regex {
+    LOWERCASE_FLAG_REGEX.get_or_init(|| {
+        regex::Regex::new(
+            r#"^(?:\d{4}[-/]\d{2}[-/]\d{2}[ \t]+([pstcurm])(?:[ \t]+"|[ \t]*$)|[ \t]+([pstcurm])[ \t]+[A-Z])"#,
+        )
+        .expect("Failed to compile lowercase flag regex")
+    })
+}
+
+/// Entry-level pass for the options that go beyond aligning amounts:
+/// flag case, metadata indentation, blank lines and date order.
+///
+/// Applies `edits` to the document lines first so the result includes them,
+/// then returns the edits turning the document into the rewritten lines.
+fn normalize_entries(
+    doc: &crate::document::Document,
+    tree: &tree_sitter::Tree,
+    config: &FormattingConfig,
+    edits: Vec<lsp_types::TextEdit>,
+) -> Vec<lsp_types::TextEdit> {
+    let original: Vec<String> = doc
+        .content
+        .lines()
+        .map(|line| line.to_string().trim_end_matches(['\n', '\r']).to_string())
+        .collect();
+
+    let mut lines = original.clone();
+    for edit in &edits {
+        // Line edits replace the start of a line and keep whatever follows
+        let line = &mut lines[edit.range.start.line as usize];
+        let rest = line[utf16_to_byte(line, edit.range.end.character)..].to_string();
+        *line = format!("{}{rest}", edit.new_text);
+    }
+
+    if config.uppercase_flags {
+        for line in &mut lines {
+            if let Some(captures) = get_lowercase_flag_regex().captures(line)
+                && let Some(flag) = captures.get(1).or_else(|| captures.get(2))
+            {
+                let range = flag.range();
+                line.replace_range(range.clone(), &line[range].to_ascii_uppercase());
+            }
+        }
+    }
+
+    if let Some(width) = config.metadata_indent_width {
+        indent_metadata(tree, width, &mut lines);
+    }
+
+    if config.sort_by_date || config.blank_lines_between_entries.is_some() {
+        lines = arrange_entries(doc, tree, config, &lines);
+    }
+
+    let newline = match doc.content.lines().next() {
+        Some(line) if line.to_string().ends_with("\r\n") => "\r\n",
+        _ => "\n",
+    };
+    diff_lines(&original, &lines, newline)
+}
+
+/// Byte offset in `line` of a column in UTF-16 code units, as LSP counts them.
+fn utf16_to_byte(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (byte, c) in line.char_indices() {
+        if units >= character as usize {
+            return byte;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Indents metadata relative to its entry, or to the posting above it.
+fn indent_metadata(tree: &tree_sitter::Tree, width: usize, lines: &mut [String]) {
+    for entry in crate::beancount_data::entry_nodes(tree.root_node()) {
+        let mut owner_indent = 0;
+        let mut cursor = entry.walk();
+        for child in entry.named_children(&mut cursor) {
+            let row = child.start_position().row;
+            match child.kind() {
+                "posting" => {
+                    owner_indent = lines[row].len() - lines[row].trim_start().len();
+                }
+                "key_value" => {
+                    lines[row] = format!(
+                        "{}{}",
+                        " ".repeat(owner_indent + width),
+                        lines[row].trim_start()
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Lines that move together when entries are sorted.
+#[derive(Debug)]
+struct EntryBlock {
+    /// First line, including comments directly above the entry.
+    start: usize,
+    /// Line after the last line.
+    end: usize,
+    /// Date of the entry; blocks without one keep their position.
+    date: Option<String>,
+}
+
+/// Splits the document into dated entries with the comments directly above
+/// them, and everything else that must stay in place.
+fn entry_blocks(doc: &crate::document::Document, tree: &tree_sitter::Tree) -> Vec<EntryBlock> {
+    let mut blocks = Vec::new();
+    let mut comments: Option<(usize, usize)> = None;
+
+    for node in crate::beancount_data::entry_nodes(tree.root_node()) {
+        let start = node.start_position().row;
+        let end = last_row(node) + 1;
+
+        if node.kind() == "comment" {
+            comments = match comments {
+                Some((comment_start, comment_end)) if comment_end == start => {
+                    Some((comment_start, end))
+                }
+                Some((comment_start, comment_end)) => {
+                    blocks.push(EntryBlock {
+                        start: comment_start,
+                        end: comment_end,
+                        date: None,
+                    });
+                    Some((start, end))
+                }
+                None => Some((start, end)),
+            };
+            continue;
+        }
+
+        let date = node
+            .child_by_field_name("date")
+            .map(|date| text_for_tree_sitter_node(&doc.content, &date).replace('/', "-"));
+        let start = match comments.take() {
+            Some((comment_start, comment_end)) if comment_end == start && date.is_some() => {
+                comment_start
+            }
+            Some((comment_start, comment_end)) => {
+                blocks.push(EntryBlock {
+                    start: comment_start,
+                    end: comment_end,
+                    date: None,
+                });
+                start
+            }
+            None => start,
+        };
+        blocks.push(EntryBlock { start, end, date });
+    }
+
+    if let Some((start, end)) = comments {
+        blocks.push(EntryBlock {
+            start,
+            end,
+            date: None,
+        });
+    }
+    blocks
+}
+
+/// Sorts runs of dated entries separated only by blank lines and normalizes
+/// the blank lines between them.
+fn arrange_entries(
+    doc: &crate::document::Document,
+    tree: &tree_sitter::Tree,
+    config: &FormattingConfig,
+    lines: &[String],
+) -> Vec<String> {
+    let blocks = entry_blocks(doc, tree);
+    let is_blank = |start: usize, end: usize| lines[start..end].iter().all(|l| l.trim().is_empty());
+
+    let mut arranged = Vec::with_capacity(lines.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < blocks.len() {
+        if blocks[i].date.is_none() {
+            i += 1;
+            continue;
+        }
+        let mut j = i + 1;
+        while j < blocks.len()
+            && blocks[j].date.is_some()
+            && is_blank(blocks[j - 1].end, blocks[j].start)
+        {
+            j += 1;
+        }
+        let run = &blocks[i..j];
+
+        arranged.extend_from_slice(&lines[copied..run[0].start]);
+        let mut order: Vec<&EntryBlock> = run.iter().collect();
+        if config.sort_by_date {
+            order.sort_by(|a, b| a.date.cmp(&b.date));
+        }
+        for (k, block) in order.into_iter().enumerate() {
+            if k > 0 {
+                match config.blank_lines_between_entries {
+                    Some(count) => arranged.extend(std::iter::repeat_n(String::new(), count)),
+                    None => arranged.extend_from_slice(&lines[run[k - 1].end..run[k].start]),
+                }
+            }
+            arranged.extend_from_slice(&lines[block.start..block.end]);
+        }
+
+        copied = run[run.len() - 1].end;
+        i = j;
+    }
+    arranged.extend_from_slice(&lines[copied..]);
+    arranged
+}
+
+/// Edits turning `original` into `lines`: one per changed line while the
+/// line count is the same, otherwise a single edit over the changed lines.
+fn diff_lines(original: &[String], lines: &[String], newline: &str) -> Vec<lsp_types::TextEdit> {
+    let line_end = |row: usize| {
+        lsp_types::Position::new(row as u32, original[row].encode_utf16().count() as u32)
+    };
+
+    if original.len() == lines.len() {
+        return original
+            .iter()
+            .zip(lines)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(row, (_, new))| lsp_types::TextEdit {
+                range: lsp_types::Range::new(
+                    lsp_types::Position::new(row as u32, 0),
+                    line_end(row),
+                ),
+                new_text: new.clone(),
+            })
+            .collect();
+    }
+
+    let prefix = original
+        .iter()
+        .zip(lines)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = original[prefix..]
+        .iter()
+        .rev()
+        .zip(lines[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old_end = original.len() - suffix;
+    let new_end = lines.len() - suffix;
+
+    let edit = if old_end < original.len() {
+        // Replace whole lines together with their line breaks
+        lsp_types::TextEdit {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(prefix as u32, 0),
+                lsp_types::Position::new(old_end as u32, 0),
+            ),
+            new_text: lines[prefix..new_end]
+                .iter()
+                .map(|line| format!("{line}{newline}"))
+                .collect(),
+        }
+    } else {
+        // The last line has no line break after it
+        let end = line_end(original.len() - 1);
+        let (start, leading) = if prefix < original.len() {
+            (lsp_types::Position::new(prefix as u32, 0), "")
+        } else {
+            (end, newline)
+        };
+        lsp_types::TextEdit {
+            range: lsp_types::Range::new(start, end),
+            new_text: format!("{leading}{}", lines[prefix..].join(newline)),
+        }
+    };
+    vec![edit]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::beancount_data::BeancountData;
     use crate::config::Config;
-    use crate::document::Document;
     use crate::server::LspServerStateSnapshot;
     use std::collections::HashMap;
     use std::str::FromStr;
-    use std::sync::Arc;
-    use tree_sitter_beancount::tree_sitter;
 
     struct TestState {
         snapshot: LspServerStateSnapshot,
@@ -592,41 +1107,7 @@ mod tests {
 
     impl TestState {
         fn new(content: &str) -> anyhow::Result<Self> {
-            // Use a consistent path that works across platforms
-            let path = std::env::current_dir()?.join("test.beancount");
-            let rope_content = ropey::Rope::from_str(content);
-
-            // Parse the content with tree-sitter
-            let mut parser = tree_sitter::Parser::new();
-            parser.set_language(&tree_sitter_beancount::language())?;
-            let tree = parser.parse(content, None).unwrap();
-
-            // Create the necessary data structures
-            let mut forest = HashMap::new();
-            forest.insert(path.clone(), Arc::new(tree.clone()));
-
-            let mut open_docs = HashMap::new();
-            open_docs.insert(
-                path.clone(),
-                Document {
-                    content: rope_content.clone(),
-                },
-            );
-
-            let mut beancount_data = HashMap::new();
-            beancount_data.insert(
-                path.clone(),
-                Arc::new(BeancountData::new(&tree, &rope_content)),
-            );
-
-            let snapshot = LspServerStateSnapshot {
-                beancount_data,
-                config: Config::new(std::env::current_dir()?),
-                forest,
-                open_docs,
-            };
-
-            Ok(TestState { snapshot })
+            Self::new_with_config(content, crate::config::FormattingConfig::default())
         }
 
         fn new_with_config(
@@ -635,41 +1116,9 @@ mod tests {
         ) -> anyhow::Result<Self> {
             // Use a consistent path that works across platforms
             let path = std::env::current_dir()?.join("test.beancount");
-            let rope_content = ropey::Rope::from_str(content);
-
-            // Parse the content with tree-sitter
-            let mut parser = tree_sitter::Parser::new();
-            parser.set_language(&tree_sitter_beancount::language())?;
-            let tree = parser.parse(content, None).unwrap();
-
-            // Create the necessary data structures
-            let mut forest = HashMap::new();
-            forest.insert(path.clone(), Arc::new(tree.clone()));
-
-            let mut open_docs = HashMap::new();
-            open_docs.insert(
-                path.clone(),
-                Document {
-                    content: rope_content.clone(),
-                },
-            );
-
-            let mut beancount_data = HashMap::new();
-            beancount_data.insert(
-                path.clone(),
-                Arc::new(BeancountData::new(&tree, &rope_content)),
-            );
-
             let mut config = Config::new(std::env::current_dir()?);
             config.formatting = format_config;
-
-            let snapshot = LspServerStateSnapshot {
-                beancount_data,
-                config,
-                forest,
-                open_docs,
-            };
-
+            let snapshot = LspServerStateSnapshot::from_files(config, &[(path, content)]);
             Ok(TestState { snapshot })
         }
 
@@ -696,18 +1145,51 @@ mod tests {
                 },
             };
 
-            // Create a new snapshot for each test call
-            let snapshot = LspServerStateSnapshot {
-                beancount_data: self.snapshot.beancount_data.clone(),
-                config: self.snapshot.config.clone(),
-                forest: self.snapshot.forest.clone(),
-                open_docs: self.snapshot.open_docs.clone(),
+            formatting(self.snapshot.clone(), params)
+        }
+
+        fn format_range(
+            &self,
+            start: u32,
+            end: u32,
+        ) -> anyhow::Result<Option<Vec<lsp_types::TextEdit>>> {
+            let params = lsp_types::DocumentRangeFormattingParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: test_uri()? },
+                range: lsp_types::Range::new(
+                    lsp_types::Position::new(start, 0),
+                    lsp_types::Position::new(end, 0),
+                ),
+                options: Default::default(),
+                work_done_progress_params: Default::default(),
             };
+            range_formatting(self.snapshot.clone(), params)
+        }
 
-            formatting(snapshot, params)
+        fn format_on_type(
+            &self,
+            position: lsp_types::Position,
+            ch: &str,
+        ) -> anyhow::Result<Option<Vec<lsp_types::TextEdit>>> {
+            let params = lsp_types::DocumentOnTypeFormattingParams {
+                text_document_position: lsp_types::TextDocumentPositionParams {
+                    text_document: lsp_types::TextDocumentIdentifier { uri: test_uri()? },
+                    position,
+                },
+                ch: ch.to_string(),
+                options: Default::default(),
+            };
+            on_type_formatting(self.snapshot.clone(), params)
         }
     }
 
+    fn test_uri() -> anyhow::Result<lsp_types::Uri> {
+        let path = std::env::current_dir()?.join("test.beancount");
+        let url = url::Url::from_file_path(&path)
+            .map_err(|_| anyhow::anyhow!("Failed to convert path to URL: {:?}", path))?;
+        lsp_types::Uri::from_str(url.as_str())
+            .map_err(|e| anyhow::anyhow!("Failed to create URI: {:?}", e))
+    }
+
     fn apply_edits(content: &str, edits: &[lsp_types::TextEdit]) -> String {
         let rope = ropey::Rope::from_str(content);
         let mut sorted_edits = edits.to_vec();
@@ -729,8 +1211,12 @@ mod tests {
             let end_line = edit.range.end.line as usize;
             let end_char = edit.range.end.character as usize;
 
-            let start_char_idx = result.line_to_char(start_line) + start_char;
-            let end_char_idx = result.line_to_char(end_line) + end_char;
+            let utf16_to_char = |line: usize, character: usize| {
+                let line_start = result.char_to_utf16_cu(result.line_to_char(line));
+                result.utf16_cu_to_char(line_start + character)
+            };
+            let start_char_idx = utf16_to_char(start_line, start_char);
+            let end_char_idx = utf16_to_char(end_line, end_char);
 
             // Remove the old text
             if start_char_idx < end_char_idx {
@@ -981,6 +1467,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1024,6 +1511,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1070,6 +1558,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1106,6 +1595,7 @@ mod tests {
             account_amount_spacing: 3,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1141,6 +1631,7 @@ mod tests {
             account_amount_spacing: 5,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1190,6 +1681,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1244,6 +1736,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 2,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1289,6 +1782,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 0,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1325,6 +1819,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1374,6 +1869,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1441,6 +1937,7 @@ mod tests {
             account_amount_spacing: 2, // Should have at least 2 spaces
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1648,6 +2145,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1698,6 +2196,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: Some(4),
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1742,6 +2241,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: Some(2),
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1782,6 +2282,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: Some(2),
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1937,6 +2438,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -1982,6 +2484,7 @@ mod tests {
             account_amount_spacing: 2,
             number_currency_spacing: 1,
             indent_width: None,
+            ..crate::config::FormattingConfig::default()
         };
 
         let state = TestState::new_with_config(content, format_config).unwrap();
@@ -2059,4 +2562,352 @@ mod tests {
             assert_eq!(edits2.len(), 0, "Second format should generate zero edits");
         }
     }
+
+    const TWO_TRANSACTIONS: &str = r#"2023-01-01 * "First"
+  Assets:Cash 100.00 USD
+  Expenses:Food  -100.00 USD
+
+2023-01-02 * "Second"
+  Assets:Cash 5.00 USD
+  Expenses:Food   -5.00 USD
+"#;
+
+    #[test]
+    fn test_range_formatting_only_edits_selected_entry() {
+        let state = TestState::new(TWO_TRANSACTIONS).unwrap();
+        let all = state.format().unwrap().unwrap();
+        assert!(all.iter().any(|edit| edit.range.start.line < 3));
+        assert!(all.iter().any(|edit| edit.range.start.line > 3));
+
+        // A range inside the second transaction covers the whole entry
+        let edits = state.format_range(5, 5).unwrap().unwrap();
+        assert!(!edits.is_empty());
+        assert!(
+            edits
+                .iter()
+                .all(|edit| (4..=6).contains(&edit.range.start.line))
+        );
+
+        // Aligned with the rest of the document, as full formatting would be
+        let expected: Vec<_> = all
+            .into_iter()
+            .filter(|edit| edit.range.start.line > 3)
+            .collect();
+        assert_eq!(edits, expected);
+    }
+
+    #[test]
+    fn test_on_type_formatting_after_enter_edits_previous_line() {
+        let state = TestState::new(TWO_TRANSACTIONS).unwrap();
+        let edits = state
+            .format_on_type(lsp_types::Position::new(2, 0), "\n")
+            .unwrap()
+            .unwrap();
+        assert!(!edits.is_empty());
+        assert!(edits.iter().all(|edit| edit.range.start.line == 1));
+    }
+
+    #[test]
+    fn test_on_type_formatting_after_currency_edits_current_line() {
+        let state = TestState::new(TWO_TRANSACTIONS).unwrap();
+        let edits = state
+            .format_on_type(lsp_types::Position::new(5, 20), "U")
+            .unwrap()
+            .unwrap();
+        assert!(!edits.is_empty());
+        assert!(edits.iter().all(|edit| edit.range.start.line == 5));
+
+        // Only the first letter of a currency after a number triggers formatting
+        for (line, character, ch) in [(5, 21, "S"), (4, 14, "S"), (5, 8, "A")] {
+            let edits = state
+                .format_on_type(lsp_types::Position::new(line, character), ch)
+                .unwrap();
+            assert_eq!(edits, None, "{line}:{character}");
+        }
+
+        let edits = state
+            .format_on_type(lsp_types::Position::new(0, 0), "\n")
+            .unwrap();
+        assert_eq!(edits, None);
+    }
+
+    #[test]
+    fn test_on_type_formatting_aligns_within_the_entry() {
+        let content = r#"2023-01-01 * "First"
+  Assets:Checking:Savings 100.00 USD
+  Expenses:Food  -100.00 USD
+
+2023-01-02 * "Second"
+  Assets:Cash 5.00 USD
+  Expenses:Food  -5.00 USD
+"#;
+        let state = TestState::new(content).unwrap();
+        let edits = state
+            .format_on_type(lsp_types::Position::new(5, 20), "U")
+            .unwrap()
+            .unwrap();
+        let formatted = apply_edits(content, &edits);
+        let line = formatted.lines().nth(5).unwrap();
+        let next = formatted.lines().nth(6).unwrap();
+        // Aligned with the other posting of the entry, not the longer account above
+        assert_eq!(line.find("USD"), next.find("USD"));
+        assert!(line.len() < "  Assets:Checking:Savings 100.00 USD".len());
+    }
+
+    #[test]
+    fn test_edit_positions_count_utf16_code_units() {
+        let content = "2023-01-01 * \"Pizza 🍕\"\n  Assets:Café 5.00 USD ; 🍕 slice\n  Expenses:Food  -5.00 USD\n";
+        let expected = "2023-01-01 * \"Pizza 🍕\"\n  Assets:Café     5.00 USD ; 🍕 slice\n  Expenses:Food  -5.00 USD\n";
+        let config = crate::config::FormattingConfig::default();
+        assert_eq!(format_with(content, config.clone()), expected);
+
+        // The entry pass applies the line edits and diffs the result itself
+        let config = crate::config::FormattingConfig {
+            uppercase_flags: true,
+            ..config
+        };
+        assert_eq!(format_with(content, config), expected);
+    }
+
+    fn format_with(content: &str, format_config: crate::config::FormattingConfig) -> String {
+        let state = TestState::new_with_config(content, format_config).unwrap();
+        let edits = state.format().unwrap().unwrap();
+        apply_edits(content, &edits)
+    }
+
+    const UNSORTED: &str = r#"option "title" "Test"
+
+2024-01-03 * "Third"
+  Assets:Cash  -3 USD
+  Expenses:Food
+
+
+
+; Bought on the way home
+2024-01-01 * "First"
+  Assets:Cash  -1 USD
+  Expenses:Food
+2024-01-02 * "Second"
+  Assets:Cash  -2 USD
+  Expenses:Food
+
+; Standalone note
+
+2023-12-31 * "Earlier"
+  Assets:Cash  -4 USD
+  Expenses:Food
+"#;
+
+    #[test]
+    fn test_entry_options_are_off_by_default() {
+        let state = TestState::new(UNSORTED).unwrap();
+        let edits = state.format().unwrap().unwrap();
+        assert_eq!(apply_edits(UNSORTED, &edits), UNSORTED);
+    }
+
+    #[test]
+    fn test_blank_lines_between_entries() {
+        let format_config = crate::config::FormattingConfig {
+            blank_lines_between_entries: Some(1),
+            ..crate::config::FormattingConfig::default()
+        };
+        let formatted = format_with(UNSORTED, format_config);
+        assert_eq!(
+            formatted,
+            r#"option "title" "Test"
+
+2024-01-03 * "Third"
+  Assets:Cash  -3 USD
+  Expenses:Food
+
+; Bought on the way home
+2024-01-01 * "First"
+  Assets:Cash  -1 USD
+  Expenses:Food
+
+2024-01-02 * "Second"
+  Assets:Cash  -2 USD
+  Expenses:Food
+
+; Standalone note
+
+2023-12-31 * "Earlier"
+  Assets:Cash  -4 USD
+  Expenses:Food
+"#
+        );
+    }
+
+    #[test]
+    fn test_sort_by_date_keeps_comments_in_place() {
+        let format_config = crate::config::FormattingConfig {
+            sort_by_date: true,
+            ..crate::config::FormattingConfig::default()
+        };
+        let formatted = format_with(UNSORTED, format_config);
+        // The comment moves with its entry; the standalone note separates
+        // the run that gets sorted from the entry after it
+        assert_eq!(
+            formatted,
+            r#"option "title" "Test"
+
+; Bought on the way home
+2024-01-01 * "First"
+  Assets:Cash  -1 USD
+  Expenses:Food
+
+
+
+2024-01-02 * "Second"
+  Assets:Cash  -2 USD
+  Expenses:Food
+2024-01-03 * "Third"
+  Assets:Cash  -3 USD
+  Expenses:Food
+
+; Standalone note
+
+2023-12-31 * "Earlier"
+  Assets:Cash  -4 USD
+  Expenses:Food
+"#
+        );
+    }
+
+    #[test]
+    fn test_sort_by_date_stays_within_sections() {
+        let content = r#"* 2024
+2024-02-01 * "February"
+  Assets:Cash  -2 USD
+  Expenses:Food
+2024-01-01 * "January"
+  Assets:Cash  -1 USD
+  Expenses:Food
+* 2023
+2023-01-01 * "Older"
+  Assets:Cash  -3 USD
+  Expenses:Food
+"#;
+        let format_config = crate::config::FormattingConfig {
+            sort_by_date: true,
+            blank_lines_between_entries: Some(1),
+            ..crate::config::FormattingConfig::default()
+        };
+        assert_eq!(
+            format_with(content, format_config),
+            r#"* 2024
+2024-01-01 * "January"
+  Assets:Cash  -1 USD
+  Expenses:Food
+
+2024-02-01 * "February"
+  Assets:Cash  -2 USD
+  Expenses:Food
+* 2023
+2023-01-01 * "Older"
+  Assets:Cash  -3 USD
+  Expenses:Food
+"#
+        );
+    }
+
+    #[test]
+    fn test_uppercase_flags() {
+        let content = r#"2024-01-01 p "Transfer"
+  s Assets:Cash  -1 USD
+  Assets:Bank
+  ; t is not a flag here
+"#;
+        let format_config = crate::config::FormattingConfig {
+            uppercase_flags: true,
+            ..crate::config::FormattingConfig::default()
+        };
+        assert_eq!(
+            format_with(content, format_config),
+            r#"2024-01-01 P "Transfer"
+  S Assets:Cash  -1 USD
+  Assets:Bank
+  ; t is not a flag here
+"#
+        );
+    }
+
+    #[test]
+    fn test_metadata_indent_width() {
+        let content = r#"2024-01-01 open Assets:Cash
+      note: "cash"
+2024-01-02 * "Lunch"
+ receipt: "r1"
+  Expenses:Food  10 USD
+   category: "meals"
+  Assets:Cash
+"#;
+        let format_config = crate::config::FormattingConfig {
+            metadata_indent_width: Some(2),
+            ..crate::config::FormattingConfig::default()
+        };
+        assert_eq!(
+            format_with(content, format_config),
+            r#"2024-01-01 open Assets:Cash
+  note: "cash"
+2024-01-02 * "Lunch"
+  receipt: "r1"
+  Expenses:Food  10 USD
+    category: "meals"
+  Assets:Cash
+"#
+        );
+    }
+
+    #[test]
+    fn test_number_normalization() {
+        let content = r#"2024-01-01 * "Salary"
+  Assets:Bank  1234567.5 USD
+  Assets:Broker  3.10 EUR
+  Income:Salary  -1,234,567.50 USD
+"#;
+        let format_config = crate::config::FormattingConfig {
+            thousands_separator: Some(true),
+            decimal_places: HashMap::from([("USD".to_string(), 2)]),
+            ..crate::config::FormattingConfig::default()
+        };
+        assert_eq!(
+            format_with(content, format_config),
+            r#"2024-01-01 * "Salary"
+  Assets:Bank     1,234,567.50 USD
+  Assets:Broker           3.10 EUR
+  Income:Salary  -1,234,567.50 USD
+"#
+        );
+    }
+
+    #[test]
+    fn test_normalize_number() {
+        let config = crate::config::FormattingConfig {
+            thousands_separator: Some(false),
+            decimal_places: HashMap::from([("USD".to_string(), 2), ("JPY".to_string(), 0)]),
+            ..crate::config::FormattingConfig::default()
+        };
+        assert_eq!(normalize_number("-1,000.5", "USD", &config), "-1000.50");
+        assert_eq!(normalize_number("1000.12345", "USD", &config), "1000.12345");
+        assert_eq!(normalize_number("1000.000", "USD", &config), "1000.00");
+        assert_eq!(normalize_number("500.00", "JPY", &config), "500");
+        assert_eq!(normalize_number("500.50", "JPY", &config), "500.5");
+        assert_eq!(normalize_number("1.5", "EUR", &config), "1.5");
+        assert_eq!(normalize_number("(1 + 2)", "USD", &config), "(1 + 2)");
+        assert_eq!(group_thousands("1234567"), "1,234,567");
+        assert_eq!(group_thousands("123"), "123");
+    }
+
+    #[test]
+    fn test_range_formatting_does_not_move_entries() {
+        let format_config = crate::config::FormattingConfig {
+            sort_by_date: true,
+            blank_lines_between_entries: Some(1),
+            ..crate::config::FormattingConfig::default()
+        };
+        let state = TestState::new_with_config(UNSORTED, format_config).unwrap();
+        let edits = state.format_range(0, 20).unwrap().unwrap();
+        assert!(edits.is_empty());
+    }
 }
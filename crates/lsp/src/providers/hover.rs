@@ -0,0 +1,263 @@
+use crate::beancount_data::{AccountClose, AccountOpen, BeancountData, SymbolKind};
+use crate::ledger::{Inventory, parse_date};
+use crate::server::LspServerStateSnapshot;
+use crate::treesitter_utils::{
+    lsp_position_to_point, lsp_range_for_node, text_for_tree_sitter_node,
+};
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use std::fmt::Write;
+use tree_sitter_beancount::tree_sitter;
+
+/// Provider function for LSP `textDocument/hover`.
+///
+/// Hovering an account shows its `open`/`close` details, metadata, and the
+/// running balance per currency as of the date of the entry under the cursor.
+pub(crate) fn hover(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::HoverParams,
+) -> Result<Option<lsp_types::Hover>> {
+    let uri = params
+        .text_document_position_params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+    let position = params.text_document_position_params.position;
+
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&uri), snapshot.open_docs.get(&uri)) else {
+        return Ok(None);
+    };
+    let content = &doc.content;
+
+    let Some(account_node) = account_node_at(tree, content, position) else {
+        return Ok(None);
+    };
+    let account = text_for_tree_sitter_node(content, &account_node);
+    let as_of = entry_date(account_node, content);
+
+    let files: Vec<&BeancountData> = snapshot
+        .index
+        .files_using(&snapshot.beancount_data, SymbolKind::Account, &account)
+        .map(|(_, data)| data)
+        .collect();
+    let markdown = account_hover_markdown(&files, &account, as_of);
+
+    Ok(Some(lsp_types::Hover {
+        contents: lsp_types::HoverContents::Markup(lsp_types::MarkupContent {
+            kind: lsp_types::MarkupKind::Markdown,
+            value: markdown,
+        }),
+        range: Some(lsp_range_for_node(content, &account_node)),
+    }))
+}
+
+/// Find the `account` node touching the cursor, if any.
+fn account_node_at<'a>(
+    tree: &'a tree_sitter::Tree,
+    content: &ropey::Rope,
+    position: lsp_types::Position,
+) -> Option<tree_sitter::Node<'a>> {
+    let point = lsp_position_to_point(content, position);
+    let before = lsp_position_to_point(
+        content,
+        lsp_types::Position::new(position.line, position.character.saturating_sub(1)),
+    );
+    [point, before].into_iter().find_map(|p| {
+        tree.root_node()
+            .named_descendant_for_point_range(p, p)
+            .filter(|node| node.kind() == "account")
+    })
+}
+
+/// Date of the entry enclosing `node`, used as the cut-off for balances.
+fn entry_date(node: tree_sitter::Node, content: &ropey::Rope) -> Option<chrono::NaiveDate> {
+    let mut current = Some(node);
+    while let Some(n) = current {
+        if let Some(date) = n.child_by_field_name("date") {
+            return parse_date(&text_for_tree_sitter_node(content, &date));
+        }
+        current = n.parent();
+    }
+    None
+}
+
+/// Balance of `account` per currency, including transactions dated on or before `as_of`.
+fn account_balance(
+    files: &[&BeancountData],
+    account: &str,
+    as_of: Option<chrono::NaiveDate>,
+) -> Inventory {
+    let mut inventory = Inventory::new();
+    for bean_data in files {
+        for txn in bean_data.transactions() {
+            if let (Some(as_of), Some(date)) = (as_of, txn.date)
+                && date > as_of
+            {
+                continue;
+            }
+            for (posting, units) in txn.posting_units() {
+                if posting.account == account {
+                    inventory.add_amount(&units);
+                }
+            }
+        }
+    }
+    inventory
+}
+
+/// Hover text of `account`, from the files using it.
+fn account_hover_markdown(
+    files: &[&BeancountData],
+    account: &str,
+    as_of: Option<chrono::NaiveDate>,
+) -> String {
+    let open: Option<&AccountOpen> = files
+        .iter()
+        .flat_map(|d| d.opens())
+        .filter(|o| o.account == account)
+        .min_by_key(|o| o.date);
+    let close: Option<&AccountClose> = files
+        .iter()
+        .flat_map(|d| d.closes())
+        .filter(|c| c.account == account)
+        .min_by_key(|c| c.date);
+
+    let mut md = format!("**{account}**\n\n");
+
+    match open {
+        Some(open) => {
+            if let Some(date) = open.date {
+                let _ = writeln!(md, "- Opened: {date}");
+            }
+            if !open.currencies.is_empty() {
+                let _ = writeln!(md, "- Currencies: {}", open.currencies.join(", "));
+            }
+            if let Some(booking) = &open.booking {
+                let _ = writeln!(md, "- Booking: {booking}");
+            }
+        }
+        None => md.push_str("- No `open` directive found\n"),
+    }
+    if let Some(date) = close.and_then(|c| c.date) {
+        let _ = writeln!(md, "- Closed: {date}");
+    }
+
+    if let Some(open) = open
+        && !open.metadata.is_empty()
+    {
+        md.push_str("\n**Metadata**\n\n");
+        for (key, value) in &open.metadata {
+            let _ = writeln!(md, "- {key}: {value}");
+        }
+    }
+
+    let balance = account_balance(files, account, as_of);
+    match as_of {
+        Some(date) => {
+            let _ = write!(md, "\n**Balance as of {date}**\n\n");
+        }
+        None => md.push_str("\n**Balance**\n\n"),
+    }
+    if balance.is_empty() {
+        md.push_str("- 0\n");
+    } else {
+        for amount in balance.amounts() {
+            let _ = writeln!(md, "- {amount}");
+        }
+    }
+
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::providers::uri::file_path_to_uri;
+
+    fn snapshot_for(text: &str) -> (LspServerStateSnapshot, lsp_types::Uri) {
+        let dir = std::env::current_dir().unwrap();
+        let path = dir.join("hover.beancount");
+        let uri = file_path_to_uri(&path).unwrap();
+        (
+            LspServerStateSnapshot::from_files(Config::new(dir), &[(path, text)]),
+            uri,
+        )
+    }
+
+    fn hover_at(text: &str, line: u32, character: u32) -> Option<String> {
+        let (snapshot, uri) = snapshot_for(text);
+        let params = lsp_types::HoverParams {
+            text_document_position_params: lsp_types::TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri },
+                position: lsp_types::Position { line, character },
+            },
+            work_done_progress_params: Default::default(),
+        };
+        hover(snapshot, params).unwrap().map(|h| match h.contents {
+            lsp_types::HoverContents::Markup(markup) => markup.value,
+            _ => panic!("Expected markdown hover"),
+        })
+    }
+
+    const LEDGER: &str = r#"2024-01-01 open Assets:Bank USD, EUR "FIFO"
+  institution: "First Bank"
+2024-01-01 open Expenses:Food
+2024-01-05 * "Store" "Groceries"
+  Expenses:Food  20.00 USD
+  Assets:Bank
+2024-02-01 * "Store" "Groceries"
+  Expenses:Food  5.25 USD
+  Assets:Bank   -5.25 USD
+2024-12-31 close Assets:Bank
+"#;
+
+    #[test]
+    fn test_hover_shows_open_details_and_metadata() {
+        let value = hover_at(LEDGER, 0, 20).expect("hover on open account");
+        assert!(value.contains("**Assets:Bank**"));
+        assert!(value.contains("Opened: 2024-01-01"));
+        assert!(value.contains("Currencies: USD, EUR"));
+        assert!(value.contains("Booking: FIFO"));
+        assert!(value.contains("Closed: 2024-12-31"));
+        assert!(value.contains("institution: \"First Bank\""));
+    }
+
+    #[test]
+    fn test_hover_balance_as_of_cursor_date() {
+        // Cursor on the elided posting of the first transaction
+        let value = hover_at(LEDGER, 5, 4).expect("hover on posting account");
+        assert!(value.contains("Balance as of 2024-01-05"));
+        assert!(value.contains("-20.00 USD"), "{value}");
+
+        // Cursor on the second transaction includes both postings
+        let value = hover_at(LEDGER, 8, 4).expect("hover on posting account");
+        assert!(value.contains("-25.25 USD"), "{value}");
+    }
+
+    #[test]
+    fn test_hover_outside_account_returns_none() {
+        assert!(hover_at(LEDGER, 3, 14).is_none());
+    }
+
+    #[test]
+    fn test_hover_range_counts_utf16_code_units() {
+        let (snapshot, uri) = snapshot_for("2024-01-01 open Assets:Café\n");
+        let params = lsp_types::HoverParams {
+            text_document_position_params: lsp_types::TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri },
+                position: lsp_types::Position::new(0, 27),
+            },
+            work_done_progress_params: Default::default(),
+        };
+        let range = hover(snapshot, params).unwrap().unwrap().range.unwrap();
+        assert_eq!(
+            range,
+            lsp_types::Range::new(
+                lsp_types::Position::new(0, 16),
+                lsp_types::Position::new(0, 27)
+            )
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use crate::beancount_data::BeancountData;
+use crate::checkers::native::balances_after_assertions;
+use crate::ledger::Amount;
+use crate::server::LspServerStateSnapshot;
+use crate::treesitter_utils::byte_to_lsp_position;
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use lsp_types::{InlayHint, InlayHintLabel, InlayHintTooltip, Position};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Provider function for LSP `textDocument/inlayHint`.
+///
+/// Shows the amount beancount infers for an elided posting and, when
+/// `inlay_hints.running_balances` is enabled, the account balance computed
+/// after each `balance` directive.
+pub(crate) fn inlay_hint(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::InlayHintParams,
+) -> Result<Option<Vec<InlayHint>>> {
+    let uri = params
+        .text_document
+        .uri
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("Failed to convert URI to file path"))?;
+
+    let (Some(data), Some(doc)) = (
+        snapshot.beancount_data.get(&uri),
+        snapshot.open_docs.get(&uri),
+    ) else {
+        return Ok(None);
+    };
+
+    let mut hints = elided_posting_hints(data, &doc.content);
+    if snapshot.config.inlay_hints.running_balances {
+        let mut files: Vec<_> = snapshot
+            .beancount_data
+            .iter()
+            .map(|(path, data)| (path.clone(), data.clone()))
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        hints.extend(balance_hints(&files, &uri, &doc.content));
+    }
+
+    let range = params.range;
+    hints.retain(|hint| {
+        hint.position.line >= range.start.line && hint.position.line <= range.end.line
+    });
+    hints.sort_by_key(|hint| (hint.position.line, hint.position.character));
+    Ok(Some(hints))
+}
+
+/// A hint placed after the residual amount of each posting without an amount.
+fn elided_posting_hints(data: &BeancountData, content: &ropey::Rope) -> Vec<InlayHint> {
+    let mut hints = Vec::new();
+    for txn in data.transactions() {
+        let mut inferred: Vec<(u32, &str, Vec<Amount>)> = Vec::new();
+        for (posting, units) in txn.posting_units() {
+            if posting.units.is_some() {
+                continue;
+            }
+            match inferred.last_mut() {
+                Some((line, _, amounts)) if *line == posting.line => amounts.push(units),
+                _ => inferred.push((posting.line, &posting.account, vec![units])),
+            }
+        }
+
+        for (line, account, amounts) in inferred {
+            let Some(position) = position_after(content, line, account) else {
+                continue;
+            };
+            let label = amounts
+                .iter()
+                .map(|amount| amount.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            hints.push(hint(
+                position,
+                label,
+                "Amount inferred from the other postings",
+            ));
+        }
+    }
+    hints
+}
+
+/// A hint at the end of each `balance` directive in `path` with the balance
+/// accumulated by the ledger at that point.
+fn balance_hints(
+    files: &[(PathBuf, Arc<BeancountData>)],
+    path: &Path,
+    content: &ropey::Rope,
+) -> Vec<InlayHint> {
+    balances_after_assertions(files)
+        .into_iter()
+        .filter(|(file, _, _)| file == path)
+        .filter_map(|(_, line, amount)| {
+            let text = content.get_line(line as usize)?.to_string();
+            // Place the hint after the amount, before any trailing comment
+            let end = text.split(';').next().unwrap_or_default().trim_end().len();
+            Some(hint(
+                byte_to_lsp_position(content, content.line_to_byte(line as usize) + end),
+                format!("accumulated {amount}"),
+                "Account balance after this assertion",
+            ))
+        })
+        .collect()
+}
+
+/// Position right after the first occurrence of `needle` on `line`.
+fn position_after(content: &ropey::Rope, line: u32, needle: &str) -> Option<Position> {
+    let text = content.get_line(line as usize)?.to_string();
+    let start = text.find(needle)?;
+    let line_start = content.line_to_byte(line as usize);
+    Some(byte_to_lsp_position(
+        content,
+        line_start + start + needle.len(),
+    ))
+}
+
+fn hint(position: Position, label: String, tooltip: &str) -> InlayHint {
+    InlayHint {
+        position,
+        label: InlayHintLabel::String(label),
+        kind: None,
+        text_edits: None,
+        tooltip: Some(InlayHintTooltip::String(tooltip.to_string())),
+        padding_left: Some(true),
+        padding_right: None,
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::providers::uri::file_path_to_uri;
+
+    fn hints_for(text: &str, running_balances: bool) -> Vec<(Position, String)> {
+        let dir = std::env::current_dir().unwrap();
+        let path = dir.join("hints.beancount");
+        let uri = file_path_to_uri(&path).unwrap();
+
+        let mut config = Config::new(dir);
+        config.inlay_hints.running_balances = running_balances;
+        let snapshot = LspServerStateSnapshot::from_files(config, &[(path, text)]);
+
+        let params = lsp_types::InlayHintParams {
+            work_done_progress_params: Default::default(),
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range::new(Position::new(0, 0), Position::new(u32::MAX, 0)),
+        };
+        inlay_hint(snapshot, params)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|hint| match hint.label {
+                InlayHintLabel::String(label) => (hint.position, label),
+                InlayHintLabel::LabelParts(_) => panic!("Expected a string label"),
+            })
+            .collect()
+    }
+
+    const LEDGER: &str = r#"2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Food
+2024-01-05 * "Grocer"
+  Expenses:Food  42.17 USD
+  Assets:Cash ; paid in cash
+2024-01-06 * "Market"
+  Expenses:Food  10 USD
+  Expenses:Food  5 EUR
+  Assets:Cash
+2024-01-10 balance Assets:Cash -52.17 USD ; after shopping
+"#;
+
+    #[test]
+    fn test_elided_posting_shows_residual() {
+        let hints = hints_for(LEDGER, false);
+        assert_eq!(
+            hints,
+            vec![
+                (Position::new(4, 13), "-42.17 USD".to_string()),
+                (Position::new(8, 13), "-5 EUR, -10 USD".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_positions_count_utf16_code_units() {
+        let text = r#"2024-01-01 open Assets:Café
+2024-01-01 open Expenses:Food
+2024-01-05 * "Grocer"
+  Expenses:Food  4.50 EUR
+  Assets:Café
+2024-01-10 balance Assets:Café -4.50 EUR
+"#;
+        assert_eq!(
+            hints_for(text, true),
+            vec![
+                (Position::new(4, 13), "-4.50 EUR".to_string()),
+                (Position::new(5, 40), "accumulated -4.50 EUR".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_running_balance_after_balance_directive() {
+        let hints = hints_for(LEDGER, true);
+        assert_eq!(
+            hints.last(),
+            Some(&(Position::new(9, 41), "accumulated -52.17 USD".to_string()))
+        );
+    }
+}
@@ -1,118 +1,155 @@
-use crate::document::Document;
+use crate::beancount_data::SymbolKind;
+use crate::cancellation::Cancelled;
+use crate::dispatcher::InvalidParams;
+use crate::providers::uri::file_path_to_uri;
 use crate::server::LspServerStateSnapshot;
-use crate::treesitter_utils::text_for_tree_sitter_node;
+use crate::treesitter_utils::{
+    lsp_position_to_point, lsp_range_for_node, text_for_tree_sitter_node,
+};
 use crate::utils::ToFilePath;
 use anyhow::Result;
 use lsp_types::Location;
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Arc;
-use tracing::debug;
-use tree_sitter::StreamingIterator;
+use std::sync::OnceLock;
 use tree_sitter_beancount::tree_sitter;
 
+/// The options renaming the root types every account name starts with, and
+/// the default names of those roots.
+const ACCOUNT_ROOTS: [(&str, &str); 5] = [
+    ("name_assets", "Assets"),
+    ("name_liabilities", "Liabilities"),
+    ("name_equity", "Equity"),
+    ("name_income", "Income"),
+    ("name_expenses", "Expenses"),
+];
+
+/// Pattern: "Root:Component:..." where components start with a capital letter or digit
+static ACCOUNT_NAME_REGEX: OnceLock<regex::Regex> = OnceLock::new();
+
+fn account_name_regex() -> &'static regex::Regex {
+    ACCOUNT_NAME_REGEX.get_or_init(|| {
+        regex::Regex::new(r"^[\p{Lu}\p{Nd}][\p{L}\p{Nd}-]*(:[\p{Lu}\p{Nd}][\p{L}\p{Nd}-]*)+$")
+            .expect("Failed to compile account name regex")
+    })
+}
+
 /// Provider function for `textDocument/references`.
 pub(crate) fn references(
     snapshot: LspServerStateSnapshot,
     params: lsp_types::ReferenceParams,
 ) -> Result<Option<Vec<lsp_types::Location>>> {
-    let uri = params
+    let Ok(uri) = params
         .text_document_position
         .text_document
         .uri
         .to_file_path()
-        .unwrap();
-    let line = params.text_document_position.position.line;
-    let char = params.text_document_position.position.character;
-    let forest = snapshot.forest;
-    let start = tree_sitter::Point {
-        row: line as usize,
-        column: if char == 0 {
-            char as usize
-        } else {
-            char as usize - 1
-        },
+    else {
+        return Ok(None);
     };
-    let end = tree_sitter::Point {
-        row: line as usize,
-        column: char as usize,
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&uri), snapshot.open_docs.get(&uri)) else {
+        return Ok(None);
     };
-    let Some(node) = forest
-        .get(&uri)
-        .expect("to have tree found")
-        .root_node()
-        .named_descendant_for_point_range(start, end)
+    let Some(node) = node_at_position(tree, &doc.content, params.text_document_position.position)
     else {
         return Ok(None);
     };
-    let content = snapshot.open_docs.get(&uri).unwrap().content.clone();
-    let node_text = text_for_tree_sitter_node(&content, &node);
-    let open_docs = snapshot.open_docs;
-    let locs = find_references(&forest, &open_docs, node_text);
+    let Some((kind, name)) = symbol_at(&doc.content, node) else {
+        return Ok(None);
+    };
+    let locs = find_references(&snapshot, kind, &name)?;
     Ok(Some(locs))
 }
 
+/// Provider function for `textDocument/prepareRename`.
+///
+/// Only accounts, currencies, tags, links and payees can be renamed; dates,
+/// numbers and keywords resolve to nothing.
+pub(crate) fn prepare_rename(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::TextDocumentPositionParams,
+) -> Result<Option<lsp_types::PrepareRenameResponse>> {
+    let Ok(uri) = params.text_document.uri.to_file_path() else {
+        return Ok(None);
+    };
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&uri), snapshot.open_docs.get(&uri)) else {
+        return Ok(None);
+    };
+    let Some(node) = node_at_position(tree, &doc.content, params.position) else {
+        return Ok(None);
+    };
+    Ok(symbol_at(&doc.content, node).map(|(_, name)| {
+        lsp_types::PrepareRenameResponse::RangeWithPlaceholder {
+            range: lsp_range_for_node(&doc.content, &node),
+            placeholder: name,
+        }
+    }))
+}
+
 /// Provider function for `textDocument/rename`.
 pub(crate) fn rename(
     snapshot: LspServerStateSnapshot,
     params: lsp_types::RenameParams,
 ) -> Result<Option<lsp_types::WorkspaceEdit>> {
-    let uri = &params
+    let Ok(uri) = params
         .text_document_position
         .text_document
         .uri
         .to_file_path()
-        .unwrap();
-    let line = &params.text_document_position.position.line;
-    let char = &params.text_document_position.position.character;
-    let forest = snapshot.forest;
-    let _tree = forest.get(uri).unwrap();
-    let open_docs = snapshot.open_docs;
-    let doc = open_docs.get(uri).unwrap();
-    let content = doc.clone().content;
-    let start = tree_sitter::Point {
-        row: *line as usize,
-        column: if *char == 0 {
-            *char as usize
-        } else {
-            *char as usize - 1
-        },
+    else {
+        return Ok(None);
     };
-    let end = tree_sitter::Point {
-        row: *line as usize,
-        column: *char as usize,
+    let (Some(tree), Some(doc)) = (snapshot.forest.get(&uri), snapshot.open_docs.get(&uri)) else {
+        return Ok(None);
     };
-    let Some(node) = forest
-        .get(uri)
-        .expect("to have tree found")
-        .root_node()
-        .named_descendant_for_point_range(start, end)
+    let Some(node) = node_at_position(tree, &doc.content, params.text_document_position.position)
     else {
         return Ok(None);
     };
-    let node_text = text_for_tree_sitter_node(&content, &node);
-    let locs = find_references(&forest, &open_docs, node_text);
-    let new_name = params.new_name;
+    let Some((kind, name)) = symbol_at(&doc.content, node) else {
+        return Ok(None);
+    };
+    let new_name = renamed_text(kind, &params.new_name);
+    if kind == SymbolKind::Account {
+        validate_account_name(&new_name, &account_roots(&snapshot))?;
+    }
 
-    // Group locations by URI string to avoid mutable key type warning
-    let mut grouped_locs: std::collections::HashMap<String, Vec<lsp_types::Location>> =
+    // Sub-accounts keep the part of their name below the renamed account
+    let renamed: Vec<(String, String)> =
+        if kind == SymbolKind::Account && snapshot.config.rename.hierarchical_accounts {
+            let prefix = format!("{name}:");
+            snapshot
+                .index
+                .names(SymbolKind::Account)
+                .into_iter()
+                .filter(|(account, _)| *account == name || account.starts_with(&prefix))
+                .map(|(account, _)| {
+                    (
+                        account.to_string(),
+                        format!("{new_name}{}", &account[name.len()..]),
+                    )
+                })
+                .collect()
+        } else {
+            vec![(name, new_name)]
+        };
+
+    // Group edits by URI string to avoid mutable key type warning
+    let mut grouped_edits: std::collections::HashMap<String, Vec<lsp_types::TextEdit>> =
         std::collections::HashMap::new();
-    for loc in locs {
-        grouped_locs
-            .entry(loc.uri.to_string())
-            .or_default()
-            .push(loc);
+    for (old_name, new_name) in renamed {
+        for loc in find_references(&snapshot, kind, &old_name)? {
+            grouped_edits
+                .entry(loc.uri.to_string())
+                .or_default()
+                .push(lsp_types::TextEdit::new(loc.range, new_name.clone()));
+        }
     }
 
     #[allow(clippy::mutable_key_type)]
     let mut changes = std::collections::HashMap::new();
-    for (uri_str, locations) in grouped_locs {
+    for (uri_str, mut edits) in grouped_edits {
         let uri = lsp_types::Uri::from_str(&uri_str).unwrap();
-        let mut edits: Vec<_> = locations
-            .into_iter()
-            .map(|l| lsp_types::TextEdit::new(l.range, new_name.clone()))
-            .collect();
         // Send edits ordered from the back so we do not invalidate following positions.
         edits.sort_by_key(|edit| edit.range.start);
         edits.reverse();
@@ -121,85 +158,459 @@ pub(crate) fn rename(
     Ok(Some(lsp_types::WorkspaceEdit::new(changes)))
 }
 
-/// Find all references to a given text in the project using tree-sitter queries.
-fn find_references(
-    forest: &HashMap<PathBuf, Arc<tree_sitter::Tree>>,
-    open_docs: &HashMap<PathBuf, Document>,
-    node_text: String,
-) -> Vec<lsp_types::Location> {
-    forest
+/// Find the smallest named node touching the cursor position.
+///
+/// The character before the cursor is included so that a cursor placed right
+/// after a word still resolves to it.
+pub(crate) fn node_at_position<'a>(
+    tree: &'a tree_sitter::Tree,
+    content: &ropey::Rope,
+    position: lsp_types::Position,
+) -> Option<tree_sitter::Node<'a>> {
+    let before = lsp_types::Position::new(position.line, position.character.saturating_sub(1));
+    let start = lsp_position_to_point(content, before);
+    let end = lsp_position_to_point(content, position);
+    tree.root_node()
+        .named_descendant_for_point_range(start, end)
+}
+
+/// The kind and name of the account, currency, tag, link or payee at `node`.
+///
+/// The grammar labels the string of a transaction without payee as its
+/// narration; the index records that string as the payee.
+fn symbol_at(content: &ropey::Rope, node: tree_sitter::Node) -> Option<(SymbolKind, String)> {
+    let kind = match node.kind() {
+        "account" => SymbolKind::Account,
+        "currency" => SymbolKind::Currency,
+        "tag" => SymbolKind::Tag,
+        "link" => SymbolKind::Link,
+        "payee" => SymbolKind::Payee,
+        "narration"
+            if node
+                .parent()
+                .is_some_and(|txn| txn.child_by_field_name("payee").is_none()) =>
+        {
+            SymbolKind::Payee
+        }
+        _ => return None,
+    };
+    let name = text_for_tree_sitter_node(content, &node).trim().to_string();
+    Some((kind, name))
+}
+
+/// The text replacing each occurrence, adding the `#`, `^` or quotes the
+/// new name was typed without.
+fn renamed_text(kind: SymbolKind, new_name: &str) -> String {
+    let new_name = new_name.trim();
+    match kind {
+        SymbolKind::Tag if !new_name.starts_with('#') => format!("#{new_name}"),
+        SymbolKind::Link if !new_name.starts_with('^') => format!("^{new_name}"),
+        SymbolKind::Payee
+            if !(new_name.len() >= 2 && new_name.starts_with('"') && new_name.ends_with('"')) =>
+        {
+            format!("\"{}\"", new_name.replace('"', "\\\""))
+        }
+        _ => new_name.to_string(),
+    }
+}
+
+/// The names of the root types, as set by the `name_*` options of the workspace.
+fn account_roots(snapshot: &LspServerStateSnapshot) -> Vec<String> {
+    let options: HashMap<&str, &str> = snapshot
+        .beancount_data
+        .values()
+        .flat_map(|data| data.options())
+        .collect();
+    ACCOUNT_ROOTS
         .iter()
-        .flat_map(|(url, tree)| {
-            let query = match tree_sitter::Query::new(
-                &tree_sitter_beancount::language(),
-                "(account)@account",
-            ) {
-                Ok(q) => q,
-                Err(_e) => return vec![],
-            };
-            let capture_account = query
-                .capture_index_for_name("account")
-                .expect("account should be captured");
-            let text = if open_docs.get(url).is_some() {
-                open_docs.get(url).unwrap().text().to_string()
-            } else {
-                match std::fs::read_to_string(url) {
-                    Ok(content) => content,
-                    Err(_) => {
-                        // If file read fails, return empty results
-                        debug!("Failed to read file: {:?}", url);
-                        return vec![];
-                    }
-                }
-            };
-            let source = text.as_bytes();
-            {
-                let mut query_cursor = tree_sitter::QueryCursor::new();
-                let mut matches = query_cursor.matches(&query, tree.root_node(), source);
-                let mut results = Vec::new();
-                while let Some(m) = matches.next() {
-                    if let Some(node) = m.nodes_for_capture_index(capture_account).next() {
-                        let m_text = node.utf8_text(source).expect("");
-                        if m_text == node_text {
-                            results.push((url.clone(), node));
-                        }
-                    }
-                }
-                results
-            }
-        })
-        .map(|(url, node): (PathBuf, tree_sitter::Node)| {
-            let range = node.range();
-            Location::new(
-                {
-                    // Handle cross-platform file URI creation
-                    let file_path_str = url.to_str().unwrap();
-                    let uri_str = if cfg!(windows)
-                        && file_path_str.len() > 1
-                        && file_path_str.chars().nth(1) == Some(':')
-                    {
-                        // Windows absolute path like "C:\path"
-                        format!("file:///{}", file_path_str.replace('\\', "/"))
-                    } else if cfg!(windows) && file_path_str.starts_with('/') {
-                        // Unix-style path on Windows, convert to Windows style
-                        format!("file:///C:{}", file_path_str.replace('\\', "/"))
-                    } else {
-                        // Unix path or other platforms
-                        format!("file://{file_path_str}")
-                    };
-                    lsp_types::Uri::from_str(&uri_str).unwrap()
-                },
-                lsp_types::Range {
-                    start: lsp_types::Position {
-                        line: range.start_point.row as u32,
-                        character: range.start_point.column as u32,
-                    },
-                    end: lsp_types::Position {
-                        line: range.end_point.row as u32,
-                        character: range.end_point.column as u32,
-                    },
-                },
+        .map(|(option, default)| options.get(option).unwrap_or(default).to_string())
+        .collect()
+}
+
+/// Reject account names beancount would not parse.
+fn validate_account_name(account: &str, roots: &[String]) -> Result<(), InvalidParams> {
+    let root = account.split(':').next().unwrap_or_default();
+    if !roots.iter().any(|name| name == root) {
+        return Err(InvalidParams(format!(
+            "`{account}` must start with one of {}",
+            roots.join(", ")
+        )));
+    }
+    if !account_name_regex().is_match(account) {
+        return Err(InvalidParams(format!(
+            "`{account}` is not a valid account name: components must start with a capital letter or digit and contain only letters, digits and dashes"
+        )));
+    }
+    Ok(())
+}
+
+/// Find all references to a symbol in the workspace.
+fn find_references(
+    snapshot: &LspServerStateSnapshot,
+    kind: SymbolKind,
+    name: &str,
+) -> Result<Vec<lsp_types::Location>, Cancelled> {
+    let occurrences =
+        snapshot
+            .index
+            .occurrences(&snapshot.beancount_data, kind, name, &snapshot.cancellation)?;
+    Ok(occurrences
+        .into_iter()
+        .filter_map(|(path, symbol)| Some(Location::new(file_path_to_uri(path)?, symbol.range)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::path::PathBuf;
+
+    const MAIN: &str = r#"2024-01-01 open Assets:Cash USD
+2024-01-01 commodity EUR
+2024-01-02 price EUR 1.10 USD
+2024-01-03 * "Grocer" "Weekly shop" #food ^receipt-1
+  Expenses:Food  10 EUR {1.10 USD}
+  Assets:Cash
+2024-01-04 * "Grocer"
+  Expenses:Food  5 USD
+  Assets:Cash
+"#;
+
+    const OTHER: &str = r#"2024-02-01 * "Grocer" "Again" #food
+  Expenses:Food  5 USD
+  Assets:Cash
+2024-02-02 * "Bakery" "Bread" ^receipt-1
+  Expenses:Food  2 USD
+  Assets:Cash
+2024-02-03 * "Bakery" "Cake"
+  Expenses:Food:Sweets  4 USD
+  Assets:Cash
+"#;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::current_dir().unwrap().join(name)
+    }
+
+    fn snapshot() -> LspServerStateSnapshot {
+        LspServerStateSnapshot::from_files(
+            Config::new(path("main.beancount")),
+            &[
+                (path("main.beancount"), MAIN),
+                (path("other.beancount"), OTHER),
+            ],
+        )
+    }
+
+    fn position(line: u32, character: u32) -> lsp_types::TextDocumentPositionParams {
+        lsp_types::TextDocumentPositionParams {
+            text_document: lsp_types::TextDocumentIdentifier {
+                uri: file_path_to_uri(&path("main.beancount")).unwrap(),
+            },
+            position: lsp_types::Position::new(line, character),
+        }
+    }
+
+    /// (file name, line, start character) of every reference.
+    fn references_at(line: u32, character: u32) -> Vec<(String, u32, u32)> {
+        let params = lsp_types::ReferenceParams {
+            text_document_position: position(line, character),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: lsp_types::ReferenceContext {
+                include_declaration: true,
+            },
+        };
+        references(snapshot(), params)
+            .unwrap()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|location| {
+                let name = location
+                    .uri
+                    .as_str()
+                    .rsplit('/')
+                    .next()
+                    .unwrap()
+                    .to_string();
+                (
+                    name,
+                    location.range.start.line,
+                    location.range.start.character,
+                )
+            })
+            .collect()
+    }
+
+    fn rename_in(
+        snapshot: LspServerStateSnapshot,
+        line: u32,
+        character: u32,
+        new_name: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let params = lsp_types::RenameParams {
+            text_document_position: position(line, character),
+            new_name: new_name.to_string(),
+            work_done_progress_params: Default::default(),
+        };
+        let edit = rename(snapshot, params)?.unwrap();
+        let mut edits: Vec<(String, String)> = edit
+            .changes
+            .unwrap()
+            .into_iter()
+            .flat_map(|(uri, edits)| {
+                let name = uri.as_str().rsplit('/').next().unwrap().to_string();
+                edits
+                    .into_iter()
+                    .map(move |edit| (name.clone(), edit.new_text))
+            })
+            .collect();
+        edits.sort();
+        Ok(edits)
+    }
+
+    fn rename_at(line: u32, character: u32, new_name: &str) -> Vec<(String, String)> {
+        rename_in(snapshot(), line, character, new_name).unwrap()
+    }
+
+    #[test]
+    fn test_account_references() {
+        assert_eq!(
+            references_at(0, 20),
+            vec![
+                ("main.beancount".to_string(), 0, 16),
+                ("main.beancount".to_string(), 5, 2),
+                ("main.beancount".to_string(), 8, 2),
+                ("other.beancount".to_string(), 2, 2),
+                ("other.beancount".to_string(), 5, 2),
+                ("other.beancount".to_string(), 8, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_currency_references_include_price_commodity_and_cost() {
+        let refs = references_at(1, 22);
+        let lines: Vec<u32> = refs
+            .iter()
+            .filter(|(file, ..)| file == "main.beancount")
+            .map(|(_, line, _)| *line)
+            .collect();
+        assert_eq!(lines, vec![1, 2, 4]);
+
+        // The cost currency is USD
+        let usd = references_at(4, 31);
+        assert!(
+            usd.contains(&("main.beancount".to_string(), 0, 28)),
+            "{usd:?}"
+        );
+        assert!(
+            usd.contains(&("main.beancount".to_string(), 2, 26)),
+            "{usd:?}"
+        );
+        assert_eq!(usd.len(), 7, "{usd:?}");
+    }
+
+    #[test]
+    fn test_tag_link_and_payee_references() {
+        assert_eq!(
+            references_at(3, 39),
+            vec![
+                ("main.beancount".to_string(), 3, 36),
+                ("other.beancount".to_string(), 0, 30),
+            ]
+        );
+        assert_eq!(
+            references_at(3, 44),
+            vec![
+                ("main.beancount".to_string(), 3, 42),
+                ("other.beancount".to_string(), 3, 30),
+            ]
+        );
+        assert_eq!(
+            references_at(3, 15),
+            vec![
+                ("main.beancount".to_string(), 3, 13),
+                ("main.beancount".to_string(), 6, 13),
+                ("other.beancount".to_string(), 0, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancelled_search_stops() {
+        let snapshot = snapshot();
+        snapshot.cancellation.cancel();
+        let params = lsp_types::ReferenceParams {
+            text_document_position: position(0, 20),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: lsp_types::ReferenceContext {
+                include_declaration: true,
+            },
+        };
+        let error = references(snapshot, params).unwrap_err();
+        assert!(error.is::<Cancelled>());
+    }
+
+    #[test]
+    fn test_positions_count_utf16_code_units() {
+        let text = "2024-03-01 * \"Crème brûlée 🍮\" \"Dessert\" #sweet\n  Expenses:Food  5 USD\n  Assets:Cash\n";
+        let snapshot = LspServerStateSnapshot::from_files(
+            Config::new(path("main.beancount")),
+            &[(path("main.beancount"), text)],
+        );
+        let params = lsp_types::ReferenceParams {
+            text_document_position: position(0, 43),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: lsp_types::ReferenceContext {
+                include_declaration: true,
+            },
+        };
+        let locations = references(snapshot, params).unwrap().unwrap();
+        assert_eq!(
+            locations[0].range,
+            lsp_types::Range::new(
+                lsp_types::Position::new(0, 41),
+                lsp_types::Position::new(0, 47)
             )
-        })
-        .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_closed_document_has_no_references_or_rename() {
+        let snapshot = LspServerStateSnapshot::new(Config::new(path("main.beancount")));
+        let params = lsp_types::ReferenceParams {
+            text_document_position: position(0, 20),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: lsp_types::ReferenceContext {
+                include_declaration: true,
+            },
+        };
+        assert!(references(snapshot.clone(), params).unwrap().is_none());
+        let params = lsp_types::RenameParams {
+            text_document_position: position(0, 20),
+            new_name: "Assets:Wallet".to_string(),
+            work_done_progress_params: Default::default(),
+        };
+        assert!(rename(snapshot, params).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_narration_with_payee_has_no_references() {
+        assert!(references_at(3, 25).is_empty());
+    }
+
+    #[test]
+    fn test_rename_tag_and_payee_adds_missing_prefix_and_quotes() {
+        assert_eq!(
+            rename_at(3, 39, "groceries"),
+            vec![
+                ("main.beancount".to_string(), "#groceries".to_string()),
+                ("other.beancount".to_string(), "#groceries".to_string()),
+            ]
+        );
+        assert_eq!(
+            rename_at(6, 15, "Corner Shop"),
+            vec![
+                ("main.beancount".to_string(), "\"Corner Shop\"".to_string()),
+                ("main.beancount".to_string(), "\"Corner Shop\"".to_string()),
+                ("other.beancount".to_string(), "\"Corner Shop\"".to_string()),
+            ]
+        );
+        assert_eq!(
+            rename_at(3, 44, "^invoice-1"),
+            vec![
+                ("main.beancount".to_string(), "^invoice-1".to_string()),
+                ("other.beancount".to_string(), "^invoice-1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rename_tag_leaves_same_named_accounts_alone() {
+        let edits = rename_at(3, 39, "#Cash");
+        assert!(edits.iter().all(|(_, text)| text == "#Cash"));
+        assert_eq!(edits.len(), 2);
+    }
+
+    #[test]
+    fn test_prepare_rename_rejects_dates_numbers_and_keywords() {
+        let prepare =
+            |line, character| prepare_rename(snapshot(), position(line, character)).unwrap();
+        assert_eq!(prepare(3, 3), None);
+        assert_eq!(prepare(4, 18), None);
+        assert_eq!(prepare(0, 12), None);
+        assert_eq!(
+            prepare(0, 20),
+            Some(lsp_types::PrepareRenameResponse::RangeWithPlaceholder {
+                range: lsp_types::Range::new(
+                    lsp_types::Position::new(0, 16),
+                    lsp_types::Position::new(0, 27)
+                ),
+                placeholder: "Assets:Cash".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_rename_validates_account_names() {
+        let error = rename_in(snapshot(), 0, 20, "Cash:Wallet").unwrap_err();
+        assert!(error.is::<InvalidParams>());
+        assert!(
+            error.to_string().contains("must start with one of"),
+            "{error}"
+        );
+        let error = rename_in(snapshot(), 0, 20, "Assets:wallet").unwrap_err();
+        assert!(
+            error.to_string().contains("not a valid account name"),
+            "{error}"
+        );
+        let error = rename_in(snapshot(), 0, 20, "Assets").unwrap_err();
+        assert!(
+            error.to_string().contains("not a valid account name"),
+            "{error}"
+        );
+        assert!(rename_in(snapshot(), 0, 20, "Assets:Wallet-2").is_ok());
+    }
+
+    #[test]
+    fn test_rename_uses_root_names_from_options() {
+        let snapshot = LspServerStateSnapshot::from_files(
+            Config::new(path("main.beancount")),
+            &[
+                (path("main.beancount"), MAIN),
+                (
+                    path("options.beancount"),
+                    "option \"name_assets\" \"Aktiva\"\n",
+                ),
+            ],
+        );
+        let error = rename_in(snapshot.clone(), 0, 20, "Assets:Wallet").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("one of Aktiva, Liabilities, Equity, Income, Expenses"),
+            "{error}"
+        );
+        assert!(rename_in(snapshot, 0, 20, "Aktiva:Wallet").is_ok());
+    }
+
+    #[test]
+    fn test_hierarchical_rename_renames_sub_accounts() {
+        let flat = rename_at(4, 5, "Expenses:Groceries");
+        assert!(flat.iter().all(|(_, text)| text == "Expenses:Groceries"));
+        assert_eq!(flat.len(), 4);
+
+        let mut snapshot = snapshot();
+        snapshot.config.rename.hierarchical_accounts = true;
+        let edits = rename_in(snapshot, 4, 5, "Expenses:Groceries").unwrap();
+        assert_eq!(edits.len(), 5);
+        assert!(edits.contains(&(
+            "other.beancount".to_string(),
+            "Expenses:Groceries:Sweets".to_string()
+        )));
+    }
 }
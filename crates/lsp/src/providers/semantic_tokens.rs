@@ -1,14 +1,21 @@
+use crate::ledger::parse_date;
 use crate::server::LspServerStateSnapshot;
 use crate::utils::ToFilePath;
 use anyhow::Result;
+use chrono::NaiveDate;
 use lsp_types::{
-    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensLegend,
-    SemanticTokensParams, SemanticTokensResult,
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensDelta,
+    SemanticTokensDeltaParams, SemanticTokensEdit, SemanticTokensFullDeltaResult,
+    SemanticTokensLegend, SemanticTokensParams, SemanticTokensRangeParams,
+    SemanticTokensRangeResult, SemanticTokensResult,
 };
 use ropey::Rope;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::path::PathBuf;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use strum::IntoEnumIterator;
 use tree_sitter_beancount::NodeKind;
 use tree_sitter_beancount::tree_sitter::{self, Node};
@@ -29,6 +36,7 @@ enum TokenKind {
     Parameter,
     Property,
     Class,
+    Namespace,
 }
 
 fn token_types() -> Vec<SemanticTokenType> {
@@ -51,13 +59,69 @@ fn token_type(kind: TokenKind) -> SemanticTokenType {
         TokenKind::Parameter => SemanticTokenType::PARAMETER,
         TokenKind::Property => SemanticTokenType::PROPERTY,
         TokenKind::Class => SemanticTokenType::CLASS,
+        TokenKind::Namespace => SemanticTokenType::NAMESPACE,
     }
 }
 
-// We currently do not expose token modifiers; keep list empty for a minimal implementation.
-const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[];
+#[repr(u8)]
+#[derive(strum_macros::EnumIter, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum TokenModifier {
+    /// Account opened by this `open` directive.
+    Declaration,
+    /// Account used after its `close` date.
+    Deprecated,
+    /// Token of an entry generated by beancount (`P`, `S`, `T`, `C`, `U`, `R`, `M` flags).
+    Readonly,
+    Assets,
+    Liabilities,
+    Equity,
+    Income,
+    Expenses,
+    Positive,
+    Negative,
+    /// Token of a `!` transaction or posting.
+    Flagged,
+}
 
-#[derive(Debug)]
+fn token_modifiers() -> Vec<SemanticTokenModifier> {
+    TokenModifier::iter().map(token_modifier).collect()
+}
+
+fn modifier_bit(modifier: TokenModifier) -> u32 {
+    1 << (modifier as u32)
+}
+
+fn token_modifier(modifier: TokenModifier) -> SemanticTokenModifier {
+    match modifier {
+        TokenModifier::Declaration => SemanticTokenModifier::DECLARATION,
+        TokenModifier::Deprecated => SemanticTokenModifier::DEPRECATED,
+        TokenModifier::Readonly => SemanticTokenModifier::READONLY,
+        TokenModifier::Assets => SemanticTokenModifier::new("assets"),
+        TokenModifier::Liabilities => SemanticTokenModifier::new("liabilities"),
+        TokenModifier::Equity => SemanticTokenModifier::new("equity"),
+        TokenModifier::Income => SemanticTokenModifier::new("income"),
+        TokenModifier::Expenses => SemanticTokenModifier::new("expenses"),
+        TokenModifier::Positive => SemanticTokenModifier::new("positive"),
+        TokenModifier::Negative => SemanticTokenModifier::new("negative"),
+        TokenModifier::Flagged => SemanticTokenModifier::new("flagged"),
+    }
+}
+
+/// Flags beancount puts on the entries it generates itself.
+const GENERATED_FLAGS: &str = "PSTCURM";
+
+/// What tokens need to know about the entry they belong to.
+#[derive(Clone, Copy)]
+struct TokenContext<'a> {
+    /// Earliest close date of every closed account in the workspace.
+    closes: &'a HashMap<String, NaiveDate>,
+    /// Date of the enclosing entry.
+    date: Option<NaiveDate>,
+    /// Modifiers shared by every token of the enclosing entry or posting.
+    modifiers: u32,
+}
+
+#[derive(Debug, Clone)]
 struct RawToken {
     line: u32,
     start: u32,
@@ -70,7 +134,73 @@ struct RawToken {
 pub(crate) fn legend() -> SemanticTokensLegend {
     SemanticTokensLegend {
         token_types: token_types(),
-        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+        token_modifiers: token_modifiers(),
+    }
+}
+
+/// Tokens last returned for each document, so `full/delta` requests can
+/// answer with edits and only walk the entries an edit changed.
+#[derive(Debug, Default)]
+pub(crate) struct SemanticTokensCache {
+    next_result_id: u64,
+    documents: HashMap<PathBuf, DocumentTokens>,
+}
+
+#[derive(Debug)]
+struct DocumentTokens {
+    /// Tree the server currently holds for the document.
+    current: Arc<tree_sitter::Tree>,
+    cached: Option<CachedTokens>,
+}
+
+#[derive(Debug)]
+struct CachedTokens {
+    result_id: String,
+    /// Tree the tokens were computed from, with every later edit applied so
+    /// it can be compared with the current tree.
+    tree: tree_sitter::Tree,
+    /// Whether `tree` was edited since the tokens were computed.
+    edited: bool,
+    /// Close dates the `deprecated` modifiers were computed from.
+    closes: HashMap<String, NaiveDate>,
+    /// Tokens of each top-level node of `tree`, with its start row.
+    entries: Vec<EntryTokens>,
+    data: Arc<Vec<SemanticToken>>,
+}
+
+type EntryTokens = (usize, Arc<Vec<RawToken>>);
+
+impl SemanticTokensCache {
+    /// Start tracking a document opened with `tree`.
+    pub fn open(&mut self, path: PathBuf, tree: Arc<tree_sitter::Tree>) {
+        self.documents.insert(
+            path,
+            DocumentTokens {
+                current: tree,
+                cached: None,
+            },
+        );
+    }
+
+    /// Record that `edit` turned the document into `tree`.
+    pub fn edit(
+        &mut self,
+        path: &Path,
+        edit: &tree_sitter::InputEdit,
+        tree: Arc<tree_sitter::Tree>,
+    ) {
+        if let Some(document) = self.documents.get_mut(path) {
+            if let Some(cached) = &mut document.cached {
+                cached.tree.edit(edit);
+                cached.edited = true;
+            }
+            document.current = tree;
+        }
+    }
+
+    /// Forget the tokens of a closed document.
+    pub fn remove(&mut self, path: &Path) {
+        self.documents.remove(path);
     }
 }
 
@@ -79,32 +209,231 @@ pub(crate) fn semantic_tokens_full(
     snapshot: LspServerStateSnapshot,
     params: SemanticTokensParams,
 ) -> Result<Option<SemanticTokensResult>> {
-    let uri_path: PathBuf = match params.text_document.uri.to_file_path() {
-        Ok(path) => path,
-        Err(_) => return Ok(None),
+    let Ok(path) = params.text_document.uri.to_file_path() else {
+        return Ok(None);
     };
+    Ok(document_tokens(&snapshot, &path)?.map(|(result_id, data)| {
+        SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: Some(result_id),
+            data: data.to_vec(),
+        })
+    }))
+}
 
-    let tree: &tree_sitter::Tree = match snapshot.forest.get(&uri_path) {
-        Some(tree) => tree,
-        None => return Ok(None),
+/// Handle `textDocument/semanticTokens/full/delta`.
+///
+/// Answers with the edits from the tokens sent under `previous_result_id`,
+/// or with all tokens when those are no longer cached.
+pub(crate) fn semantic_tokens_full_delta(
+    snapshot: LspServerStateSnapshot,
+    params: SemanticTokensDeltaParams,
+) -> Result<Option<SemanticTokensFullDeltaResult>> {
+    let Ok(path) = params.text_document.uri.to_file_path() else {
+        return Ok(None);
     };
+    let previous = snapshot
+        .semantic_tokens
+        .lock()
+        .unwrap()
+        .documents
+        .get(&path)
+        .and_then(|document| document.cached.as_ref())
+        .filter(|cached| cached.result_id == params.previous_result_id)
+        .map(|cached| cached.data.clone());
 
-    let document = match snapshot.open_docs.get(&uri_path) {
-        Some(doc) => doc.clone(),
-        None => return Ok(None),
+    let Some((result_id, data)) = document_tokens(&snapshot, &path)? else {
+        return Ok(None);
     };
-    let content: Rope = document.content;
+    Ok(Some(match previous {
+        Some(previous) => SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+            result_id: Some(result_id),
+            edits: token_edits(&previous, &data),
+        }),
+        None => SemanticTokensFullDeltaResult::Tokens(SemanticTokens {
+            result_id: Some(result_id),
+            data: data.to_vec(),
+        }),
+    }))
+}
 
-    let mut raw_tokens = Vec::new();
-    collect_tokens(tree.root_node(), &content, &mut raw_tokens);
+/// Handle `textDocument/semanticTokens/range`.
+///
+/// Only walks the entries overlapping the requested lines, so the visible
+/// part of a large document highlights without tokenizing all of it.
+pub(crate) fn semantic_tokens_range(
+    snapshot: LspServerStateSnapshot,
+    params: SemanticTokensRangeParams,
+) -> Result<Option<SemanticTokensRangeResult>> {
+    let Ok(path) = params.text_document.uri.to_file_path() else {
+        return Ok(None);
+    };
+    let (Some(tree), Some(document)) = (snapshot.forest.get(&path), snapshot.open_docs.get(&path))
+    else {
+        return Ok(None);
+    };
+
+    let closes = snapshot.index.close_dates();
+    let lines = params.range.start.line as usize..=params.range.end.line as usize;
+    let raw_tokens = tokenize(&snapshot, tree, &document.content, &closes, Some(lines))?;
+    Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+        result_id: None,
+        data: encode(raw_tokens),
+    })))
+}
+
+/// Tokens of a whole document and their result id.
+///
+/// The cached tokens are returned as is while neither the tree nor the close
+/// dates changed, and after an edit only the entries in the changed ranges
+/// are walked again. Tokens of a snapshot older than the current tree are
+/// not cached.
+fn document_tokens(
+    snapshot: &LspServerStateSnapshot,
+    path: &Path,
+) -> Result<Option<(String, Arc<Vec<SemanticToken>>)>> {
+    let (Some(tree), Some(document)) = (snapshot.forest.get(path), snapshot.open_docs.get(path))
+    else {
+        return Ok(None);
+    };
+    let closes = snapshot.index.close_dates();
+
+    let previous = {
+        let cache = snapshot.semantic_tokens.lock().unwrap();
+        let cached = cache
+            .documents
+            .get(path)
+            .filter(|document| Arc::ptr_eq(&document.current, tree))
+            .and_then(|document| document.cached.as_ref())
+            .filter(|cached| cached.closes == closes);
+        if let Some(cached) = cached
+            && !cached.edited
+        {
+            return Ok(Some((cached.result_id.clone(), cached.data.clone())));
+        }
+        cached.map(|cached| (cached.tree.clone(), cached.entries.clone()))
+    };
 
-    if raw_tokens.is_empty() {
-        return Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
-            result_id: None,
-            data: vec![],
-        })));
+    let entries = entry_tokens(
+        snapshot,
+        tree,
+        &document.content,
+        &closes,
+        previous.as_ref(),
+    )?;
+    let raw_tokens = entries
+        .iter()
+        .flat_map(|(_, tokens)| tokens.iter().cloned())
+        .collect();
+    let data = Arc::new(encode(raw_tokens));
+
+    let mut cache = snapshot.semantic_tokens.lock().unwrap();
+    cache.next_result_id += 1;
+    let result_id = cache.next_result_id.to_string();
+    if let Some(document) = cache.documents.get_mut(path)
+        && Arc::ptr_eq(&document.current, tree)
+    {
+        document.cached = Some(CachedTokens {
+            result_id: result_id.clone(),
+            tree: (**tree).clone(),
+            edited: false,
+            closes,
+            entries,
+            data: data.clone(),
+        });
+    }
+    Ok(Some((result_id, data)))
+}
+
+/// Tokens of each top-level node of `tree`.
+///
+/// `previous` holds an edited tree and the tokens of its top-level nodes;
+/// those outside the ranges the edits changed are reused, moved to their
+/// new line.
+fn entry_tokens(
+    snapshot: &LspServerStateSnapshot,
+    tree: &tree_sitter::Tree,
+    content: &Rope,
+    closes: &HashMap<String, NaiveDate>,
+    previous: Option<&(tree_sitter::Tree, Vec<EntryTokens>)>,
+) -> Result<Vec<EntryTokens>> {
+    let context = TokenContext {
+        closes,
+        date: None,
+        modifiers: 0,
+    };
+
+    // Unchanged nodes of the previous tree by byte range. Only nodes starting
+    // a line are reused, as their columns cannot have moved.
+    let mut reusable = HashMap::new();
+    if let Some((old_tree, old_entries)) = previous {
+        let changed: Vec<_> = old_tree.changed_ranges(tree).collect();
+        let root = old_tree.root_node();
+        let mut cursor = root.walk();
+        for (node, (row, tokens)) in root.children(&mut cursor).zip(old_entries) {
+            let overlaps_change = changed.iter().any(|range| {
+                range.start_byte < node.end_byte() && node.start_byte() < range.end_byte
+            });
+            if !node.has_changes() && !overlaps_change && node.start_position().column == 0 {
+                reusable.insert(node.byte_range(), (*row, tokens));
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    for child in root.children(&mut cursor) {
+        snapshot.cancellation.check()?;
+        let row = child.start_position().row;
+        let tokens = match reusable.get(&child.byte_range()) {
+            Some(&(old_row, tokens)) if old_row == row => tokens.clone(),
+            Some(&(old_row, tokens)) => Arc::new(
+                tokens
+                    .iter()
+                    .map(|token| RawToken {
+                        line: (token.line as usize - old_row + row) as u32,
+                        ..token.clone()
+                    })
+                    .collect(),
+            ),
+            None => {
+                let mut tokens = Vec::new();
+                collect_tokens(child, content, context, None, &mut tokens);
+                Arc::new(tokens)
+            }
+        };
+        entries.push((row, tokens));
     }
+    Ok(entries)
+}
+
+/// Collect the tokens of the entries overlapping `lines`, or of all entries.
+fn tokenize(
+    snapshot: &LspServerStateSnapshot,
+    tree: &tree_sitter::Tree,
+    content: &Rope,
+    closes: &HashMap<String, NaiveDate>,
+    lines: Option<RangeInclusive<usize>>,
+) -> Result<Vec<RawToken>> {
+    let context = TokenContext {
+        closes,
+        date: None,
+        modifiers: 0,
+    };
+
+    // Collect entry by entry so a cancelled request stops early
+    let mut raw_tokens = Vec::new();
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    for child in root.children(&mut cursor) {
+        snapshot.cancellation.check()?;
+        collect_tokens(child, content, context, lines.as_ref(), &mut raw_tokens);
+    }
+    Ok(raw_tokens)
+}
 
+/// Sort tokens and encode them relative to the previous one.
+fn encode(mut raw_tokens: Vec<RawToken>) -> Vec<SemanticToken> {
     raw_tokens.sort_by(|a, b| match a.line.cmp(&b.line) {
         Ordering::Equal => a.start.cmp(&b.start),
         other => other,
@@ -133,29 +462,145 @@ pub(crate) fn semantic_tokens_full(
         prev_line = token.line;
         prev_start = token.start;
     }
+    data
+}
 
-    Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
-        result_id: None,
-        data,
-    })))
+/// A single edit replacing the tokens between the common prefix and suffix.
+///
+/// Tokens are relative to the previous one, so an edit only changes the
+/// tokens it touches and the first token after them.
+fn token_edits(previous: &[SemanticToken], current: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    // Edit offsets count integers, and every token is encoded as five
+    const TOKEN_LEN: usize = 5;
+
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(old, new)| old == new)
+        .count();
+    if prefix == previous.len() && prefix == current.len() {
+        return vec![];
+    }
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    vec![SemanticTokensEdit {
+        start: (prefix * TOKEN_LEN) as u32,
+        delete_count: ((previous.len() - prefix - suffix) * TOKEN_LEN) as u32,
+        data: Some(current[prefix..current.len() - suffix].to_vec()),
+    }]
 }
 
-fn collect_tokens(node: Node, content: &Rope, out: &mut Vec<RawToken>) {
-    if let Some(kind) = classify_node(node.kind().into())
-        && let Some(tok) = to_semantic_token(&node, content, kind)
+fn collect_tokens(
+    node: Node,
+    content: &Rope,
+    context: TokenContext,
+    lines: Option<&RangeInclusive<usize>>,
+    out: &mut Vec<RawToken>,
+) {
+    if let Some(lines) = lines
+        && (node.end_position().row < *lines.start() || node.start_position().row > *lines.end())
     {
-        out.push(tok);
+        return;
+    }
+    let context = scope_context(node, content, context);
+
+    let node_kind: NodeKind = node.kind().into();
+    if let Some(kind) = classify_node(node_kind) {
+        let modifiers = context.modifiers | node_modifiers(node, node_kind, content, &context);
+        if let Some(tok) = to_semantic_token(&node, content, kind, modifiers) {
+            out.push(tok);
+        }
     }
 
     let mut cursor = node.walk();
     for child in node.children(&mut cursor) {
-        collect_tokens(child, content, out);
+        collect_tokens(child, content, context, lines, out);
+    }
+}
+
+/// Narrow the context when entering an entry or a posting.
+fn scope_context<'a>(node: Node, content: &Rope, context: TokenContext<'a>) -> TokenContext<'a> {
+    if node.kind() == "posting" {
+        let flag = node.child_by_field_name("optflag");
+        return TokenContext {
+            modifiers: context.modifiers | flag_modifiers(flag, content),
+            ..context
+        };
+    }
+    match node.child_by_field_name("date") {
+        Some(date) => TokenContext {
+            date: parse_date(&node_text(&date, content)),
+            modifiers: context.modifiers | flag_modifiers(node.child_by_field_name("txn"), content),
+            ..context
+        },
+        None => context,
     }
 }
 
+fn flag_modifiers(flag: Option<Node>, content: &Rope) -> u32 {
+    match flag.map(|flag| node_text(&flag, content)).as_deref() {
+        Some("!") => modifier_bit(TokenModifier::Flagged),
+        Some(flag) if flag.len() == 1 && GENERATED_FLAGS.contains(flag) => {
+            modifier_bit(TokenModifier::Readonly)
+        }
+        _ => 0,
+    }
+}
+
+/// Modifiers that depend on the token itself.
+fn node_modifiers(node: Node, kind: NodeKind, content: &Rope, context: &TokenContext) -> u32 {
+    let parent_kind = node.parent().map(|parent| parent.kind());
+    match kind {
+        NodeKind::Account => {
+            let account = node_text(&node, content);
+            let mut modifiers = match account.split(':').next() {
+                Some("Assets") => modifier_bit(TokenModifier::Assets),
+                Some("Liabilities") => modifier_bit(TokenModifier::Liabilities),
+                Some("Equity") => modifier_bit(TokenModifier::Equity),
+                Some("Income") => modifier_bit(TokenModifier::Income),
+                Some("Expenses") => modifier_bit(TokenModifier::Expenses),
+                _ => 0,
+            };
+            if parent_kind == Some("open") {
+                modifiers |= modifier_bit(TokenModifier::Declaration);
+            }
+            if parent_kind != Some("close")
+                && let (Some(closed), Some(date)) = (context.closes.get(&account), context.date)
+                && date > *closed
+            {
+                modifiers |= modifier_bit(TokenModifier::Deprecated);
+            }
+            modifiers
+        }
+        NodeKind::Number | NodeKind::Minus => {
+            // A minus sign belongs to the number only as a unary operator
+            let negated = parent_kind == Some("unary_number_expr")
+                && node
+                    .parent()
+                    .and_then(|parent| parent.child(0))
+                    .is_some_and(|first| first.kind() == "minus");
+            match (kind, negated) {
+                (_, true) => modifier_bit(TokenModifier::Negative),
+                (NodeKind::Number, false) => modifier_bit(TokenModifier::Positive),
+                _ => 0,
+            }
+        }
+        _ => 0,
+    }
+}
+
+fn node_text(node: &Node, content: &Rope) -> String {
+    crate::treesitter_utils::text_for_tree_sitter_node(content, node)
+}
+
 fn classify_node(kind: NodeKind) -> Option<TokenKind> {
     match kind {
-        NodeKind::Account => Option::None,
+        NodeKind::Account => Option::Some(TokenKind::Namespace),
 
         NodeKind::Asterisk => Option::Some(TokenKind::Operator),
         NodeKind::At => Option::Some(TokenKind::Operator),
@@ -189,7 +634,12 @@ fn classify_node(kind: NodeKind) -> Option<TokenKind> {
     }
 }
 
-fn to_semantic_token(node: &Node, content: &Rope, kind: TokenKind) -> Option<RawToken> {
+fn to_semantic_token(
+    node: &Node,
+    content: &Rope,
+    kind: TokenKind,
+    modifiers: u32,
+) -> Option<RawToken> {
     let start_byte = node.start_byte();
     let end_byte = node.end_byte();
 
@@ -214,13 +664,14 @@ fn to_semantic_token(node: &Node, content: &Rope, kind: TokenKind) -> Option<Raw
         start: u32::try_from(column_utf16).ok()?,
         length: u32::try_from(length_utf16).ok()?,
         token_type: token_index(kind),
-        modifiers_bitset: 0,
+        modifiers_bitset: modifiers,
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Mutex;
     use strum::IntoEnumIterator;
 
     #[test]
@@ -273,4 +724,406 @@ mod tests {
             );
         }
     }
+
+    type Token = (u32, u32, String, TokenKind, Vec<TokenModifier>);
+
+    /// A snapshot with `text` open, sharing `cache` with other snapshots.
+    fn snapshot(
+        text: &str,
+        cache: &Arc<Mutex<SemanticTokensCache>>,
+    ) -> (LspServerStateSnapshot, lsp_types::TextDocumentIdentifier) {
+        use crate::config::Config;
+        use std::str::FromStr;
+
+        let path = std::env::current_dir().unwrap().join("tokens.beancount");
+        let mut snapshot = LspServerStateSnapshot::new(Config::new(path.clone()));
+        snapshot.semantic_tokens = cache.clone();
+        snapshot.open_document(path.clone(), text).unwrap();
+        let uri = url::Url::from_file_path(&path).unwrap();
+        let text_document = lsp_types::TextDocumentIdentifier {
+            uri: lsp_types::Uri::from_str(uri.as_str()).unwrap(),
+        };
+        (snapshot, text_document)
+    }
+
+    fn full_of(
+        snapshot: LspServerStateSnapshot,
+        text_document: lsp_types::TextDocumentIdentifier,
+    ) -> SemanticTokens {
+        let params = SemanticTokensParams {
+            text_document,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(SemanticTokensResult::Tokens(result)) =
+            semantic_tokens_full(snapshot, params).unwrap()
+        else {
+            panic!("Expected tokens");
+        };
+        result
+    }
+
+    fn full(text: &str, cache: &Arc<Mutex<SemanticTokensCache>>) -> SemanticTokens {
+        let (snapshot, text_document) = snapshot(text, cache);
+        full_of(snapshot, text_document)
+    }
+
+    fn delta_of(
+        snapshot: LspServerStateSnapshot,
+        text_document: lsp_types::TextDocumentIdentifier,
+        previous_result_id: &str,
+    ) -> SemanticTokensFullDeltaResult {
+        let params = SemanticTokensDeltaParams {
+            text_document,
+            previous_result_id: previous_result_id.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        semantic_tokens_full_delta(snapshot, params)
+            .unwrap()
+            .expect("Expected a delta result")
+    }
+
+    /// Decoded tokens as (line, start, text, type, modifiers).
+    fn decode(text: &str, data: Vec<SemanticToken>) -> Vec<Token> {
+        let content = Rope::from_str(text);
+        let kinds: Vec<TokenKind> = TokenKind::iter().collect();
+        let (mut line, mut start) = (0, 0);
+        data.into_iter()
+            .map(|token| {
+                if token.delta_line > 0 {
+                    start = 0;
+                }
+                line += token.delta_line;
+                start += token.delta_start;
+                let line_text = content.line(line as usize).to_string();
+                let text = line_text
+                    .chars()
+                    .skip(start as usize)
+                    .take(token.length as usize)
+                    .collect();
+                let modifiers = TokenModifier::iter()
+                    .filter(|m| token.token_modifiers_bitset & modifier_bit(*m) != 0)
+                    .collect();
+                (
+                    line,
+                    start,
+                    text,
+                    kinds[token.token_type as usize],
+                    modifiers,
+                )
+            })
+            .collect()
+    }
+
+    fn tokens(text: &str) -> Vec<Token> {
+        decode(text, full(text, &Default::default()).data)
+    }
+
+    fn modifiers_of(tokens: &[Token], line: u32, text: &str) -> Vec<TokenModifier> {
+        tokens
+            .iter()
+            .find(|token| token.0 == line && token.2 == text)
+            .unwrap_or_else(|| panic!("No token {text:?} on line {line}: {tokens:?}"))
+            .4
+            .clone()
+    }
+
+    const LEDGER: &str = r#"2024-01-01 open Assets:Cash
+2024-01-01 open Expenses:Food
+2024-02-01 close Assets:Cash
+2024-01-15 * "Lunch"
+  Expenses:Food  12.50 USD
+  Assets:Cash  -12.50 USD
+2024-03-01 ! "Late lunch"
+  Expenses:Food  3 USD
+  Assets:Cash
+2024-03-02 P "(Padding inserted)"
+  Income:Salary  1 USD
+"#;
+
+    #[test]
+    fn test_accounts_have_root_and_declaration_modifiers() {
+        use TokenModifier::*;
+        let tokens = tokens(LEDGER);
+        assert_eq!(
+            tokens.iter().find(|t| t.2 == "Assets:Cash").map(|t| t.3),
+            Some(TokenKind::Namespace)
+        );
+        assert_eq!(
+            modifiers_of(&tokens, 0, "Assets:Cash"),
+            vec![Declaration, Assets]
+        );
+        assert_eq!(modifiers_of(&tokens, 4, "Expenses:Food"), vec![Expenses]);
+        assert_eq!(modifiers_of(&tokens, 2, "Assets:Cash"), vec![Assets]);
+    }
+
+    #[test]
+    fn test_closed_account_used_later_is_deprecated() {
+        use TokenModifier::*;
+        let tokens = tokens(LEDGER);
+        assert_eq!(modifiers_of(&tokens, 5, "Assets:Cash"), vec![Assets]);
+        assert_eq!(
+            modifiers_of(&tokens, 8, "Assets:Cash"),
+            vec![Deprecated, Assets, Flagged]
+        );
+    }
+
+    #[test]
+    fn test_amount_signs() {
+        use TokenModifier::*;
+        let tokens = tokens(LEDGER);
+        assert_eq!(modifiers_of(&tokens, 4, "12.50"), vec![Positive]);
+        assert_eq!(modifiers_of(&tokens, 5, "12.50"), vec![Negative]);
+        assert_eq!(modifiers_of(&tokens, 5, "-"), vec![Negative]);
+    }
+
+    #[test]
+    fn test_flagged_and_generated_entries() {
+        use TokenModifier::*;
+        let tokens = tokens(LEDGER);
+        assert_eq!(modifiers_of(&tokens, 6, "!"), vec![Flagged]);
+        assert_eq!(modifiers_of(&tokens, 6, "\"Late lunch\""), vec![Flagged]);
+        assert_eq!(modifiers_of(&tokens, 7, "3"), vec![Positive, Flagged]);
+        assert_eq!(modifiers_of(&tokens, 4, "USD"), Vec::<TokenModifier>::new());
+        assert_eq!(
+            modifiers_of(&tokens, 10, "Income:Salary"),
+            vec![Readonly, Income]
+        );
+    }
+
+    #[test]
+    fn legend_modifiers_follow_bit_order() {
+        let modifiers = legend().token_modifiers;
+        for (idx, modifier) in TokenModifier::iter().enumerate() {
+            assert_eq!(modifier_bit(modifier), 1 << idx);
+            assert_eq!(modifiers[idx], token_modifier(modifier));
+        }
+    }
+
+    #[test]
+    fn test_range_only_tokenizes_requested_lines() {
+        let (snapshot, text_document) = snapshot(LEDGER, &Default::default());
+        let params = SemanticTokensRangeParams {
+            text_document,
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(4, 0),
+                lsp_types::Position::new(5, 0),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(SemanticTokensRangeResult::Tokens(result)) =
+            semantic_tokens_range(snapshot, params).unwrap()
+        else {
+            panic!("Expected tokens");
+        };
+        assert_eq!(result.result_id, None);
+
+        let in_range: Vec<Token> = tokens(LEDGER)
+            .into_iter()
+            .filter(|token| (4..=5).contains(&token.0))
+            .collect();
+        assert!(!in_range.is_empty());
+        assert_eq!(decode(LEDGER, result.data), in_range);
+    }
+
+    #[test]
+    fn test_full_reuses_result_id_until_tree_changes() {
+        let cache = Default::default();
+        let (snapshot, text_document) = snapshot(LEDGER, &cache);
+        let first = full_of(snapshot.clone(), text_document.clone());
+        let second = full_of(snapshot, text_document);
+        assert!(first.result_id.is_some());
+        assert_eq!(second.result_id, first.result_id);
+        assert_eq!(second.data, first.data);
+
+        let reparsed = full(LEDGER, &cache);
+        assert_ne!(reparsed.result_id, first.result_id);
+        assert_eq!(reparsed.data, first.data);
+    }
+
+    #[test]
+    fn test_delta_is_empty_for_unchanged_document() {
+        let cache = Default::default();
+        let (snapshot, text_document) = snapshot(LEDGER, &cache);
+        let first = full_of(snapshot.clone(), text_document.clone());
+
+        let SemanticTokensFullDeltaResult::TokensDelta(delta) =
+            delta_of(snapshot, text_document, first.result_id.as_deref().unwrap())
+        else {
+            panic!("Expected a delta");
+        };
+        assert_eq!(delta.result_id, first.result_id);
+        assert!(delta.edits.is_empty());
+    }
+
+    #[test]
+    fn test_delta_edits_rebuild_changed_tokens() {
+        let (mut state, text_document) = open_state(LEDGER);
+        let first = full_of(state.snapshot(), text_document.clone());
+        change(&mut state, &text_document, (4, 17), (4, 26), "-7 EUR");
+        let changed = LEDGER.replace("  Expenses:Food  12.50 USD", "  Expenses:Food  -7 EUR");
+
+        let SemanticTokensFullDeltaResult::TokensDelta(delta) = delta_of(
+            state.snapshot(),
+            text_document,
+            first.result_id.as_deref().unwrap(),
+        ) else {
+            panic!("Expected a delta");
+        };
+        assert_ne!(delta.result_id, first.result_id);
+        assert_eq!(delta.edits.len(), 1);
+
+        // Apply the edit to the flattened integers, as clients do
+        let flatten = |data: &[SemanticToken]| -> Vec<u32> {
+            data.iter()
+                .flat_map(|t| {
+                    [
+                        t.delta_line,
+                        t.delta_start,
+                        t.length,
+                        t.token_type,
+                        t.token_modifiers_bitset,
+                    ]
+                })
+                .collect()
+        };
+        let edit = &delta.edits[0];
+        let mut ints = flatten(&first.data);
+        let start = edit.start as usize;
+        ints.splice(
+            start..start + edit.delete_count as usize,
+            flatten(edit.data.as_deref().unwrap()),
+        );
+        assert_eq!(ints, flatten(&full(&changed, &Default::default()).data));
+        assert!(edit.data.as_ref().unwrap().len() < first.data.len());
+    }
+
+    #[test]
+    fn test_delta_with_unknown_result_id_sends_all_tokens() {
+        let cache = Default::default();
+        let (snapshot, text_document) = snapshot(LEDGER, &cache);
+        full_of(snapshot.clone(), text_document.clone());
+        let SemanticTokensFullDeltaResult::Tokens(tokens) =
+            delta_of(snapshot, text_document, "unknown")
+        else {
+            panic!("Expected all tokens");
+        };
+        assert_eq!(tokens.data, full(LEDGER, &Default::default()).data);
+    }
+
+    /// A server state with `text` open.
+    fn open_state(
+        text: &str,
+    ) -> (
+        crate::server::LspServerState,
+        lsp_types::TextDocumentIdentifier,
+    ) {
+        use crate::config::Config;
+
+        let dir = std::env::temp_dir();
+        let path = dir.join("tokens.beancount");
+        let uri = crate::providers::uri::file_path_to_uri(&path).unwrap();
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let mut state = crate::server::LspServerState::new(
+            sender,
+            Config::new(dir),
+            lsp_types::ClientCapabilities::default(),
+        );
+        crate::providers::text_document::did_open(
+            &mut state,
+            lsp_types::DidOpenTextDocumentParams {
+                text_document: lsp_types::TextDocumentItem::new(
+                    uri.clone(),
+                    "beancount".to_string(),
+                    1,
+                    text.to_string(),
+                ),
+            },
+        )
+        .unwrap();
+        (state, lsp_types::TextDocumentIdentifier { uri })
+    }
+
+    /// Replace the text between two positions of an open document.
+    fn change(
+        state: &mut crate::server::LspServerState,
+        text_document: &lsp_types::TextDocumentIdentifier,
+        start: (u32, u32),
+        end: (u32, u32),
+        text: &str,
+    ) {
+        let change = lsp_types::TextDocumentContentChangeEvent {
+            range: Some(lsp_types::Range::new(
+                lsp_types::Position::new(start.0, start.1),
+                lsp_types::Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        };
+        crate::providers::text_document::did_change(
+            state,
+            lsp_types::DidChangeTextDocumentParams {
+                text_document: lsp_types::VersionedTextDocumentIdentifier::new(
+                    text_document.uri.clone(),
+                    2,
+                ),
+                content_changes: vec![change],
+            },
+        )
+        .unwrap();
+    }
+
+    /// Tokens cached for the only open document.
+    fn cached_entries(state: &crate::server::LspServerState) -> Vec<EntryTokens> {
+        let cache = state.semantic_tokens.lock().unwrap();
+        let document = cache.documents.values().next().unwrap();
+        document.cached.as_ref().unwrap().entries.clone()
+    }
+
+    #[test]
+    fn test_edit_reuses_tokens_outside_changed_ranges() {
+        let (mut state, text_document) = open_state(LEDGER);
+        full_of(state.snapshot(), text_document.clone());
+        let before = cached_entries(&state);
+
+        // Change an amount and add a line above the last entries
+        change(&mut state, &text_document, (4, 17), (4, 22), "-7");
+        change(&mut state, &text_document, (6, 0), (6, 0), "; moved\n");
+        let text = state.open_docs.values().next().unwrap().text().to_string();
+        let tokens = full_of(state.snapshot(), text_document);
+        assert_eq!(
+            decode(&text, tokens.data),
+            decode(&text, full(&text, &Default::default()).data)
+        );
+
+        let after = cached_entries(&state);
+        // The opens and the close before the edits are shared as is
+        for index in 0..3 {
+            assert!(Arc::ptr_eq(&before[index].1, &after[index].1));
+        }
+        // The edited transaction is walked again
+        assert!(!Arc::ptr_eq(&before[3].1, &after[3].1));
+        // The last transaction moved down a line
+        let (row, last) = after.last().unwrap();
+        assert_eq!(*row, 10);
+        assert_eq!(last[0].line, 10);
+    }
+
+    #[test]
+    fn test_stale_snapshot_does_not_replace_cached_tokens() {
+        let (mut state, text_document) = open_state(LEDGER);
+        let stale = state.snapshot();
+        change(&mut state, &text_document, (4, 17), (4, 22), "-7");
+        let current = full_of(state.snapshot(), text_document.clone());
+
+        // A request started before the edit finishes last
+        let outdated = full_of(stale, text_document.clone());
+        assert_ne!(outdated.data, current.data);
+
+        let again = full_of(state.snapshot(), text_document);
+        assert_eq!(again.result_id, current.result_id);
+        assert_eq!(again.data, current.data);
+    }
 }
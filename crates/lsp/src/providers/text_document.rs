@@ -1,23 +1,21 @@
 use crate::beancount_data::BeancountData;
-use crate::checkers::create_checker;
+use crate::checkers::resolve_checker;
 use crate::document::Document;
 use crate::providers::diagnostics;
 use crate::server::LspServerState;
 use crate::server::LspServerStateSnapshot;
 use crate::server::ProgressMsg;
 use crate::server::Task;
-use crate::to_json;
 use crate::treesitter_utils::lsp_textdocchange_to_ts_inputedit;
 use crate::utils::ToFilePath;
 use anyhow::Result;
 use crossbeam_channel::Sender;
 use glob::glob;
-use lsp_types::notification::Notification;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::debug;
 use tree_sitter_beancount::tree_sitter;
 
@@ -103,9 +101,7 @@ fn process_includes(
 
                     // Add to state
                     state.forest.insert(path.clone(), Arc::new(tree));
-                    state
-                        .beancount_data
-                        .insert(path.clone(), Arc::new(beancount_data));
+                    state.set_beancount_data(path.clone(), Arc::new(beancount_data));
 
                     debug!("Processed included file: {:?}", path);
 
@@ -153,14 +149,17 @@ pub(crate) fn did_open(
         .forest
         .entry(uri.clone())
         .or_insert_with(|| Arc::new(parser.parse(&params.text_document.text, None).unwrap()));
+    state
+        .semantic_tokens
+        .lock()
+        .unwrap()
+        .open(uri.clone(), state.forest[&uri].clone());
 
-    state.beancount_data.entry(uri.clone()).or_insert_with(|| {
+    if !state.beancount_data.contains_key(&uri) {
         let content = ropey::Rope::from_str(&params.text_document.text);
-        Arc::new(BeancountData::new(
-            state.forest.get(&uri).unwrap(),
-            &content,
-        ))
-    });
+        let beancount_data = BeancountData::new(state.forest.get(&uri).unwrap(), &content);
+        state.set_beancount_data(uri.clone(), Arc::new(beancount_data));
+    }
 
     // Process any included files from this document
     let mut processed = HashSet::new();
@@ -168,11 +167,8 @@ pub(crate) fn did_open(
         debug!("Error processing includes for {:?}: {}", uri, e);
     }
 
-    let snapshot = state.snapshot();
-    let task_sender = state.task_sender.clone();
-    state.thread_pool.execute(move || {
-        let _result = handle_diagnostics(snapshot, task_sender, params.text_document.uri);
-    });
+    let delay = Duration::from_millis(state.config.bean_check.debounce_ms);
+    state.request_check(params.text_document.uri, delay);
 
     Ok(())
 }
@@ -184,11 +180,8 @@ pub(crate) fn did_save(
 ) -> Result<()> {
     tracing::debug!("text_document::did_save");
 
-    let snapshot = state.snapshot();
-    let task_sender = state.task_sender.clone();
-    state.thread_pool.execute(move || {
-        let _result = handle_diagnostics(snapshot, task_sender, params.text_document.uri);
-    });
+    let delay = Duration::from_millis(state.config.bean_check.debounce_ms);
+    state.request_check(params.text_document.uri, delay);
 
     Ok(())
 }
@@ -201,6 +194,7 @@ pub(crate) fn did_close(
     tracing::debug!("text_document::did_close");
     let uri = params.text_document.uri.to_file_path().unwrap();
     state.open_docs.remove(&uri);
+    state.semantic_tokens.lock().unwrap().remove(&uri);
     Ok(())
 }
 
@@ -212,77 +206,107 @@ pub(crate) fn did_change(
     tracing::debug!("text_document::did_change");
     let uri = &params.text_document.uri.to_file_path().unwrap();
     tracing::debug!("text_document::did_change - requesting {:#?}", uri);
+    // Each change is expressed against the text left by the previous one, so
+    // they are applied, parsed and extracted one at a time.
+    for change in &params.content_changes {
+        apply_change(state, uri, change)?;
+    }
+
+    if state.config.bean_check.on_change {
+        let delay = Duration::from_millis(state.config.bean_check.on_change_delay_ms);
+        state.request_check(params.text_document.uri, delay);
+    }
+
+    debug!("text_document::did_change - done");
+    Ok(())
+}
+
+/// Apply one content change to an open document, its tree and its data.
+fn apply_change(
+    state: &mut LspServerState,
+    uri: &PathBuf,
+    change: &lsp_types::TextDocumentContentChangeEvent,
+) -> Result<()> {
     let doc = state.open_docs.get_mut(uri).unwrap();
 
-    tracing::debug!("text_document::did_change - convert edits");
-    let edits = params
-        .content_changes
-        .iter()
-        .map(|change| lsp_textdocchange_to_ts_inputedit(&doc.content, change))
-        .collect::<Result<Vec<_>, _>>()?;
+    tracing::debug!("text_document::did_change - convert edit");
+    let edit = lsp_textdocchange_to_ts_inputedit(&doc.content, change)?;
 
-    tracing::debug!("text_document::did_change - apply edits - document");
-    for change in &params.content_changes {
-        let text = change.text.as_str();
-        let text_bytes = text.as_bytes();
-        let text_end_byte_idx = text_bytes.len();
-
-        let range = if let Some(range) = change.range {
-            range
-        } else {
-            let start_line_idx = doc.content.byte_to_line(0);
-            let end_line_idx = doc.content.byte_to_line(text_end_byte_idx);
-
-            let start = lsp_types::Position::new(start_line_idx as u32, 0);
-            let end = lsp_types::Position::new(end_line_idx as u32, 0);
-            lsp_types::Range { start, end }
-        };
+    tracing::debug!("text_document::did_change - apply edit - document");
+    let text = change.text.as_str();
+    let text_bytes = text.as_bytes();
+    let text_end_byte_idx = text_bytes.len();
 
-        let start_row_char_idx = doc.content.line_to_char(range.start.line as usize);
-        let start_col_char_idx = doc.content.utf16_cu_to_char(range.start.character as usize);
-        let end_row_char_idx = doc.content.line_to_char(range.end.line as usize);
-        let end_col_char_idx = doc.content.utf16_cu_to_char(range.end.character as usize);
+    let range = if let Some(range) = change.range {
+        range
+    } else {
+        let start_line_idx = doc.content.byte_to_line(0);
+        let end_line_idx = doc.content.byte_to_line(text_end_byte_idx);
 
-        let start_char_idx = start_row_char_idx + start_col_char_idx;
-        let end_char_idx = end_row_char_idx + end_col_char_idx;
-        doc.content.remove(start_char_idx..end_char_idx);
+        let start = lsp_types::Position::new(start_line_idx as u32, 0);
+        let end = lsp_types::Position::new(end_line_idx as u32, 0);
+        lsp_types::Range { start, end }
+    };
 
-        if !change.text.is_empty() {
-            doc.content.insert(start_char_idx, text);
-        }
-    }
+    let start_row_char_idx = doc.content.line_to_char(range.start.line as usize);
+    let start_col_char_idx = doc.content.utf16_cu_to_char(range.start.character as usize);
+    let end_row_char_idx = doc.content.line_to_char(range.end.line as usize);
+    let end_col_char_idx = doc.content.utf16_cu_to_char(range.end.character as usize);
 
-    debug!("text_document::did_change - apply edits - tree");
-    let result = {
-        let parser = state.parsers.get_mut(uri).unwrap();
-        let old_tree_arc = state.forest.get(uri).unwrap();
-        let mut old_tree = (**old_tree_arc).clone();
+    let start_char_idx = start_row_char_idx + start_col_char_idx;
+    let end_char_idx = end_row_char_idx + end_col_char_idx;
+    doc.content.remove(start_char_idx..end_char_idx);
 
-        for edit in &edits {
-            old_tree.edit(edit);
-        }
+    if !change.text.is_empty() {
+        doc.content.insert(start_char_idx, text);
+    }
 
-        parser.parse(doc.text().to_string(), Some(&old_tree))
-    };
+    debug!("text_document::did_change - apply edit - tree");
+    let parser = state.parsers.get_mut(uri).unwrap();
+    let mut old_tree = (**state.forest.get(uri).unwrap()).clone();
+    old_tree.edit(&edit);
+
+    // Feed the parser rope chunks instead of copying the whole document
+    let content = &doc.content;
+    let result = parser.parse_with_options(
+        &mut |byte, _| {
+            if byte >= content.len_bytes() {
+                return &[] as &[u8];
+            }
+            let (chunk, chunk_byte, _, _) = content.chunk_at_byte(byte);
+            &chunk.as_bytes()[byte - chunk_byte..]
+        },
+        Some(&old_tree),
+        None,
+    );
 
     debug!("text_document::did_change - save tree");
     if let Some(tree) = result {
-        let tree_arc = Arc::new(tree);
-        *state.forest.get_mut(uri).unwrap() = tree_arc.clone();
-        *state.beancount_data.get_mut(uri).unwrap() =
-            Arc::new(BeancountData::new(&tree_arc, &doc.content));
+        // Only the entries in changed ranges need to be extracted again
+        let changed: Vec<_> = old_tree.changed_ranges(&tree).collect();
+        let beancount_data = state.beancount_data[uri].update(&tree, content, &[edit], &changed);
+        let tree = Arc::new(tree);
+        state
+            .semantic_tokens
+            .lock()
+            .unwrap()
+            .edit(uri, &edit, tree.clone());
+        state.forest.insert(uri.clone(), tree);
+        state.set_beancount_data(uri.clone(), Arc::new(beancount_data));
     }
-
-    debug!("text_document::did_change - done");
     Ok(())
 }
 
-fn handle_diagnostics(
+pub(crate) fn handle_diagnostics(
     snapshot: LspServerStateSnapshot,
     sender: Sender<Task>,
-    uri: lsp_types::Uri,
+    uris: Vec<lsp_types::Uri>,
 ) -> Result<()> {
     tracing::debug!("text_document::handle_diagnostics");
+    if snapshot.cancellation.is_cancelled() {
+        tracing::debug!("Skipping bean-check superseded by a newer one");
+        return Ok(());
+    }
 
     // Create the appropriate checker based on configuration
     tracing::debug!(
@@ -293,7 +317,7 @@ fn handle_diagnostics(
         snapshot.config.bean_check.python_script_path.display()
     );
 
-    let checker = create_checker(&snapshot.config.bean_check);
+    let checker = resolve_checker(&snapshot.config.bean_check);
     tracing::debug!(
         "Using checker: {}, available: {}",
         checker.name(),
@@ -304,45 +328,109 @@ fn handle_diagnostics(
         .send(Task::Progress(ProgressMsg::BeanCheck { done: 0, total: 1 }))
         .unwrap();
 
-    let root_journal_path = if snapshot.config.journal_root.is_some() {
-        snapshot.config.journal_root.unwrap()
-    } else {
-        // Use proper URI to file path conversion instead of string replacement
-        uri.to_file_path().unwrap_or_default()
+    // The journal covers every file; without one, each requested file is
+    // checked on its own
+    let roots: Vec<PathBuf> = match snapshot.config.journal_root {
+        Some(root) => vec![root],
+        None => uris
+            .iter()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect(),
     };
 
-    let diags = diagnostics::diagnostics(
-        snapshot.beancount_data,
-        checker.as_ref(),
-        &root_journal_path,
-    );
+    let mut diags: HashMap<PathBuf, Vec<lsp_types::Diagnostic>> = HashMap::new();
+    for root in &roots {
+        if snapshot.cancellation.is_cancelled() {
+            break;
+        }
+        let results =
+            diagnostics::diagnostics(snapshot.beancount_data.clone(), checker.as_ref(), root);
+        for (file, file_diagnostics) in results {
+            let merged = diags.entry(file).or_default();
+            for diagnostic in file_diagnostics {
+                if !merged.contains(&diagnostic) {
+                    merged.push(diagnostic);
+                }
+            }
+        }
+    }
 
     sender
         .send(Task::Progress(ProgressMsg::BeanCheck { done: 1, total: 1 }))
         .unwrap();
 
+    if snapshot.cancellation.is_cancelled() {
+        tracing::debug!("Dropping bean-check results superseded by a newer run");
+        return Ok(());
+    }
+
+    // Report every file of the forest so that fixed files get cleared
+    let mut diagnostics = diags;
     for file in snapshot.forest.keys() {
-        let diagnostics = if diags.contains_key(file) {
-            diags.get(file).unwrap().clone()
-        } else {
-            vec![]
-        };
-        sender
-            .send(Task::Notify(lsp_server::Notification {
-                method: lsp_types::notification::PublishDiagnostics::METHOD.to_owned(),
-                params: to_json(lsp_types::PublishDiagnosticsParams {
-                    uri: {
-                        let url = url::Url::from_file_path(file)
-                            .expect("Failed to convert file path to URI");
-                        lsp_types::Uri::from_str(url.as_str())
-                            .expect("Failed to parse URL as LSP URI")
-                    },
-                    diagnostics,
-                    version: None,
-                })
-                .unwrap(),
-            }))
-            .unwrap()
+        diagnostics.entry(file.clone()).or_default();
     }
+    sender.send(Task::Diagnostics(diagnostics)).unwrap();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::providers::uri::file_path_to_uri;
+
+    #[test]
+    fn test_changes_apply_in_sequence() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("main.beancount");
+        let uri = file_path_to_uri(&path).unwrap();
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let mut state = LspServerState::new(
+            sender,
+            Config::new(dir.path().to_path_buf()),
+            lsp_types::ClientCapabilities::default(),
+        );
+        did_open(
+            &mut state,
+            lsp_types::DidOpenTextDocumentParams {
+                text_document: lsp_types::TextDocumentItem::new(
+                    uri.clone(),
+                    "beancount".to_string(),
+                    1,
+                    "2024-01-01 open Assets:Cash\n".to_string(),
+                ),
+            },
+        )
+        .unwrap();
+
+        let change = |line, text: &str| lsp_types::TextDocumentContentChangeEvent {
+            range: Some(lsp_types::Range::new(
+                lsp_types::Position::new(line, 0),
+                lsp_types::Position::new(line, 0),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        };
+        // The second change refers to a line added by the first one
+        did_change(
+            &mut state,
+            lsp_types::DidChangeTextDocumentParams {
+                text_document: lsp_types::VersionedTextDocumentIdentifier::new(uri, 2),
+                content_changes: vec![
+                    change(1, "2024-01-01 open Assets:Bank\n"),
+                    change(2, "2024-01-01 open Assets:Card\n"),
+                ],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            state.open_docs[&path].text().to_string(),
+            "2024-01-01 open Assets:Cash\n2024-01-01 open Assets:Bank\n2024-01-01 open Assets:Card\n"
+        );
+        assert_eq!(
+            state.beancount_data[&path].get_accounts(),
+            vec!["Assets:Cash", "Assets:Bank", "Assets:Card"]
+        );
+    }
+}
@@ -1,2 +1,34 @@
 // This module is reserved for cross-platform URI handling utilities.
 // Functions can be added here as needed.
+
+use std::path::Path;
+use std::str::FromStr;
+
+/// Build a percent-encoded `file://` URI for an absolute path.
+///
+/// Returns `None` for relative paths, which have no file URI.
+pub(crate) fn file_path_to_uri(path: &Path) -> Option<lsp_types::Uri> {
+    let url = url::Url::from_file_path(path).ok()?;
+    lsp_types::Uri::from_str(url.as_str()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ToFilePath;
+
+    #[test]
+    fn test_special_characters_are_encoded() {
+        let path = std::env::current_dir()
+            .unwrap()
+            .join("my ledger #2.beancount");
+        let uri = file_path_to_uri(&path).unwrap();
+        assert!(uri.as_str().ends_with("/my%20ledger%20%232.beancount"));
+        assert_eq!(uri.to_file_path().unwrap(), path);
+    }
+
+    #[test]
+    fn test_relative_path_has_no_uri() {
+        assert!(file_path_to_uri(Path::new("main.beancount")).is_none());
+    }
+}
@@ -0,0 +1,447 @@
+use crate::beancount_data::BeancountData;
+use crate::forest::include_patterns;
+use crate::server::{LspServerState, ParsedFile, Task};
+use crate::to_json;
+use crate::utils::ToFilePath;
+use anyhow::Result;
+use lsp_types::{FileChangeType, FileSystemWatcher, GlobPattern};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::debug;
+use tree_sitter_beancount::tree_sitter;
+
+/// Id of the dynamic `workspace/didChangeWatchedFiles` registration.
+const WATCHER_REGISTRATION_ID: &str = "beancount-watched-files";
+
+/// Patterns watched regardless of the journal's includes.
+const DEFAULT_WATCH_PATTERNS: &[&str] = &["**/*.beancount", "**/*.bean"];
+
+/// Register file watchers for beancount files and the include globs of the
+/// forest, replacing the previous registration when the globs changed.
+pub(crate) fn register_file_watchers(state: &mut LspServerState) {
+    let dynamic_registration = state
+        .client_capabilities
+        .workspace
+        .as_ref()
+        .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+        .and_then(|watched_files| watched_files.dynamic_registration)
+        .unwrap_or(false);
+    if !dynamic_registration {
+        return;
+    }
+
+    let patterns = watch_patterns(state);
+    if state.watched_patterns.as_ref() == Some(&patterns) {
+        return;
+    }
+
+    if state.watched_patterns.is_some() {
+        state.send_request::<lsp_types::request::UnregisterCapability>(
+            lsp_types::UnregistrationParams {
+                unregisterations: vec![lsp_types::Unregistration {
+                    id: WATCHER_REGISTRATION_ID.to_string(),
+                    method: "workspace/didChangeWatchedFiles".to_string(),
+                }],
+            },
+            |_, _| {},
+        );
+    }
+
+    debug!("Watching {} file patterns", patterns.len());
+    let options = lsp_types::DidChangeWatchedFilesRegistrationOptions {
+        watchers: patterns
+            .iter()
+            .map(|pattern| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(pattern.clone()),
+                kind: None,
+            })
+            .collect(),
+    };
+    let register_options = match to_json(options) {
+        Ok(options) => options,
+        Err(e) => {
+            tracing::error!("Failed to serialize file watchers: {}", e);
+            return;
+        }
+    };
+    state.send_request::<lsp_types::request::RegisterCapability>(
+        lsp_types::RegistrationParams {
+            registrations: vec![lsp_types::Registration {
+                id: WATCHER_REGISTRATION_ID.to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: Some(register_options),
+            }],
+        },
+        |_, _| {},
+    );
+    state.watched_patterns = Some(patterns);
+}
+
+/// Default patterns plus every include glob found in the forest.
+fn watch_patterns(state: &LspServerState) -> Vec<String> {
+    let mut include_globs: Vec<String> = forest_include_patterns(state)
+        .into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    include_globs.sort();
+
+    DEFAULT_WATCH_PATTERNS
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .chain(include_globs)
+        .collect()
+}
+
+/// Include globs of every file in the forest, from their parsed data.
+fn forest_include_patterns(state: &LspServerState) -> Vec<String> {
+    state
+        .forest
+        .keys()
+        .filter_map(|path| Some(include_patterns(state.beancount_data.get(path)?, path)))
+        .flatten()
+        .collect()
+}
+
+/// Provider function for LSP `workspace/didChangeWatchedFiles`.
+///
+/// Drops deleted files right away, and re-parses files of the forest that
+/// changed on disk, plus new files matching an `include` glob, on the thread
+/// pool. Open documents are left alone since the editor owns their content.
+pub(crate) fn did_change_watched_files(
+    state: &mut LspServerState,
+    params: lsp_types::DidChangeWatchedFilesParams,
+) -> Result<()> {
+    let include_globs: Vec<glob::Pattern> = forest_include_patterns(state)
+        .iter()
+        .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+        .collect();
+
+    let mut to_parse = Vec::new();
+    let mut deleted = Vec::new();
+    for change in params.changes {
+        let Ok(path) = change.uri.to_file_path() else {
+            continue;
+        };
+        if state.open_docs.contains_key(&path) {
+            debug!("Ignoring disk change of open document {:?}", path);
+            continue;
+        }
+
+        let known = state.forest.contains_key(&path);
+        match change.typ {
+            FileChangeType::DELETED if known => deleted.push(path),
+            FileChangeType::CHANGED if known => to_parse.push(path),
+            FileChangeType::CREATED
+                if known || include_globs.iter().any(|glob| glob.matches_path(&path)) =>
+            {
+                to_parse.push(path)
+            }
+            _ => {}
+        }
+    }
+
+    if !deleted.is_empty() {
+        for path in &deleted {
+            debug!("Dropping deleted file {:?}", path);
+            state.forest.remove(path);
+            state.remove_beancount_data(path);
+            state.parsers.remove(path);
+        }
+        let removed = prune_unreachable_files(state);
+        state.handle_diagnostics_task(
+            deleted
+                .into_iter()
+                .chain(removed)
+                .map(|path| (path, vec![]))
+                .collect(),
+        );
+        register_file_watchers(state);
+        state.schedule_check();
+    }
+
+    if !to_parse.is_empty() {
+        let known: HashSet<PathBuf> = state.forest.keys().cloned().collect();
+        let sender = state.task_sender.clone();
+        state.thread_pool.execute(move || {
+            let files = parse_files(to_parse, &known);
+            let _ = sender.send(Task::FilesReloaded(files));
+        });
+    }
+    Ok(())
+}
+
+/// Store files re-parsed by [`did_change_watched_files`], then drop the files
+/// the journal no longer includes.
+pub(crate) fn files_reloaded(state: &mut LspServerState, files: Vec<ParsedFile>) {
+    for (path, tree, data) in files {
+        // The document may have been opened while it was parsed
+        if state.open_docs.contains_key(&path) {
+            continue;
+        }
+        debug!("Reloaded {:?} from disk", path);
+        state.forest.insert(path.clone(), tree);
+        state.set_beancount_data(path, data);
+    }
+
+    let removed = prune_unreachable_files(state);
+    if !removed.is_empty() {
+        state.handle_diagnostics_task(removed.into_iter().map(|path| (path, vec![])).collect());
+    }
+    register_file_watchers(state);
+    state.schedule_check();
+}
+
+/// Remove the files that are neither open nor reachable from the journal
+/// through `include` directives, returning them.
+fn prune_unreachable_files(state: &mut LspServerState) -> Vec<PathBuf> {
+    let Some(root) = state.journal_root() else {
+        return vec![];
+    };
+
+    let mut reachable = HashSet::new();
+    let mut pending = vec![root];
+    while let Some(path) = pending.pop() {
+        let Some(data) = state.beancount_data.get(&path) else {
+            continue;
+        };
+        let globs: Vec<glob::Pattern> = include_patterns(data, &path)
+            .iter()
+            .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+            .collect();
+        reachable.insert(path);
+        for included in state.forest.keys() {
+            if !reachable.contains(included) && globs.iter().any(|glob| glob.matches_path(included))
+            {
+                pending.push(included.clone());
+            }
+        }
+    }
+
+    let removed: Vec<PathBuf> = state
+        .forest
+        .keys()
+        .filter(|path| !reachable.contains(*path) && !state.open_docs.contains_key(*path))
+        .cloned()
+        .collect();
+    for path in &removed {
+        debug!("Dropping {:?}, no longer included by the journal", path);
+        state.forest.remove(path);
+        state.remove_beancount_data(path);
+        state.parsers.remove(path);
+    }
+    removed
+}
+
+/// Parse files from disk, following includes to files that are not `known` yet.
+fn parse_files(mut to_parse: Vec<PathBuf>, known: &HashSet<PathBuf>) -> Vec<ParsedFile> {
+    let mut files = Vec::new();
+    let mut seen: HashSet<PathBuf> = to_parse.iter().cloned().collect();
+    while let Some(path) = to_parse.pop() {
+        let Some((tree, data)) = parse_file(&path) else {
+            continue;
+        };
+        for pattern in include_patterns(&data, &path) {
+            let Ok(paths) = glob::glob(&pattern) else {
+                continue;
+            };
+            for included in paths.flatten() {
+                if !known.contains(&included) && seen.insert(included.clone()) {
+                    to_parse.push(included);
+                }
+            }
+        }
+        files.push((path, Arc::new(tree), Arc::new(data)));
+    }
+    files
+}
+
+fn parse_file(path: &Path) -> Option<(tree_sitter::Tree, BeancountData)> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            debug!("Failed to read {:?}: {}", path, e);
+            return None;
+        }
+    };
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_beancount::language())
+        .ok()?;
+    let tree = parser.parse(&text, None)?;
+    let content = ropey::Rope::from_str(&text);
+    let data = BeancountData::new(&tree, &content);
+    Some((tree, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::providers::uri::file_path_to_uri;
+    use crossbeam_channel::Receiver;
+    use tempfile::TempDir;
+
+    fn state_for(dir: &Path) -> (LspServerState, Receiver<lsp_server::Message>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let client_capabilities = lsp_types::ClientCapabilities {
+            workspace: Some(lsp_types::WorkspaceClientCapabilities {
+                did_change_watched_files: Some(
+                    lsp_types::DidChangeWatchedFilesClientCapabilities {
+                        dynamic_registration: Some(true),
+                        relative_pattern_support: None,
+                    },
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut config = Config::new(dir.to_path_buf());
+        config.journal_root = Some(dir.join("main.beancount"));
+        (
+            LspServerState::new(sender, config, client_capabilities),
+            receiver,
+        )
+    }
+
+    fn event(path: &Path, typ: FileChangeType) -> lsp_types::DidChangeWatchedFilesParams {
+        lsp_types::DidChangeWatchedFilesParams {
+            changes: vec![lsp_types::FileEvent {
+                uri: file_path_to_uri(path).unwrap(),
+                typ,
+            }],
+        }
+    }
+
+    fn load(state: &mut LspServerState, root: PathBuf) {
+        let files = parse_files(vec![root], &HashSet::new());
+        files_reloaded(state, files);
+    }
+
+    /// Apply the result of the background parse started by a file event.
+    fn wait_for_reload(state: &mut LspServerState) {
+        loop {
+            let task = state
+                .task_receiver
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap();
+            let reloaded = matches!(task, Task::FilesReloaded(_));
+            state.handle_task(task).unwrap();
+            if reloaded {
+                return;
+            }
+        }
+    }
+
+    fn setup() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let main = dir.path().join("main.beancount");
+        let included = dir.path().join("2024.beancount");
+        fs::create_dir(dir.path().join("imports")).unwrap();
+        fs::write(
+            &main,
+            "include \"2024.beancount\"\ninclude \"imports/*.beancount\"\n",
+        )
+        .unwrap();
+        fs::write(&included, "2024-01-01 open Assets:Cash\n").unwrap();
+        (dir, main, included)
+    }
+
+    #[test]
+    fn test_changed_file_is_reparsed() {
+        let (dir, main, included) = setup();
+        let (mut state, _receiver) = state_for(dir.path());
+        load(&mut state, main.clone());
+        assert_eq!(
+            state.beancount_data[&included].get_accounts(),
+            vec!["Assets:Cash"]
+        );
+
+        fs::write(&included, "2024-01-01 open Assets:Bank\n").unwrap();
+        did_change_watched_files(&mut state, event(&included, FileChangeType::CHANGED)).unwrap();
+        wait_for_reload(&mut state);
+
+        assert_eq!(
+            state.beancount_data[&included].get_accounts(),
+            vec!["Assets:Bank"]
+        );
+    }
+
+    #[test]
+    fn test_created_file_matching_include_glob_is_added() {
+        let (dir, main, _) = setup();
+        let (mut state, _receiver) = state_for(dir.path());
+        load(&mut state, main);
+
+        let imported = dir.path().join("imports").join("bank.beancount");
+        fs::write(&imported, "2024-02-01 open Assets:Bank\n").unwrap();
+        did_change_watched_files(&mut state, event(&imported, FileChangeType::CREATED)).unwrap();
+        wait_for_reload(&mut state);
+        assert!(state.forest.contains_key(&imported));
+
+        // Files outside the includes are ignored
+        let unrelated = dir.path().join("scratch.beancount");
+        fs::write(&unrelated, "").unwrap();
+        did_change_watched_files(&mut state, event(&unrelated, FileChangeType::CREATED)).unwrap();
+        assert!(!state.forest.contains_key(&unrelated));
+    }
+
+    #[test]
+    fn test_deleted_file_is_dropped() {
+        let (dir, main, included) = setup();
+        let (mut state, _receiver) = state_for(dir.path());
+        load(&mut state, main);
+
+        fs::remove_file(&included).unwrap();
+        did_change_watched_files(&mut state, event(&included, FileChangeType::DELETED)).unwrap();
+
+        assert!(!state.forest.contains_key(&included));
+        assert!(!state.beancount_data.contains_key(&included));
+    }
+
+    #[test]
+    fn test_file_no_longer_included_is_pruned() {
+        let (dir, main, included) = setup();
+        let (mut state, _receiver) = state_for(dir.path());
+        load(&mut state, main.clone());
+        assert!(state.forest.contains_key(&included));
+
+        fs::write(&main, "include \"imports/*.beancount\"\n").unwrap();
+        did_change_watched_files(&mut state, event(&main, FileChangeType::CHANGED)).unwrap();
+        wait_for_reload(&mut state);
+
+        assert!(state.forest.contains_key(&main));
+        assert!(!state.forest.contains_key(&included));
+        assert!(!state.beancount_data.contains_key(&included));
+    }
+
+    #[test]
+    fn test_watchers_include_globs_and_are_registered_once() {
+        let (dir, main, _) = setup();
+        let (mut state, receiver) = state_for(dir.path());
+        load(&mut state, main);
+
+        register_file_watchers(&mut state);
+        register_file_watchers(&mut state);
+
+        let registrations: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                lsp_server::Message::Request(request)
+                    if request.method == "client/registerCapability" =>
+                {
+                    Some(request)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(registrations.len(), 1);
+
+        let patterns = state.watched_patterns.unwrap();
+        assert!(patterns.contains(&"**/*.beancount".to_string()));
+        let imports_glob = dir.path().join("imports/*.beancount");
+        assert!(patterns.contains(&imports_glob.to_string_lossy().to_string()));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::beancount_data;
+use crate::providers::completion::score_account;
+use crate::providers::uri::file_path_to_uri;
+use crate::server::LspServerStateSnapshot;
+use crate::workspace_index::WorkspaceIndex;
+use anyhow::Result;
+use lsp_types::{Location, OneOf, SymbolKind, WorkspaceSymbol};
+use std::path::PathBuf;
+
+/// Upper bound on returned symbols so huge ledgers stay responsive.
+const MAX_WORKSPACE_SYMBOLS: usize = 256;
+
+/// Score returned by `score_account` when nothing matched.
+const NO_MATCH_SCORE: f32 = 1.0;
+
+/// A named declaration found somewhere in the forest.
+struct Candidate {
+    name: String,
+    kind: SymbolKind,
+    path: PathBuf,
+    range: lsp_types::Range,
+}
+
+/// Provider function for LSP `workspace/symbol`.
+///
+/// Fuzzy-matches accounts, commodities, payees, tags, links and events across
+/// all parsed files, ranked like account completion.
+pub(crate) fn workspace_symbol(
+    snapshot: LspServerStateSnapshot,
+    params: lsp_types::WorkspaceSymbolParams,
+) -> Result<Option<lsp_types::WorkspaceSymbolResponse>> {
+    let query = params.query.trim();
+
+    let mut scored: Vec<(f32, Candidate)> = candidates(&snapshot.index)
+        .into_iter()
+        .filter_map(|candidate| {
+            if query.is_empty() {
+                return Some((NO_MATCH_SCORE, candidate));
+            }
+            let score = score_account(&candidate.name, query);
+            (score > NO_MATCH_SCORE).then_some((score, candidate))
+        })
+        .collect();
+
+    scored.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.1.name.cmp(&b.1.name))
+    });
+
+    let symbols = scored
+        .into_iter()
+        .filter_map(|(_, candidate)| {
+            let uri = file_path_to_uri(&candidate.path)?;
+            Some(WorkspaceSymbol {
+                name: candidate.name,
+                kind: candidate.kind,
+                tags: None,
+                container_name: None,
+                location: OneOf::Left(Location::new(uri, candidate.range)),
+                data: None,
+            })
+        })
+        .take(MAX_WORKSPACE_SYMBOLS)
+        .collect();
+
+    Ok(Some(lsp_types::WorkspaceSymbolResponse::Nested(symbols)))
+}
+
+/// Collect one candidate per declared name, using its earliest declaration.
+fn candidates(index: &WorkspaceIndex) -> Vec<Candidate> {
+    let declarations = [
+        (beancount_data::SymbolKind::Account, SymbolKind::CLASS),
+        (beancount_data::SymbolKind::Currency, SymbolKind::CONSTANT),
+        (beancount_data::SymbolKind::Payee, SymbolKind::STRING),
+        (beancount_data::SymbolKind::Tag, SymbolKind::KEY),
+        (beancount_data::SymbolKind::Link, SymbolKind::KEY),
+    ]
+    .into_iter()
+    .flat_map(|(kind, symbol_kind)| {
+        index
+            .declarations(kind)
+            .into_iter()
+            .map(move |(name, path, range)| Candidate {
+                name: name.trim_matches('"').to_string(),
+                kind: symbol_kind,
+                path: path.to_path_buf(),
+                range,
+            })
+    });
+    let events = index.events().map(|(event, path, range)| Candidate {
+        name: event.to_string(),
+        kind: SymbolKind::EVENT,
+        path: path.to_path_buf(),
+        range,
+    });
+    declarations.chain(events).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn search(text: &str, query: &str) -> Vec<WorkspaceSymbol> {
+        let dir = std::env::current_dir().unwrap();
+        let snapshot = LspServerStateSnapshot::from_files(
+            Config::new(dir.clone()),
+            &[(dir.join("symbols.beancount"), text)],
+        );
+        let params = lsp_types::WorkspaceSymbolParams {
+            query: query.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        match workspace_symbol(snapshot, params).unwrap() {
+            Some(lsp_types::WorkspaceSymbolResponse::Nested(symbols)) => symbols,
+            other => panic!("Unexpected response: {other:?}"),
+        }
+    }
+
+    const LEDGER: &str = r#"2020-01-01 commodity USD
+2020-01-01 open Expenses:Food
+2020-01-01 open Expenses:Fuel
+2020-01-01 open Assets:Cash
+2024-01-02 * "Food Market" "Weekly shop" #groceries ^receipt-1
+  Expenses:Food  20 USD
+  Assets:Cash
+2024-01-03 event "location" "Lisbon"
+"#;
+
+    #[test]
+    fn test_account_query_ranks_best_match_first() {
+        let symbols = search(LEDGER, "Exp:Food");
+        assert_eq!(symbols[0].name, "Expenses:Food");
+        assert_eq!(symbols[0].kind, SymbolKind::CLASS);
+        match &symbols[0].location {
+            OneOf::Left(location) => {
+                assert_eq!(location.range.start, lsp_types::Position::new(1, 16))
+            }
+            OneOf::Right(_) => panic!("Expected a full location"),
+        }
+    }
+
+    #[test]
+    fn test_all_symbol_kinds_are_searchable() {
+        let names = |query| {
+            search(LEDGER, query)
+                .into_iter()
+                .map(|s| (s.name, s.kind))
+                .collect::<Vec<_>>()
+        };
+        assert!(names("USD").contains(&("USD".to_string(), SymbolKind::CONSTANT)));
+        assert!(names("Food Market").contains(&("Food Market".to_string(), SymbolKind::STRING)));
+        assert!(names("groceries").contains(&("#groceries".to_string(), SymbolKind::KEY)));
+        assert!(names("receipt").contains(&("^receipt-1".to_string(), SymbolKind::KEY)));
+        assert!(names("Lisbon").contains(&("location: Lisbon".to_string(), SymbolKind::EVENT)));
+    }
+
+    #[test]
+    fn test_non_matching_query_returns_nothing() {
+        assert!(search(LEDGER, "zzzz").is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 use crate::beancount_data::BeancountData;
+use crate::cancellation::CancellationToken;
+use crate::check_scheduler::CheckScheduler;
 use crate::config::Config;
 use crate::dispatcher::NotificationDispatcher;
 use crate::dispatcher::RequestDispatcher;
@@ -6,17 +8,27 @@ use crate::document::Document;
 use crate::forest;
 use crate::handlers;
 use crate::progress::Progress;
+use crate::providers::diagnostics::DiagnosticData;
+use crate::providers::semantic_tokens::SemanticTokensCache;
+use crate::providers::text_document;
+use crate::providers::uri::file_path_to_uri;
+use crate::providers::watched_files;
+use crate::workspace_index::WorkspaceIndex;
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
 use lsp_types::notification::Notification;
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tree_sitter_beancount::tree_sitter;
 
+/// Settings section requested through `workspace/configuration`.
+pub(crate) const CONFIGURATION_SECTION: &str = "beancount";
+
 pub(crate) type RequestHandler = fn(&mut LspServerState, lsp_server::Response);
-pub(crate) type ForestData = Box<Option<(PathBuf, Arc<tree_sitter::Tree>, Arc<BeancountData>)>>;
+pub(crate) type ParsedFile = (PathBuf, Arc<tree_sitter::Tree>, Arc<BeancountData>);
+pub(crate) type ForestData = Box<Option<ParsedFile>>;
 
 #[derive(Debug)]
 pub(crate) enum ProgressMsg {
@@ -34,14 +46,21 @@ pub(crate) enum ProgressMsg {
 #[derive(Debug)]
 pub(crate) enum Task {
     Response(lsp_server::Response),
-    Notify(lsp_server::Notification),
     Progress(ProgressMsg),
+    /// Diagnostics from a finished check, with an entry for every checked file.
+    Diagnostics(HashMap<PathBuf, Vec<lsp_types::Diagnostic>>),
+    /// A check started by the scheduler completed, with or without results.
+    CheckFinished,
+    /// Files re-parsed from disk after `workspace/didChangeWatchedFiles`.
+    FilesReloaded(Vec<ParsedFile>),
 }
 
 #[derive(Debug)]
 pub(crate) enum Event {
     Lsp(lsp_server::Message),
     Task(Task),
+    /// The pending bean-check is due.
+    CheckDue,
 }
 
 /*
@@ -54,18 +73,43 @@ struct LspServer {
 pub(crate) struct LspServerState {
     pub beancount_data: HashMap<PathBuf, Arc<BeancountData>>,
 
+    // Cancelled when a newer bean-check run supersedes the pending one
+    pub check_cancellation: CancellationToken,
+
+    // Debounces bean-check requests and runs one check at a time
+    pub check_scheduler: CheckScheduler,
+
+    // What the client told us it supports during initialization
+    pub client_capabilities: lsp_types::ClientCapabilities,
+
+    // Settings from the initialization options, which runtime settings update
+    pub base_config: Config,
+
     // the lsp server config options
     pub config: Config,
 
+    // Latest diagnostics of every file, served to pull clients
+    pub diagnostic_data: DiagnosticData,
+
     pub forest: HashMap<PathBuf, Arc<tree_sitter::Tree>>,
 
+    // Workspace diagnostic request held open until diagnostics change
+    pub held_workspace_diagnostic:
+        Option<(lsp_server::RequestId, lsp_types::WorkspaceDiagnosticParams)>,
+
+    // Symbols of every file in the forest, kept in sync with beancount_data
+    pub index: Arc<WorkspaceIndex>,
+
     // Documents that are currently kept in memory from the client
     pub open_docs: HashMap<PathBuf, Document>,
 
     pub parsers: HashMap<PathBuf, tree_sitter::Parser>,
 
     // The request queue keeps track of all incoming and outgoing requests.
-    pub req_queue: lsp_server::ReqQueue<(String, Instant), RequestHandler>,
+    pub req_queue: lsp_server::ReqQueue<(String, Instant, CancellationToken), RequestHandler>,
+
+    // Semantic tokens last sent for each open document, shared with snapshots
+    pub semantic_tokens: Arc<Mutex<SemanticTokensCache>>,
 
     // Channel to send language server messages to the client
     pub sender: Sender<lsp_server::Message>,
@@ -81,14 +125,22 @@ pub(crate) struct LspServerState {
 
     // Thread pool for async execution
     pub thread_pool: threadpool::ThreadPool,
+
+    // Glob patterns currently registered with the client for file watching
+    pub watched_patterns: Option<Vec<String>>,
 }
 
 /// A snapshot of the state of the language server
+#[derive(Clone)]
 pub(crate) struct LspServerStateSnapshot {
     pub beancount_data: HashMap<PathBuf, Arc<BeancountData>>,
+    /// Set when the request or job this snapshot was taken for is cancelled.
+    pub cancellation: CancellationToken,
     pub config: Config,
     pub forest: HashMap<PathBuf, Arc<tree_sitter::Tree>>,
+    pub index: Arc<WorkspaceIndex>,
     pub open_docs: HashMap<PathBuf, Document>,
+    pub semantic_tokens: Arc<Mutex<SemanticTokensCache>>,
 }
 
 /*
@@ -101,71 +153,44 @@ impl LspServer {
 }
 */
 impl LspServerState {
-    pub fn new(sender: Sender<lsp_server::Message>, config: Config) -> Self {
+    pub fn new(
+        sender: Sender<lsp_server::Message>,
+        config: Config,
+        client_capabilities: lsp_types::ClientCapabilities,
+    ) -> Self {
         let (task_sender, task_receiver) = crossbeam_channel::unbounded();
         //let (event_tx, event_rx) = crossbeam_channel::unbounded();
         Self {
             beancount_data: HashMap::new(),
+            check_cancellation: CancellationToken::default(),
+            check_scheduler: CheckScheduler::default(),
+            client_capabilities,
+            base_config: config.clone(),
             config,
+            diagnostic_data: DiagnosticData::new(),
             forest: HashMap::new(),
+            held_workspace_diagnostic: None,
+            index: Arc::new(WorkspaceIndex::default()),
             open_docs: HashMap::new(),
             parsers: HashMap::new(),
             req_queue: lsp_server::ReqQueue::default(),
+            semantic_tokens: Arc::default(),
             sender,
             shutdown_requested: false,
             task_sender,
             task_receiver,
             thread_pool: threadpool::ThreadPool::default(),
+            watched_patterns: None,
         }
     }
 
     pub fn run(&mut self, receiver: Receiver<lsp_server::Message>) -> Result<()> {
         tracing::info!("LSP server starting main event loop");
 
-        // init forest
-        if self.config.journal_root.is_some() {
-            let file = self.config.journal_root.as_ref().unwrap();
-
-            let journal_root = if file.is_relative() {
-                self.config.root_file.join(file)
-            } else {
-                file.clone()
-            };
-
-            // Check if exists
-            if !journal_root.exists() {
-                let error_msg = format!("Journal root does not exist: {}", journal_root.display());
-                tracing::error!("{}", error_msg);
-
-                // Send error message to client
-                self.send_notification::<lsp_types::notification::ShowMessage>(
-                    lsp_types::ShowMessageParams {
-                        typ: lsp_types::MessageType::ERROR,
-                        message: error_msg.clone(),
-                    },
-                );
-
-                // Log warning and continue without forest initialization instead of returning error
-                // This allows the language server to continue functioning for open documents
-                tracing::warn!(
-                    "Continuing without forest initialization due to invalid journal root"
-                );
-            } else {
-                tracing::info!(
-                    "Initializing forest for journal root: {}",
-                    journal_root.display()
-                );
-                let snapshot = self.snapshot();
-                let sender = self.task_sender.clone();
-                self.thread_pool.execute(move || {
-                    match forest::parse_initial_forest(snapshot, journal_root, sender) {
-                        Ok(_) => tracing::info!("Forest initialization completed successfully"),
-                        Err(e) => tracing::error!("Forest initialization failed: {}", e),
-                    }
-                });
-            }
-        } else {
-            tracing::warn!("No journal_root configured, skipping forest initialization");
+        self.init_forest();
+        watched_files::register_file_watchers(self);
+        if self.supports_configuration_pull() {
+            self.request_configuration();
         }
 
         tracing::debug!("Entering main event loop");
@@ -182,11 +207,200 @@ impl LspServerState {
         Ok(())
     }
 
+    /// Journal file configured by the client, resolved against the workspace root.
+    pub(crate) fn journal_root(&self) -> Option<PathBuf> {
+        let file = self.config.journal_root.as_ref()?;
+        Some(if file.is_relative() {
+            self.config.root_file.join(file)
+        } else {
+            file.clone()
+        })
+    }
+
+    /// Parse the journal root and all of its includes in the background.
+    pub(crate) fn init_forest(&mut self) {
+        let Some(journal_root) = self.journal_root() else {
+            tracing::warn!("No journal_root configured, skipping forest initialization");
+            return;
+        };
+
+        // Check if exists
+        if !journal_root.exists() {
+            let error_msg = format!("Journal root does not exist: {}", journal_root.display());
+            tracing::error!("{}", error_msg);
+
+            // Send error message to client
+            self.send_notification::<lsp_types::notification::ShowMessage>(
+                lsp_types::ShowMessageParams {
+                    typ: lsp_types::MessageType::ERROR,
+                    message: error_msg.clone(),
+                },
+            );
+
+            // Log warning and continue without forest initialization instead of returning error
+            // This allows the language server to continue functioning for open documents
+            tracing::warn!("Continuing without forest initialization due to invalid journal root");
+            return;
+        }
+
+        tracing::info!(
+            "Initializing forest for journal root: {}",
+            journal_root.display()
+        );
+        // Start from an empty forest so every included file is visited again
+        let mut snapshot = self.snapshot();
+        snapshot.forest.clear();
+        let sender = self.task_sender.clone();
+        self.thread_pool.execute(move || {
+            match forest::parse_initial_forest(snapshot, journal_root, sender) {
+                Ok(_) => tracing::info!("Forest initialization completed successfully"),
+                Err(e) => tracing::error!("Forest initialization failed: {}", e),
+            }
+        });
+    }
+
+    /// Whether the client answers `workspace/configuration` requests.
+    pub(crate) fn supports_configuration_pull(&self) -> bool {
+        self.client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false)
+    }
+
+    /// Ask the client for the `beancount` settings section.
+    pub(crate) fn request_configuration(&mut self) {
+        self.send_request::<lsp_types::request::WorkspaceConfiguration>(
+            lsp_types::ConfigurationParams {
+                items: vec![lsp_types::ConfigurationItem {
+                    scope_uri: None,
+                    section: Some(CONFIGURATION_SECTION.to_string()),
+                }],
+            },
+            |state, response| {
+                let Some(result) = response.result else {
+                    tracing::warn!("workspace/configuration failed: {:?}", response.error);
+                    return;
+                };
+                match serde_json::from_value::<Vec<serde_json::Value>>(result) {
+                    Ok(mut settings) if !settings.is_empty() => {
+                        state.apply_config(settings.swap_remove(0));
+                    }
+                    Ok(_) => tracing::debug!("Client returned no configuration"),
+                    Err(e) => tracing::warn!("Invalid workspace/configuration response: {}", e),
+                }
+            },
+        );
+    }
+
+    /// Replace the settings with `json` on top of the initialization options, rebuilding the
+    /// forest when the journal root changed and re-checking when the journal or
+    /// the checker settings changed.
+    pub(crate) fn apply_config(&mut self, json: serde_json::Value) {
+        if json.is_null() {
+            return;
+        }
+        tracing::debug!("Applying configuration: {}", json);
+
+        // Settings missing from `json` revert to the initialization options
+        let mut config = self.base_config.clone();
+        if let Err(e) = config.update(json) {
+            tracing::warn!("Failed to update configuration: {}", e);
+            return;
+        }
+        let old_root = self.journal_root();
+        let bean_check_changed = config.bean_check != self.config.bean_check;
+        self.config = config;
+        let root_changed = self.journal_root() != old_root;
+
+        if root_changed {
+            tracing::info!("Journal root changed, rebuilding forest");
+            let open_docs = &self.open_docs;
+            let dropped: Vec<PathBuf> = self
+                .forest
+                .keys()
+                .filter(|path| !open_docs.contains_key(*path))
+                .cloned()
+                .collect();
+            self.forest.retain(|path, _| open_docs.contains_key(path));
+            self.beancount_data
+                .retain(|path, _| open_docs.contains_key(path));
+            Arc::make_mut(&mut self.index).retain_files(|path| open_docs.contains_key(path));
+
+            // Files that left the journal keep no diagnostics
+            self.handle_diagnostics_task(dropped.into_iter().map(|path| (path, vec![])).collect());
+            self.init_forest();
+        }
+
+        if root_changed || bean_check_changed {
+            self.schedule_check();
+        }
+    }
+
+    /// Re-check the journal, or an open document when no journal is configured.
+    pub(crate) fn schedule_check(&mut self) {
+        let check_file = self
+            .journal_root()
+            .or_else(|| self.open_docs.keys().next().cloned());
+        if let Some(uri) = check_file.and_then(|file| file_path_to_uri(&file)) {
+            let delay = Duration::from_millis(self.config.bean_check.debounce_ms);
+            self.request_check(uri, delay);
+        }
+    }
+
+    /// Re-check when the cached diagnostics predate the current documents and
+    /// no check is pending yet.
+    pub(crate) fn check_if_stale(&mut self) {
+        if self.diagnostic_data.is_stale() && !self.check_scheduler.is_pending() {
+            self.schedule_check();
+        }
+    }
+
+    /// Check `uri` once no other check was requested for `delay`.
+    ///
+    /// A running check would report outdated results, so it is cancelled; the
+    /// new check starts once the cancelled one reports back.
+    pub(crate) fn request_check(&mut self, uri: lsp_types::Uri, delay: Duration) {
+        self.check_scheduler.request(uri, delay, Instant::now());
+        if self.check_scheduler.is_running() {
+            self.check_cancellation.cancel();
+        }
+        self.start_due_check();
+    }
+
+    /// Start the pending check if it is due and no other check is running.
+    fn start_due_check(&mut self) {
+        if let Some(uris) = self.check_scheduler.due(Instant::now()) {
+            self.spawn_check(uris);
+        }
+    }
+
+    /// Run bean-check for `uris` on the thread pool.
+    fn spawn_check(&mut self, uris: Vec<lsp_types::Uri>) {
+        self.check_cancellation = CancellationToken::default();
+
+        self.diagnostic_data.check_started();
+        let mut snapshot = self.snapshot();
+        snapshot.cancellation = self.check_cancellation.clone();
+        let sender = self.task_sender.clone();
+        self.thread_pool.execute(move || {
+            if let Err(e) = text_document::handle_diagnostics(snapshot, sender.clone(), uris) {
+                tracing::error!("Diagnostics failed: {}", e);
+            }
+            let _ = sender.send(Task::CheckFinished);
+        });
+    }
+
     // Blocks until new event is received
     pub fn next_event(&self, receiver: &Receiver<lsp_server::Message>) -> Option<Event> {
+        let check_timer = match self.check_scheduler.next_deadline() {
+            Some(deadline) => crossbeam_channel::at(deadline),
+            None => crossbeam_channel::never(),
+        };
         crossbeam_channel::select! {
             recv(receiver) -> msg => msg.ok().map(Event::Lsp),
             recv(self.task_receiver) -> task => Some(Event::Task(task.unwrap())),
+            recv(check_timer) -> _ => Some(Event::CheckDue),
         }
     }
 
@@ -213,6 +427,10 @@ impl LspServerState {
                     self.on_notification(notif)?;
                 }
             },
+            Event::CheckDue => {
+                tracing::debug!("Pending bean-check is due");
+                self.start_due_check();
+            }
         };
 
         let duration = start_time.elapsed();
@@ -224,12 +442,8 @@ impl LspServerState {
     }
 
     // Handles a task sent by another async task
-    fn handle_task(&mut self, task: Task) -> anyhow::Result<()> {
+    pub(crate) fn handle_task(&mut self, task: Task) -> anyhow::Result<()> {
         match task {
-            Task::Notify(notification) => {
-                tracing::debug!("Sending notification: {}", notification.method);
-                self.send(notification.into());
-            }
             Task::Response(response) => {
                 tracing::debug!("Sending response for request: {}", response.id);
                 self.respond(response);
@@ -238,10 +452,106 @@ impl LspServerState {
                 tracing::debug!("Handling progress task: {:?}", progress_task);
                 self.handle_progress_task(progress_task)?;
             }
+            Task::Diagnostics(diagnostics) => {
+                self.handle_diagnostics_task(diagnostics);
+            }
+            Task::CheckFinished => {
+                self.check_scheduler.finished();
+                self.start_due_check();
+            }
+            Task::FilesReloaded(files) => {
+                watched_files::files_reloaded(self, files);
+            }
         }
         Ok(())
     }
 
+    /// Whether the client pulls diagnostics (LSP 3.17) instead of receiving them.
+    pub(crate) fn pulls_diagnostics(&self) -> bool {
+        self.client_capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|text_document| text_document.diagnostic.is_some())
+    }
+
+    // Caches the result of a check and tells the client about changed files
+    pub(crate) fn handle_diagnostics_task(
+        &mut self,
+        diagnostics: HashMap<PathBuf, Vec<lsp_types::Diagnostic>>,
+    ) {
+        let changed = self.diagnostic_data.update(diagnostics);
+        tracing::debug!("Diagnostics changed for {} files", changed.len());
+        if changed.is_empty() {
+            return;
+        }
+
+        if let Some((id, params)) = self.held_workspace_diagnostic.take() {
+            self.answer_workspace_diagnostic(id, params);
+        }
+
+        if self.pulls_diagnostics() {
+            let refresh_support = self
+                .client_capabilities
+                .workspace
+                .as_ref()
+                .and_then(|workspace| workspace.diagnostic.as_ref())
+                .and_then(|diagnostic| diagnostic.refresh_support)
+                .unwrap_or(false);
+            if refresh_support {
+                self.send_request::<lsp_types::request::WorkspaceDiagnosticRefresh>((), |_, _| {});
+            }
+            return;
+        }
+
+        for file in changed {
+            let Some(uri) = file_path_to_uri(&file) else {
+                tracing::warn!("Cannot publish diagnostics for {}", file.display());
+                continue;
+            };
+            let diagnostics = self.diagnostic_data.get(&file).to_vec();
+            self.send_notification::<lsp_types::notification::PublishDiagnostics>(
+                lsp_types::PublishDiagnosticsParams {
+                    uri,
+                    diagnostics,
+                    version: None,
+                },
+            );
+        }
+    }
+
+    /// Answer a workspace diagnostic request, or hold it open while the client
+    /// already has the current report of every file.
+    pub(crate) fn answer_workspace_diagnostic(
+        &mut self,
+        id: lsp_server::RequestId,
+        params: lsp_types::WorkspaceDiagnosticParams,
+    ) {
+        let result = crate::providers::diagnostics::workspace_diagnostic(
+            &self.diagnostic_data,
+            self.forest.keys(),
+            &params,
+        );
+        let response = match result {
+            Ok(Some(report)) => lsp_server::Response::new_ok(id, report),
+            Ok(None) => {
+                // A newer request replaces the held one, which gets an empty report
+                if let Some((previous, _)) = self.held_workspace_diagnostic.replace((id, params)) {
+                    let empty = lsp_types::WorkspaceDiagnosticReportResult::Report(
+                        lsp_types::WorkspaceDiagnosticReport { items: vec![] },
+                    );
+                    self.respond(lsp_server::Response::new_ok(previous, empty));
+                }
+                return;
+            }
+            Err(e) => lsp_server::Response::new_err(
+                id,
+                lsp_server::ErrorCode::InternalError as i32,
+                e.to_string(),
+            ),
+        };
+        self.respond(response);
+    }
+
     fn handle_progress_task(&mut self, task: ProgressMsg) -> Result<()> {
         match task {
             ProgressMsg::BeanCheck { total, done } => {
@@ -260,9 +570,12 @@ impl LspServerState {
                 )
             }
             ProgressMsg::ForestInit { total, done, data } => {
-                if let Some(data) = *data {
+                // Open documents are kept in sync by the client, not from disk
+                if let Some(data) = *data
+                    && !self.open_docs.contains_key(&data.0)
+                {
                     self.forest.insert(data.0.clone(), data.1);
-                    self.beancount_data.insert(data.0, data.2);
+                    self.set_beancount_data(data.0, data.2);
                 }
                 let progress_state = if done == 0 {
                     Progress::Begin
@@ -276,7 +589,10 @@ impl LspServerState {
                     progress_state,
                     Some(format!("{done}/{total}")),
                     Some(Progress::fraction(done, total)),
-                )
+                );
+                if done == total {
+                    watched_files::register_file_watchers(self);
+                }
             }
         }
         Ok(())
@@ -285,15 +601,42 @@ impl LspServerState {
     // Registers a request with the server. We register all these request to make
     // sure they all get handled and so we can measure the time it takes for them
     // to complete from the point of view of the client.
-    fn register_request(&mut self, request: &lsp_server::Request, start_time: Instant) {
-        self.req_queue
-            .incoming
-            .register(request.id.clone(), (request.method.clone(), start_time))
+    fn register_request(
+        &mut self,
+        request: &lsp_server::Request,
+        start_time: Instant,
+    ) -> CancellationToken {
+        let cancellation = CancellationToken::default();
+        self.req_queue.incoming.register(
+            request.id.clone(),
+            (request.method.clone(), start_time, cancellation.clone()),
+        );
+        cancellation
+    }
+
+    /// Cancel an in-flight request on behalf of the client.
+    ///
+    /// The client gets a `RequestCancelled` error right away; the job stops at
+    /// its next cancellation check and its late response is dropped.
+    pub(crate) fn cancel_request(&mut self, id: lsp_server::RequestId) {
+        let Some((method, _, cancellation)) = self.req_queue.incoming.complete(&id) else {
+            return;
+        };
+        tracing::debug!("Cancelling request {} ({})", id, method);
+        cancellation.cancel();
+        self.send(
+            lsp_server::Response::new_err(
+                id,
+                lsp_server::ErrorCode::RequestCanceled as i32,
+                "canceled by client".to_string(),
+            )
+            .into(),
+        );
     }
 
     // Handles a language server protocol request
     fn on_request(&mut self, req: lsp_server::Request, start_time: Instant) -> Result<()> {
-        self.register_request(&req, start_time);
+        let cancellation = self.register_request(&req, start_time);
         if self.shutdown_requested {
             tracing::warn!("Request {} received after shutdown was requested", req.id);
             self.respond(lsp_server::Response::new_err(
@@ -306,22 +649,50 @@ impl LspServerState {
 
         tracing::debug!("Processing request: method={}, id={}", req.method, req.id);
 
-        RequestDispatcher::new(self, req)
+        RequestDispatcher::new(self, req, cancellation)
             .on_sync::<lsp_types::request::Shutdown>(|state, _request| {
                 tracing::info!("Received shutdown request");
                 state.shutdown_requested = true;
                 Ok(())
             })?
+            .on_sync::<lsp_types::request::DocumentDiagnosticRequest>(
+                handlers::text_document::diagnostic,
+            )?
+            .on_sync_deferred::<lsp_types::request::WorkspaceDiagnosticRequest>(
+                handlers::workspace::diagnostic,
+            )?
             .on::<lsp_types::request::Completion>(handlers::text_document::completion)?
             .on::<lsp_types::request::Formatting>(handlers::text_document::formatting)?
+            .on::<lsp_types::request::RangeFormatting>(handlers::text_document::range_formatting)?
+            .on::<lsp_types::request::OnTypeFormatting>(
+                handlers::text_document::on_type_formatting,
+            )?
             .on::<lsp_types::request::WillSaveWaitUntil>(
                 handlers::text_document::will_save_wait_until,
             )?
+            .on::<lsp_types::request::CodeActionRequest>(handlers::text_document::code_action)?
+            .on::<lsp_types::request::DocumentSymbolRequest>(
+                handlers::text_document::document_symbol,
+            )?
+            .on::<lsp_types::request::FoldingRangeRequest>(handlers::text_document::folding_range)?
+            .on::<lsp_types::request::GotoDefinition>(handlers::text_document::definition)?
+            .on::<lsp_types::request::HoverRequest>(handlers::text_document::hover)?
+            .on::<lsp_types::request::InlayHintRequest>(handlers::text_document::inlay_hint)?
+            .on::<lsp_types::request::PrepareRenameRequest>(
+                handlers::text_document::handle_prepare_rename,
+            )?
             .on::<lsp_types::request::Rename>(handlers::text_document::handle_rename)?
             .on::<lsp_types::request::References>(handlers::text_document::handle_references)?
             .on::<lsp_types::request::SemanticTokensFullRequest>(
                 handlers::text_document::semantic_tokens_full,
             )?
+            .on::<lsp_types::request::SemanticTokensFullDeltaRequest>(
+                handlers::text_document::semantic_tokens_full_delta,
+            )?
+            .on::<lsp_types::request::SemanticTokensRangeRequest>(
+                handlers::text_document::semantic_tokens_range,
+            )?
+            .on::<lsp_types::request::WorkspaceSymbolRequest>(handlers::workspace::symbol)?
             .finish();
         Ok(())
     }
@@ -347,13 +718,20 @@ impl LspServerState {
             .on::<lsp_types::notification::DidChangeTextDocument>(
                 handlers::text_document::did_change,
             )?
+            .on::<lsp_types::notification::DidChangeConfiguration>(
+                handlers::workspace::did_change_configuration,
+            )?
+            .on::<lsp_types::notification::DidChangeWatchedFiles>(
+                handlers::workspace::did_change_watched_files,
+            )?
+            .on::<lsp_types::notification::Cancel>(handlers::cancel_request)?
             .finish();
         Ok(())
     }
 
     // Sends a response to the client. This method logs the time it took us to reply to a request from the client.
     pub(crate) fn respond(&mut self, response: lsp_server::Response) {
-        if let Some((method, start)) = self.req_queue.incoming.complete(&response.id) {
+        if let Some((method, start, _)) = self.req_queue.incoming.complete(&response.id) {
             let duration = start.elapsed();
             let is_error = response.error.is_some();
 
@@ -380,7 +758,8 @@ impl LspServerState {
 
             self.send(response.into());
         } else {
-            tracing::warn!("Received response for unknown request: {}", response.id);
+            // Cancelled requests were already answered
+            tracing::debug!("Dropping response for completed request: {}", response.id);
         }
     }
 
@@ -433,12 +812,387 @@ impl LspServerState {
         self.send(not.into());
     }
 
+    /// Store the parsed data of a file and update the workspace index with it.
+    pub(crate) fn set_beancount_data(&mut self, path: PathBuf, data: Arc<BeancountData>) {
+        self.diagnostic_data.mark_stale();
+        Arc::make_mut(&mut self.index).update_file(path.clone(), &data);
+        self.beancount_data.insert(path, data);
+    }
+
+    /// Forget the parsed data of a file.
+    pub(crate) fn remove_beancount_data(&mut self, path: &Path) {
+        self.diagnostic_data.mark_stale();
+        Arc::make_mut(&mut self.index).remove_file(path);
+        self.beancount_data.remove(path);
+    }
+
     pub(crate) fn snapshot(&self) -> LspServerStateSnapshot {
         LspServerStateSnapshot {
             beancount_data: self.beancount_data.clone(),
+            cancellation: CancellationToken::default(),
             config: self.config.clone(),
             forest: self.forest.clone(),
+            index: self.index.clone(),
             open_docs: self.open_docs.clone(),
+            semantic_tokens: self.semantic_tokens.clone(),
         }
     }
 }
+
+impl LspServerStateSnapshot {
+    /// A snapshot of an empty workspace.
+    pub(crate) fn new(config: Config) -> Self {
+        Self {
+            beancount_data: HashMap::new(),
+            cancellation: CancellationToken::default(),
+            config,
+            forest: HashMap::new(),
+            index: Default::default(),
+            open_docs: HashMap::new(),
+            semantic_tokens: Default::default(),
+        }
+    }
+
+    /// Parse `text` and add it to the snapshot as an open document.
+    pub(crate) fn open_document(&mut self, path: PathBuf, text: &str) -> Result<()> {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&tree_sitter_beancount::language())?;
+        let tree = parser
+            .parse(text, None)
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse {}", path.display()))?;
+        let content = ropey::Rope::from_str(text);
+
+        let data = Arc::new(BeancountData::new(&tree, &content));
+        Arc::make_mut(&mut self.index).update_file(path.clone(), &data);
+        self.beancount_data.insert(path.clone(), data);
+        let tree = Arc::new(tree);
+        self.semantic_tokens
+            .lock()
+            .unwrap()
+            .open(path.clone(), tree.clone());
+        self.forest.insert(path.clone(), tree);
+        self.open_docs.insert(path, Document { content });
+        Ok(())
+    }
+
+    /// A snapshot with every `(path, text)` pair open as a document.
+    #[cfg(test)]
+    pub(crate) fn from_files<P: AsRef<Path>>(config: Config, files: &[(P, &str)]) -> Self {
+        let mut snapshot = Self::new(config);
+        for (path, text) in files {
+            snapshot
+                .open_document(path.as_ref().to_path_buf(), text)
+                .unwrap();
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with(
+        client_capabilities: lsp_types::ClientCapabilities,
+    ) -> (LspServerState, Receiver<lsp_server::Message>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let config = Config::new(std::env::temp_dir());
+        (
+            LspServerState::new(sender, config, client_capabilities),
+            receiver,
+        )
+    }
+
+    fn insert_file(state: &mut LspServerState, path: &str, open: bool) {
+        let text = "2024-01-01 open Assets:Cash\n";
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let content = ropey::Rope::from_str(text);
+        let path = PathBuf::from(path);
+        state.set_beancount_data(path.clone(), Arc::new(BeancountData::new(&tree, &content)));
+        state.forest.insert(path.clone(), Arc::new(tree));
+        if open {
+            state.open_docs.insert(path, Document { content });
+        }
+    }
+
+    #[test]
+    fn test_cancelled_request_is_answered_once() {
+        let (mut state, receiver) = state_with(Default::default());
+        let request = lsp_server::Request::new(
+            lsp_server::RequestId::from(7),
+            "textDocument/formatting".to_string(),
+            serde_json::Value::Null,
+        );
+        let cancellation = state.register_request(&request, Instant::now());
+
+        state.cancel_request(lsp_server::RequestId::from(7));
+        assert!(cancellation.is_cancelled());
+
+        // The response of the cancelled job arrives late and is dropped
+        state.respond(lsp_server::Response::new_ok(
+            lsp_server::RequestId::from(7),
+            serde_json::Value::Null,
+        ));
+
+        let responses: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                lsp_server::Message::Response(response) => Some(response),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].error.as_ref().map(|error| error.code),
+            Some(lsp_server::ErrorCode::RequestCanceled as i32)
+        );
+    }
+
+    #[test]
+    fn test_workspace_diagnostic_is_held_until_diagnostics_change() {
+        let (mut state, receiver) = state_with(Default::default());
+        insert_file(&mut state, "/ledger/main.beancount", true);
+        let params = lsp_types::WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: vec![lsp_types::PreviousResultId {
+                uri: file_path_to_uri(Path::new("/ledger/main.beancount")).unwrap(),
+                value: state
+                    .diagnostic_data
+                    .result_id(Path::new("/ledger/main.beancount"))
+                    .to_string(),
+            }],
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let request = lsp_server::Request::new(
+            lsp_server::RequestId::from(3),
+            "workspace/diagnostic".to_string(),
+            params,
+        );
+        state.on_request(request, Instant::now()).unwrap();
+
+        // Nothing changed for the client, and the edit schedules a check
+        assert!(state.check_scheduler.is_pending());
+        let responded = |receiver: &Receiver<lsp_server::Message>| {
+            receiver
+                .try_iter()
+                .any(|message| matches!(message, lsp_server::Message::Response(_)))
+        };
+        assert!(!responded(&receiver));
+
+        state.handle_diagnostics_task(HashMap::from([(
+            PathBuf::from("/ledger/main.beancount"),
+            vec![lsp_types::Diagnostic::default()],
+        )]));
+        assert!(responded(&receiver));
+        assert!(state.held_workspace_diagnostic.is_none());
+    }
+
+    #[test]
+    fn test_journal_change_rebuilds_forest_but_keeps_open_documents() {
+        let (mut state, receiver) = state_with(Default::default());
+        insert_file(&mut state, "/ledger/open.beancount", true);
+        insert_file(&mut state, "/ledger/included.beancount", false);
+
+        state.apply_config(serde_json::json!({
+            "journal_file": "/nonexistent/main.beancount",
+            "formatting": { "prefix_width": 40 }
+        }));
+
+        assert_eq!(state.config.formatting.prefix_width, Some(40));
+        assert_eq!(
+            state.journal_root(),
+            Some(PathBuf::from("/nonexistent/main.beancount"))
+        );
+        let files: Vec<_> = state.forest.keys().cloned().collect();
+        assert_eq!(files, vec![PathBuf::from("/ledger/open.beancount")]);
+        assert!(
+            !state
+                .beancount_data
+                .contains_key(&PathBuf::from("/ledger/included.beancount"))
+        );
+
+        // The missing journal is reported to the user
+        let reported = receiver.try_iter().any(|message| {
+            matches!(message, lsp_server::Message::Notification(n)
+                if n.method == lsp_types::notification::ShowMessage::METHOD)
+        });
+        assert!(reported);
+    }
+
+    #[test]
+    fn test_unchanged_journal_keeps_forest() {
+        let (mut state, _receiver) = state_with(Default::default());
+        insert_file(&mut state, "/ledger/included.beancount", false);
+
+        state.apply_config(serde_json::json!({ "bean_check": { "method": "native" } }));
+
+        assert!(matches!(
+            state.config.bean_check.method,
+            crate::checkers::BeancountCheckMethod::Native
+        ));
+        assert_eq!(state.forest.len(), 1);
+    }
+
+    #[test]
+    fn test_removed_settings_revert_to_defaults() {
+        let (mut state, _receiver) = state_with(Default::default());
+
+        state.apply_config(serde_json::json!({ "formatting": { "prefix_width": 40 } }));
+        assert_eq!(state.config.formatting.prefix_width, Some(40));
+
+        state.apply_config(serde_json::json!({}));
+        assert_eq!(state.config.formatting.prefix_width, None);
+    }
+
+    #[test]
+    fn test_initialization_options_survive_a_configuration_response() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut config = Config::new(std::env::temp_dir());
+        config
+            .update(serde_json::json!({
+                "journal_file": "/ledger/main.beancount",
+                "formatting": { "prefix_width": 40 },
+                "bean_check": { "method": "native" }
+            }))
+            .unwrap();
+        let mut state = LspServerState::new(sender, config, Default::default());
+
+        state.request_configuration();
+        let request = receiver
+            .try_iter()
+            .find_map(|message| match message {
+                lsp_server::Message::Request(request) => Some(request),
+                _ => None,
+            })
+            .expect("a workspace/configuration request");
+        state.complete_request(lsp_server::Response::new_ok(
+            request.id,
+            serde_json::json!([{ "formatting": { "indent_width": 4 } }]),
+        ));
+
+        assert_eq!(
+            state.journal_root(),
+            Some(PathBuf::from("/ledger/main.beancount"))
+        );
+        assert_eq!(state.config.formatting.prefix_width, Some(40));
+        assert_eq!(state.config.formatting.indent_width, Some(4));
+        assert_eq!(
+            state.config.bean_check.method,
+            crate::checkers::BeancountCheckMethod::Native
+        );
+    }
+
+    #[test]
+    fn test_newer_check_cancels_the_running_one() {
+        let (mut state, _receiver) = state_with(Default::default());
+        insert_file(&mut state, "/ledger/main.beancount", true);
+        let uri = file_path_to_uri(Path::new("/ledger/main.beancount")).unwrap();
+
+        state.request_check(uri.clone(), Duration::ZERO);
+        let running = state.check_cancellation.clone();
+        assert!(!running.is_cancelled());
+
+        state.request_check(uri, Duration::ZERO);
+        assert!(running.is_cancelled());
+        assert!(state.check_scheduler.is_pending());
+
+        // The pending check starts once the cancelled one reports back
+        state.handle_task(Task::CheckFinished).unwrap();
+        assert!(!state.check_scheduler.is_pending());
+        assert!(!state.check_cancellation.is_cancelled());
+    }
+
+    #[test]
+    fn test_only_journal_and_checker_settings_trigger_a_check() {
+        let (mut state, _receiver) = state_with(Default::default());
+        insert_file(&mut state, "/ledger/open.beancount", true);
+
+        state.apply_config(serde_json::json!({ "formatting": { "indent_width": 4 } }));
+        assert!(!state.check_scheduler.is_pending());
+
+        state.apply_config(serde_json::json!({ "bean_check": { "on_change": true } }));
+        assert!(state.check_scheduler.is_pending());
+    }
+
+    #[test]
+    fn test_files_dropped_from_the_forest_lose_their_diagnostics() {
+        let (mut state, receiver) = state_with(Default::default());
+        insert_file(&mut state, "/ledger/included.beancount", false);
+        state.handle_diagnostics_task(HashMap::from([(
+            PathBuf::from("/ledger/included.beancount"),
+            vec![lsp_types::Diagnostic::default()],
+        )]));
+        receiver.try_iter().for_each(drop);
+
+        state.apply_config(serde_json::json!({ "journal_file": "/nonexistent/main.beancount" }));
+
+        let cleared = receiver.try_iter().any(|message| {
+            matches!(message, lsp_server::Message::Notification(n)
+                if n.method == lsp_types::notification::PublishDiagnostics::METHOD
+                    && n.params["uri"] == "file:///ledger/included.beancount"
+                    && n.params["diagnostics"] == serde_json::json!([]))
+        });
+        assert!(cleared);
+        assert!(
+            state
+                .diagnostic_data
+                .get(Path::new("/ledger/included.beancount"))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_did_change_configuration_applies_nested_settings() {
+        let (mut state, _receiver) = state_with(Default::default());
+
+        handlers::workspace::did_change_configuration(
+            &mut state,
+            lsp_types::DidChangeConfigurationParams {
+                settings: serde_json::json!({ "beancount": { "formatting": { "indent_width": 4 } } }),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(state.config.formatting.indent_width, Some(4));
+    }
+
+    #[test]
+    fn test_did_change_configuration_pulls_when_supported() {
+        let (mut state, receiver) = state_with(lsp_types::ClientCapabilities {
+            workspace: Some(lsp_types::WorkspaceClientCapabilities {
+                configuration: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        handlers::workspace::did_change_configuration(
+            &mut state,
+            lsp_types::DidChangeConfigurationParams {
+                settings: serde_json::Value::Null,
+            },
+        )
+        .unwrap();
+
+        let request = receiver
+            .try_iter()
+            .find_map(|message| match message {
+                lsp_server::Message::Request(request) => Some(request),
+                _ => None,
+            })
+            .expect("a workspace/configuration request");
+        assert_eq!(request.method, "workspace/configuration");
+
+        // Answering the request applies the returned section
+        state.complete_request(lsp_server::Response::new_ok(
+            request.id,
+            serde_json::json!([{ "formatting": { "currency_column": 60 } }]),
+        ));
+        assert_eq!(state.config.formatting.currency_column, Some(60));
+    }
+}
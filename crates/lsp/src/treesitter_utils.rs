@@ -1,5 +1,13 @@
 use tree_sitter_beancount::tree_sitter;
 
+/// Range of a node as an LSP range, in UTF-16 code units.
+pub fn lsp_range_for_node(source: &ropey::Rope, node: &tree_sitter::Node) -> lsp_types::Range {
+    lsp_types::Range {
+        start: byte_to_lsp_position(source, node.start_byte()),
+        end: byte_to_lsp_position(source, node.end_byte()),
+    }
+}
+
 pub fn lsp_textdocchange_to_ts_inputedit(
     source: &ropey::Rope,
     change: &lsp_types::TextDocumentContentChangeEvent,
@@ -43,7 +51,8 @@ pub fn lsp_textdocchange_to_ts_inputedit(
     })
 }
 
-fn byte_to_lsp_position(text: &ropey::Rope, byte_idx: usize) -> lsp_types::Position {
+/// LSP position, in UTF-16 code units, of a byte offset into the rope.
+pub fn byte_to_lsp_position(text: &ropey::Rope, byte_idx: usize) -> lsp_types::Position {
     let line_idx = text.byte_to_line(byte_idx);
 
     let line_utf16_cu_idx = {
@@ -62,6 +71,21 @@ fn byte_to_lsp_position(text: &ropey::Rope, byte_idx: usize) -> lsp_types::Posit
     lsp_types::Position::new(line as u32, character as u32)
 }
 
+/// Tree-sitter point, with a byte column, of an LSP position in UTF-16 code units.
+pub fn lsp_position_to_point(
+    text: &ropey::Rope,
+    position: lsp_types::Position,
+) -> tree_sitter::Point {
+    let line_idx = position.line as usize;
+    if line_idx >= text.len_lines() {
+        return tree_sitter::Point::new(line_idx, 0);
+    }
+    // Positions past the end of the line are clamped to it
+    let line = text.line(line_idx);
+    let char_idx = line.utf16_cu_to_char((position.character as usize).min(line.len_utf16_cu()));
+    tree_sitter::Point::new(line_idx, line.char_to_byte(char_idx))
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct TextPosition {
     pub char: u32,
@@ -0,0 +1,593 @@
+use crate::beancount_data::{BeancountData, Symbol, SymbolKind, TransactionData};
+use crate::cancellation::{CancellationToken, Cancelled};
+use chrono::NaiveDate;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How often a name is used across the workspace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SymbolStats {
+    /// Number of occurrences in all files.
+    pub count: usize,
+    /// Number of `open` or `commodity` directives declaring the name.
+    pub declarations: usize,
+}
+
+/// How many transactions were counted, and how many of them used each account.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountCounts {
+    pub transactions: usize,
+    pub accounts: HashMap<String, usize>,
+}
+
+impl AccountCounts {
+    /// Count a transaction using `accounts`.
+    fn add<'a>(&mut self, accounts: impl Iterator<Item = &'a str>) {
+        self.transactions += 1;
+        for account in accounts {
+            *self.accounts.entry(account.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Leave out a counted transaction that used `accounts`.
+    pub fn remove<'a>(&mut self, accounts: impl Iterator<Item = &'a str>) {
+        self.transactions = self.transactions.saturating_sub(1);
+        for account in accounts {
+            if let Some(count) = self.accounts.get_mut(account) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Share of the counted transactions that used `account`.
+    pub fn share(&self, account: &str) -> f32 {
+        match (self.accounts.get(account), self.transactions) {
+            (Some(count), transactions) if transactions > 0 => *count as f32 / transactions as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn merge(&mut self, other: &AccountCounts) {
+        self.transactions += other.transactions;
+        for (account, count) in &other.accounts {
+            *self.accounts.entry(account.clone()).or_default() += count;
+        }
+    }
+}
+
+/// What the index knows about one file.
+#[derive(Debug, Default)]
+struct FileStats {
+    /// Usage statistics of the names found in the file.
+    names: HashMap<SymbolKind, HashMap<String, SymbolStats>>,
+    /// Earliest close date of each account closed in the file.
+    closes: HashMap<String, NaiveDate>,
+    /// Accounts used by the transactions of each payee.
+    payee_accounts: HashMap<String, AccountCounts>,
+    /// Other accounts used by the transactions using each account.
+    co_postings: HashMap<String, AccountCounts>,
+    /// Date each account was last used by a transaction.
+    last_used: HashMap<String, NaiveDate>,
+    /// Earliest declaration of each account (`open`), currency (`commodity`),
+    /// payee, tag and link of the file.
+    declarations: HashMap<SymbolKind, HashMap<String, Declaration>>,
+    /// Events of the file as "type: description", with their ranges.
+    events: Vec<(String, lsp_types::Range)>,
+    /// Latest transaction with postings of each payee.
+    latest_transactions: HashMap<String, TransactionData>,
+}
+
+/// Date and range of the directive or transaction declaring a name.
+type Declaration = (Option<NaiveDate>, lsp_types::Range);
+
+/// Accounts, currencies, payees, narrations, tags and links of every file in
+/// the forest, with their cross-file frequencies, the close dates of
+/// accounts and how transactions use accounts together.
+///
+/// Each file keeps its own statistics, so a change only recounts the edited
+/// file and cloning the index shares the others; totals are summed when
+/// queried.
+#[derive(Clone, Debug, Default)]
+pub struct WorkspaceIndex {
+    files: HashMap<PathBuf, Arc<FileStats>>,
+}
+
+impl WorkspaceIndex {
+    /// Build an index over the data of several files.
+    #[cfg(test)]
+    pub fn new(files: &HashMap<PathBuf, Arc<BeancountData>>) -> Self {
+        let mut index = Self::default();
+        for (path, data) in files {
+            index.update_file(path.clone(), data);
+        }
+        index
+    }
+
+    /// Replace the statistics of `path` with those of `data`.
+    pub fn update_file(&mut self, path: PathBuf, data: &BeancountData) {
+        let mut file_stats = FileStats::default();
+        for symbol in data.symbols() {
+            let stats = file_stats
+                .names
+                .entry(symbol.kind)
+                .or_default()
+                .entry(symbol.name.clone())
+                .or_default();
+            stats.count += 1;
+            stats.declarations += symbol.declaration as usize;
+        }
+        for close in data.closes() {
+            if let Some(date) = close.date {
+                add_close(&mut file_stats.closes, &close.account, date);
+            }
+        }
+        let declarations = &mut file_stats.declarations;
+        for open in data.opens() {
+            declare(
+                declarations,
+                SymbolKind::Account,
+                &open.account,
+                open.date,
+                open.range,
+            );
+        }
+        for commodity in data.commodity_declarations() {
+            declare(
+                declarations,
+                SymbolKind::Currency,
+                &commodity.currency,
+                commodity.date,
+                commodity.range,
+            );
+        }
+        for (tag, range) in data.tag_locations() {
+            declare(declarations, SymbolKind::Tag, tag, None, range);
+        }
+        for (link, range) in data.link_locations() {
+            declare(declarations, SymbolKind::Link, link, None, range);
+        }
+        file_stats.events = data
+            .events()
+            .map(|event| {
+                (
+                    format!("{}: {}", event.event_type, event.description),
+                    event.range,
+                )
+            })
+            .collect();
+        for txn in data.transactions() {
+            if let Some((payee, range)) = &txn.payee {
+                declare(
+                    &mut file_stats.declarations,
+                    SymbolKind::Payee,
+                    payee,
+                    txn.date,
+                    *range,
+                );
+                let is_latest = file_stats
+                    .latest_transactions
+                    .get(payee)
+                    .is_none_or(|latest| txn.date > latest.date);
+                if is_latest && !txn.postings.is_empty() {
+                    file_stats
+                        .latest_transactions
+                        .insert(payee.clone(), txn.clone());
+                }
+            }
+            let accounts: HashSet<&str> = txn
+                .postings
+                .iter()
+                .map(|posting| posting.account.as_str())
+                .collect();
+            if let Some(date) = txn.date {
+                for account in &accounts {
+                    file_stats
+                        .last_used
+                        .entry(account.to_string())
+                        .and_modify(|used| *used = (*used).max(date))
+                        .or_insert(date);
+                }
+            }
+            if let Some((payee, _)) = &txn.payee {
+                file_stats
+                    .payee_accounts
+                    .entry(payee.clone())
+                    .or_default()
+                    .add(accounts.iter().copied());
+            }
+            for account in &accounts {
+                file_stats
+                    .co_postings
+                    .entry(account.to_string())
+                    .or_default()
+                    .add(accounts.iter().copied().filter(|other| other != account));
+            }
+        }
+        self.files.insert(path, Arc::new(file_stats));
+    }
+
+    /// Drop the statistics of `path`.
+    pub fn remove_file(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Keep only the files for which `keep` returns true.
+    pub fn retain_files(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        self.files.retain(|path, _| keep(path));
+    }
+
+    /// Usage statistics of a name, if it occurs anywhere.
+    #[cfg(test)]
+    pub fn stats(&self, kind: SymbolKind, name: &str) -> Option<SymbolStats> {
+        self.files
+            .values()
+            .filter_map(|file| file.names.get(&kind)?.get(name))
+            .fold(None, |total: Option<SymbolStats>, stats| {
+                let total = total.unwrap_or_default();
+                Some(SymbolStats {
+                    count: total.count + stats.count,
+                    declarations: total.declarations + stats.declarations,
+                })
+            })
+    }
+
+    /// All names of a kind, most used first and then alphabetically.
+    pub fn names(&self, kind: SymbolKind) -> Vec<(&str, SymbolStats)> {
+        let mut totals: HashMap<&str, SymbolStats> = HashMap::new();
+        for names in self.files.values().filter_map(|file| file.names.get(&kind)) {
+            for (name, stats) in names {
+                let total = totals.entry(name.as_str()).or_default();
+                total.count += stats.count;
+                total.declarations += stats.declarations;
+            }
+        }
+        let mut names: Vec<(&str, SymbolStats)> = totals.into_iter().collect();
+        names.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(b.0)));
+        names
+    }
+
+    /// Accounts opened somewhere in the workspace, most used first.
+    pub fn declared_accounts(&self) -> Vec<&str> {
+        self.names(SymbolKind::Account)
+            .into_iter()
+            .filter(|(_, stats)| stats.declarations > 0)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Earliest close date of every closed account in the workspace.
+    pub fn close_dates(&self) -> HashMap<String, NaiveDate> {
+        let mut closes = HashMap::new();
+        for file in self.files.values() {
+            for (account, date) in &file.closes {
+                add_close(&mut closes, account, *date);
+            }
+        }
+        closes
+    }
+
+    /// Transactions with `payee`, a string including its quotes, and the
+    /// accounts they used.
+    pub fn payee_accounts(&self, payee: &str) -> AccountCounts {
+        let mut counts = AccountCounts::default();
+        for file in self.files.values() {
+            if let Some(file_counts) = file.payee_accounts.get(payee) {
+                counts.merge(file_counts);
+            }
+        }
+        counts
+    }
+
+    /// Transactions using `account`, and the other accounts they used.
+    pub fn co_postings(&self, account: &str) -> AccountCounts {
+        let mut counts = AccountCounts::default();
+        for file in self.files.values() {
+            if let Some(file_counts) = file.co_postings.get(account) {
+                counts.merge(file_counts);
+            }
+        }
+        counts
+    }
+
+    /// Date each account was last used by a transaction.
+    pub fn last_used(&self) -> HashMap<&str, NaiveDate> {
+        let mut last_used: HashMap<&str, NaiveDate> = HashMap::new();
+        for file in self.files.values() {
+            for (account, date) in &file.last_used {
+                last_used
+                    .entry(account.as_str())
+                    .and_modify(|used| *used = (*used).max(*date))
+                    .or_insert(*date);
+            }
+        }
+        last_used
+    }
+
+    /// Earliest declaration of every name of `kind`, with its file and range, sorted by name.
+    ///
+    /// Of equally early declarations, the one in the first file by path is kept.
+    pub fn declarations(&self, kind: SymbolKind) -> Vec<(&str, &Path, lsp_types::Range)> {
+        let mut files: Vec<_> = self.files.iter().collect();
+        files.sort_by_key(|(path, _)| *path);
+
+        let mut earliest: HashMap<&str, (Option<NaiveDate>, &Path, lsp_types::Range)> =
+            HashMap::new();
+        for (path, file) in files {
+            for (name, (date, range)) in file.declarations.get(&kind).into_iter().flatten() {
+                match earliest.get(name.as_str()) {
+                    Some((first, _, _)) if first <= date => {}
+                    _ => {
+                        earliest.insert(name, (*date, path, *range));
+                    }
+                }
+            }
+        }
+        let mut declarations: Vec<_> = earliest
+            .into_iter()
+            .map(|(name, (_, path, range))| (name, path, range))
+            .collect();
+        declarations.sort_by_key(|(name, _, _)| *name);
+        declarations
+    }
+
+    /// Every event as "type: description", with its file and range.
+    pub fn events(&self) -> impl Iterator<Item = (&str, &Path, lsp_types::Range)> {
+        self.files.iter().flat_map(|(path, file)| {
+            file.events
+                .iter()
+                .map(move |(event, range)| (event.as_str(), path.as_path(), *range))
+        })
+    }
+
+    /// Latest transaction with postings of each payee, a string including its quotes.
+    pub fn latest_transactions(&self) -> HashMap<&str, &TransactionData> {
+        let mut latest: HashMap<&str, &TransactionData> = HashMap::new();
+        for (payee, txn) in self
+            .files
+            .values()
+            .flat_map(|file| &file.latest_transactions)
+        {
+            let current = latest.entry(payee.as_str()).or_insert(txn);
+            if txn.date > current.date {
+                *current = txn;
+            }
+        }
+        latest
+    }
+
+    /// The files of `data` whose statistics contain a name.
+    pub(crate) fn files_using<'a, 'b>(
+        &'b self,
+        data: &'a HashMap<PathBuf, Arc<BeancountData>>,
+        kind: SymbolKind,
+        name: &'b str,
+    ) -> impl Iterator<Item = (&'a Path, &'a BeancountData)> + 'b
+    where
+        'a: 'b,
+    {
+        data.iter()
+            .filter(move |(path, _)| {
+                self.files
+                    .get(*path)
+                    .and_then(|file| file.names.get(&kind))
+                    .is_some_and(|names| names.contains_key(name))
+            })
+            .map(|(path, data)| (path.as_path(), data.as_ref()))
+    }
+
+    /// Every occurrence of a name in `data`, sorted by file and position.
+    ///
+    /// Only the files whose statistics contain the name are searched, and the
+    /// search stops between files once `cancellation` is set.
+    pub(crate) fn occurrences<'a>(
+        &self,
+        data: &'a HashMap<PathBuf, Arc<BeancountData>>,
+        kind: SymbolKind,
+        name: &str,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<(&'a Path, &'a Symbol)>, Cancelled> {
+        let mut occurrences: Vec<(&Path, &Symbol)> = vec![];
+        for (path, data) in self.files_using(data, kind, name) {
+            cancellation.check()?;
+            occurrences.extend(
+                data.symbols()
+                    .filter(|symbol| symbol.kind == kind && symbol.name == name)
+                    .map(|symbol| (path, symbol)),
+            );
+        }
+        occurrences.sort_by_key(|(path, symbol)| (*path, symbol.range.start));
+        Ok(occurrences)
+    }
+}
+
+/// Record a declaration of `name`, keeping the earliest dated one.
+fn declare(
+    declarations: &mut HashMap<SymbolKind, HashMap<String, Declaration>>,
+    kind: SymbolKind,
+    name: &str,
+    date: Option<NaiveDate>,
+    range: lsp_types::Range,
+) {
+    let names = declarations.entry(kind).or_default();
+    match names.get(name) {
+        Some((earliest, _)) if *earliest <= date => {}
+        _ => {
+            names.insert(name.to_string(), (date, range));
+        }
+    }
+}
+
+/// Record that `account` closes on `date`, keeping the earliest date.
+fn add_close(closes: &mut HashMap<String, NaiveDate>, account: &str, date: NaiveDate) {
+    closes
+        .entry(account.to_string())
+        .and_modify(|closed| *closed = (*closed).min(date))
+        .or_insert(date);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter_beancount::tree_sitter;
+
+    fn data(text: &str) -> Arc<BeancountData> {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_beancount::language())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        Arc::new(BeancountData::new(&tree, &ropey::Rope::from_str(text)))
+    }
+
+    const ACCOUNTS: &str = r#"2024-01-01 open Assets:Cash USD
+2024-01-01 open Expenses:Food
+"#;
+
+    const TRANSACTIONS: &str = r#"2024-01-02 * "Grocer" "Weekly shopping" #food
+  Expenses:Food  10 USD
+  Assets:Cash
+2024-01-03 * "Grocer" "Bread" #food ^receipt
+  Expenses:Food  2 USD
+  Assets:Cash
+"#;
+
+    #[test]
+    fn test_frequencies_span_files() {
+        let files = HashMap::from([
+            (PathBuf::from("/accounts.beancount"), data(ACCOUNTS)),
+            (PathBuf::from("/2024.beancount"), data(TRANSACTIONS)),
+        ]);
+        let index = WorkspaceIndex::new(&files);
+
+        assert_eq!(
+            index.stats(SymbolKind::Account, "Expenses:Food"),
+            Some(SymbolStats {
+                count: 3,
+                declarations: 1
+            })
+        );
+        assert_eq!(
+            index.declared_accounts(),
+            vec!["Assets:Cash", "Expenses:Food"]
+        );
+        assert_eq!(
+            index.names(SymbolKind::Currency),
+            vec![(
+                "USD",
+                SymbolStats {
+                    count: 3,
+                    declarations: 0
+                }
+            )]
+        );
+        assert_eq!(index.names(SymbolKind::Payee)[0].0, "\"Grocer\"");
+        assert_eq!(index.names(SymbolKind::Payee)[0].1.count, 2);
+        assert_eq!(
+            index
+                .occurrences(
+                    &files,
+                    SymbolKind::Tag,
+                    "#food",
+                    &CancellationToken::default()
+                )
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            index
+                .occurrences(
+                    &files,
+                    SymbolKind::Link,
+                    "^receipt",
+                    &CancellationToken::default()
+                )
+                .unwrap()[0]
+                .1
+                .range
+                .start,
+            lsp_types::Position::new(3, 36)
+        );
+    }
+
+    #[test]
+    fn test_updating_a_file_replaces_its_symbols() {
+        let mut index = WorkspaceIndex::default();
+        let path = PathBuf::from("/main.beancount");
+        index.update_file(path.clone(), &data(ACCOUNTS));
+        index.update_file(path.clone(), &data("2024-01-01 open Assets:Bank\n"));
+
+        assert_eq!(index.declared_accounts(), vec!["Assets:Bank"]);
+        assert_eq!(index.stats(SymbolKind::Currency, "USD"), None);
+
+        index.remove_file(&path);
+        assert!(index.names(SymbolKind::Account).is_empty());
+    }
+
+    #[test]
+    fn test_account_usage_spans_files() {
+        let files = HashMap::from([
+            (PathBuf::from("/2024.beancount"), data(TRANSACTIONS)),
+            (
+                PathBuf::from("/2025.beancount"),
+                data("2025-01-02 * \"Grocer\" \"Wine\"\n  Expenses:Drinks  8 USD\n  Assets:Cash\n"),
+            ),
+        ]);
+        let index = WorkspaceIndex::new(&files);
+
+        let payee = index.payee_accounts("\"Grocer\"");
+        assert_eq!(payee.transactions, 3);
+        assert_eq!(payee.share("Assets:Cash"), 1.0);
+        assert_eq!(payee.share("Expenses:Drinks"), 1.0 / 3.0);
+
+        let mut cash = index.co_postings("Assets:Cash");
+        assert_eq!(cash.share("Expenses:Food"), 2.0 / 3.0);
+        assert_eq!(cash.share("Assets:Cash"), 0.0);
+        cash.remove(["Expenses:Drinks"].into_iter());
+        assert_eq!(cash.share("Expenses:Food"), 1.0);
+
+        let last_used = index.last_used();
+        assert_eq!(
+            last_used["Assets:Cash"],
+            NaiveDate::from_ymd_opt(2025, 1, 2).unwrap()
+        );
+        assert_eq!(
+            last_used["Expenses:Food"],
+            NaiveDate::from_ymd_opt(2024, 1, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_declarations_and_lookups_span_files() {
+        let files = HashMap::from([
+            (PathBuf::from("/accounts.beancount"), data(ACCOUNTS)),
+            (PathBuf::from("/2024.beancount"), data(TRANSACTIONS)),
+        ]);
+        let index = WorkspaceIndex::new(&files);
+
+        let accounts = index.declarations(SymbolKind::Account);
+        assert_eq!(
+            accounts
+                .iter()
+                .map(|(name, path, _)| (*name, *path))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Assets:Cash", Path::new("/accounts.beancount")),
+                ("Expenses:Food", Path::new("/accounts.beancount")),
+            ]
+        );
+        let payees = index.declarations(SymbolKind::Payee);
+        assert_eq!(payees.len(), 1);
+        assert_eq!(payees[0].2.start, lsp_types::Position::new(0, 13));
+
+        let latest = index.latest_transactions();
+        assert_eq!(latest["\"Grocer\""].narration.as_deref(), Some("\"Bread\""));
+
+        let users: Vec<_> = index
+            .files_using(&files, SymbolKind::Payee, "\"Grocer\"")
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(users, vec![Path::new("/2024.beancount")]);
+    }
+}